@@ -3,6 +3,7 @@ import './App.css';
 import { Challenge, Game, Action } from './types';
 import GameBoard from './Game';
 import Lobby from './Lobby';
+import { applyUpdate } from './update';
 
 enum Team {
   White = "White",
@@ -15,9 +16,16 @@ const App = () => {
   const [users, setUsers] = useState<any>([]);
   const [challenges, setChallenges] = useState<Challenge[]>([]);
   const [game, setGame] = useState<Game|null>(null);
+  // The latest game, which updates apply to before it is rendered
+  const latestGame = useRef<Game|null>(null);
 
   const ws = useRef<WebSocket>();
 
+  const showGame = (game: Game|null) => {
+    latestGame.current = game;
+    setGame(game);
+  }
+
   // Accepts an existing challenge
   const acceptChallenge = (challenge: Challenge) => {
     ws.current?.send(JSON.stringify({
@@ -67,14 +75,26 @@ const App = () => {
       ws.current.onmessage = (event: any) => {
         const json = JSON.parse(event.data);
         if (json.type === 'lobbyUserBroadcast') {
-          setUsers(json.data[1]);
+          setUsers(json.data);
         }
         if (json.type === 'challengeBroadcast') {
           updateChallenges(json.data);
         }
         if (json.type === 'gameBroadcast') {
           console.log('HIIIIIII');
-          setGame(json.data);
+          showGame(json.data);
+        }
+        if (json.type === 'gameUpdate') {
+          const updated = latestGame.current && applyUpdate(latestGame.current, json.data);
+          if (updated) {
+            showGame(updated);
+          } else {
+            // Our copy of the game is out of step, so ask for all of it again
+            ws.current?.send(JSON.stringify({ type: 'resync' }));
+          }
+        }
+        if (json.type === 'error') {
+          console.error(`${json.data.code}: ${json.data.message}`, json.data.context);
         }
         console.log(event);
       };
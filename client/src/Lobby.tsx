@@ -8,7 +8,7 @@ const Users = ({ users, sendChallenge }: any) => {
       <div className='user-body'>
         {users.map((user: any) => (
           <div className='user-item' key={user.id} onClick={_ => sendChallenge(user)}>
-            {user.id + ' - ' + user.name}
+            {user.id + ' - ' + user.name + (user.rating ? ' (' + Math.round(user.rating.rating) + ')' : '')}
           </div>
         ))}
       </div>
@@ -23,7 +23,7 @@ const Challenges = ({ challenges, acceptChallenge }: { challenges: Challenge[],
       <div className='challenge-body'>
         {challenges.map(challenge => (
           <div key={challenge.id} className='challenge-item' onClick={_ => acceptChallenge(challenge)}>
-              {(challenge.accepted ? '[Accepted]' : '[Pending]') + ' ' + challenge.source.name + '(' + challenge.source.id + ') -> ' + challenge.target.name + '(' + challenge.target.id + ')'}
+              {'[' + challenge.status + '] ' + challenge.source.name + '(' + challenge.source.id + ') -> ' + (challenge.target ? challenge.target.name + '(' + challenge.target.id + ')' : 'anyone')}
           </div>
         ))}
       </div>
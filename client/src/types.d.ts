@@ -21,8 +21,28 @@ type Challenge = {
   target: {
     id: number,
     name: string,
-  },
-  accepted: boolean,
+  }|null,
+  status: 'pending' | 'accepted' | 'declined' | 'cancelled' | 'expired',
+  rules: RuleSet,
+  position: StartingPosition|null,
+  rated: boolean,
+  colour: ColourChoice,
+  timeControl: TimeControl|null,
+}
+
+type ColourChoice = 'white' | 'black' | 'random'
+
+type StartingPosition =
+  | { type: 'notation', data: string }
+  | { type: 'preset', data: 'standard' | 'skirmish' | 'towers' };
+
+type RuleSet = {
+  width: number,
+  height: number,
+  layout: number,
+  diagonal: boolean,
+  blastRadius: number,
+  maxStack: number,
 }
 
 type Bot = {
@@ -35,6 +55,57 @@ type User = {
   name: string,
 }
 
+type Rating = {
+  rating: number,
+  deviation: number,
+  volatility: number,
+  games: number,
+}
+
+type RatedUser = User & {
+  rating: Rating|null,
+}
+
+type Standing = User & {
+  rating: Rating,
+}
+
+type TimeControl = {
+  initial: number,
+  increment: number,
+}
+
+type QueueRequest = {
+  timeControl: TimeControl,
+  ratingRange: number|null,
+  rated: boolean,
+}
+
+type QueueStatus = {
+  request: QueueRequest|null,
+  ratingRange: number|null,
+  waiting: number,
+  players: number,
+}
+
+type ChatScope = 'lobby' | 'players' | 'spectators';
+
+type ChatMessage = {
+  scope: ChatScope,
+  user: User,
+  text: string,
+}
+
+type Credentials = {
+  name: string,
+  password: string,
+}
+
+type Session = {
+  user: User,
+  token: string,
+}
+
 type Square = Bot|null;
 
 type Board = Square[64];
@@ -43,6 +114,7 @@ type Row = Square[8];
 type GameState = {
   turn: Team,
   board: Board,
+  rules: RuleSet,
 }
 
 type Game = {
@@ -50,6 +122,42 @@ type Game = {
   white: User,
   black: User,
   gamestate: GameState,
+  result: GameResult|null,
+  rated: boolean,
+  moves: number,
+  timeControl: TimeControl|null,
+}
+
+type SquareChange = {
+  index: number,
+  bot: Bot|null,
+}
+
+type GameUpdate = {
+  id: number,
+  action: Action,
+  changes: SquareChange[],
+  moves: number,
+  result: GameResult|null,
+  checksum: number,
+}
+
+type GameSummary = {
+  id: number,
+  white: User,
+  black: User,
+  rated: boolean,
+}
+
+type GameResult = {
+  winner: Team|null,
+  reason: 'boomed' | 'abandoned' | 'resigned' | 'drawAgreed',
+}
+
+type Offer = {
+  kind: 'draw' | 'takeback' | 'rematch',
+  team: Team,
+  status: 'pending' | 'accepted' | 'declined',
 }
 
-export { Challenge, Action, Bot, User, Square, Board, GameState, Game, Row };
+export { ChatScope, ChatMessage, TimeControl, QueueRequest, QueueStatus, Rating, RatedUser, Standing, Credentials, Session, Challenge, ColourChoice, StartingPosition, RuleSet, Action, Bot, User, Square, Board, GameState, Game, SquareChange, GameUpdate, GameSummary, GameResult, Offer, Row };
@@ -0,0 +1,30 @@
+import { Team } from './App';
+import { Board, Game, GameState, GameUpdate } from './types';
+
+// Hashes the turn and board with 32-bit FNV-1a the same way as the server, where the turn is 0 for
+// white and 1 for black and each square is 0 when empty or twice its stack plus 1 for black robots
+const checksum = (gamestate: GameState): number => {
+  const turn = gamestate.turn === Team.White ? 0 : 1;
+  const squares = gamestate.board.map(bot => bot ? bot.stack * 2 + (bot.team === Team.Black ? 1 : 0) : 0);
+  return [turn, ...squares].reduce((hash, byte) => Math.imul(hash ^ byte, 0x01000193) >>> 0, 0x811c9dc5);
+}
+
+// Applies an update to the game, giving null when it does not follow on from the game or the
+// checksums differ, in which case the whole game needs to be asked for again
+const applyUpdate = (game: Game, update: GameUpdate): Game|null => {
+  if (update.id !== game.id || update.moves !== game.moves + 1) {
+    return null;
+  }
+  const board = [...game.gamestate.board] as Board;
+  update.changes.forEach(change => {
+    board[change.index] = change.bot;
+  });
+  const turn = game.gamestate.turn === Team.White ? Team.Black : Team.White;
+  const gamestate = { ...game.gamestate, board, turn };
+  if (checksum(gamestate) !== update.checksum) {
+    return null;
+  }
+  return { ...game, gamestate, moves: update.moves, result: update.result };
+}
+
+export { checksum, applyUpdate };
@@ -1,26 +1,18 @@
+use boombots_rules::boom;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 pub mod net;
+mod position;
+mod ruleset;
+
+pub use boombots_rules::Team;
+pub use ruleset::{Preset, RuleSet};
 
 #[macro_use]
 extern crate serde_big_array;
 big_array! { BigArray; }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub enum Team {
-    White,
-    Black,
-}
-
-impl Team {
-    pub fn next(&self) -> Team {
-        match self {
-            Team::White => Team::Black,
-            Team::Black => Team::White,
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Bot {
     pub team: Team,
     pub stack: u8,
@@ -32,11 +24,19 @@ impl Bot {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub struct GameState {
     pub turn: Team,
     #[serde(with = "BigArray")]
     pub board: [Option<Bot>; 64],
+    #[serde(default)]
+    pub rules: RuleSet,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
@@ -68,45 +68,40 @@ impl GameState {
         self.board[pos.index()] = bot;
     }
 
-    // Booms a location on the board
-    fn _boom(&mut self, pos: Pos) -> bool {
-        match self.get(pos) {
-            Some(_) => {
-                self.set(pos, None);
-                true
-            }
-            None => false,
-        }
+    pub fn new() -> GameState {
+        RuleSet::standard().start()
     }
 
-    // Booms a position on the board and then, if there was a robot that boomed at that position,
-    // store that boom in the chain for future chain booming.
-    fn boom_chain(&mut self, pos: Pos, chain: &mut Vec<Pos>) {
-        if let Some(_boom) = self.get(pos) {
-            self.set(pos, None);
-            chain.push(pos);
-        }
+    /// The squares whose contents differ in the later state, which includes every robot that a
+    /// chain of booms removed
+    pub fn changes(&self, later: &GameState) -> Vec<net::SquareChange> {
+        (0..self.board.len())
+            .filter(|&index| self.board[index] != later.board[index])
+            .map(|index| net::SquareChange { index: index as u8, bot: later.board[index] })
+            .collect()
     }
 
-    pub fn new() -> GameState {
-        let mut gs = GameState {
-            turn: Team::White,
-            board: [None; 64],
+    /// A 32-bit FNV-1a hash of the turn and the board, which clients compare with their own copy of
+    /// the game. The turn is hashed first as 0 for white and 1 for black, and then each square as
+    /// 0 when empty or otherwise twice its stack plus 1 for black robots.
+    pub fn checksum(&self) -> u32 {
+        let turn = match self.turn {
+            Team::White => 0,
+            Team::Black => 1,
         };
-
-        for x in 0..=7 {
-            for y in 0..=1 {
-                gs.set(Pos::new(x, y), Some(Bot::new(Team::White, 1)));
-            }
-            for y in 6..=7 {
-                gs.set(Pos::new(x, y), Some(Bot::new(Team::Black, 1)));
-            }
-        }
-
-        gs
+        let squares = self.board.iter().map(|bot| match bot {
+            None => 0,
+            Some(bot) => bot.stack * 2 + (bot.team == Team::Black) as u8,
+        });
+        std::iter::once(turn)
+            .chain(squares)
+            .fold(0x811c9dc5, |hash, byte: u8| (hash ^ byte as u32).wrapping_mul(0x01000193))
     }
 
     pub fn valid(&self, action: &Action) -> bool {
+        if !self.rules.contains(action.a) || !self.rules.contains(action.b) {
+            return false;
+        }
         let source = self.get(action.a);
         let target = self.get(action.b);
         match (source, target, action.n) {
@@ -114,57 +109,79 @@ impl GameState {
             (Some(source), _, 0) => GameState::valid_boom(self.turn, &source),
             // Stack
             (Some(source), Some(target), _) => {
-                GameState::valid_stack(self.turn, &source, &target, &action)
+                self.valid_stack(&source, &target, action) && self.clear(action)
             }
             // Move
-            (Some(source), None, _) => GameState::valid_move(self.turn, &source, &action),
+            (Some(source), None, _) => self.valid_move(&source, action) && self.clear(action),
             // Invalid
             (None, _, _) => false,
         }
     }
 
     fn absdiff(a: u8, b: u8) -> u8 {
-        ((a as i8) - (b as i8)).abs() as u8
+        ((a as i8) - (b as i8)).unsigned_abs()
+    }
+
+    // Checks that no robots are in the way between the source and target of an action, as robots
+    // can never jump over other robots of either team
+    fn clear(&self, action: &Action) -> bool {
+        let distance = GameState::absdiff(action.a.x, action.b.x)
+            .max(GameState::absdiff(action.a.y, action.b.y));
+        let step = |from: u8, to: u8, i: u8| match from.cmp(&to) {
+            Ordering::Less => from + i,
+            Ordering::Equal => from,
+            Ordering::Greater => from - i,
+        };
+        (1..distance).all(|i| {
+            let pos = Pos::new(
+                step(action.a.x, action.b.x, i),
+                step(action.a.y, action.b.y, i),
+            );
+            self.get(pos).is_none()
+        })
     }
 
     // Checks if a stacking move is valid
-    fn valid_stack(turn: Team, source: &Bot, target: &Bot, action: &Action) -> bool {
+    fn valid_stack(&self, source: &Bot, target: &Bot, action: &Action) -> bool {
         // Calculate movement distances in x and y axis
         let xd = GameState::absdiff(action.a.x, action.b.x);
         let yd = GameState::absdiff(action.a.y, action.b.y);
 
         // Only allow moving robots that belong to the turn player
-        let valid_source_team = source.team == turn;
+        let valid_source_team = source.team == self.turn;
         // Only allow stacking robots onto other turn player robots
         let valid_stack_team = source.team == target.team;
-        // Only allow orthongonal movement
-        let valid_move_direction = xd == 0 || yd == 0;
+        // Only allow orthogonal movement, or diagonal movement when the rules allow it
+        let valid_move_direction = self.rules.direction(xd, yd);
         // Only allow moving a distance less than or equal to the number of bots at the source but
         // always more than 0 as this indicates a boom
-        let valid_move_distance = xd + yd > 0 && xd + yd <= source.stack;
+        let valid_move_distance = xd.max(yd) > 0 && xd.max(yd) <= source.stack;
         // Only allow moving up to as many bots as were at the source
         let valid_move_size = action.n <= source.stack;
+        // Only allow stacks up to the maximum height
+        let valid_stack_size = action.n + target.stack <= self.rules.max_stack;
 
         valid_source_team
             && valid_stack_team
+            && valid_stack_size
             && valid_move_direction
             && valid_move_distance
             && valid_move_size
     }
 
     // Checks if a moving move is valid
-    fn valid_move(turn: Team, source: &Bot, action: &Action) -> bool {
+    fn valid_move(&self, source: &Bot, action: &Action) -> bool {
         // Calculate movement distances in x and y axis
         let xd = GameState::absdiff(action.a.x, action.b.x);
         let yd = GameState::absdiff(action.a.y, action.b.y);
 
         // Only allow moving robots that belong to the turn player
-        let valid_source_team = source.team == turn;
-        // Only allow orthongonal movement
-        let valid_move_direction = xd == 0 || yd == 0;
+        let valid_source_team = source.team == self.turn;
+        // Only allow orthogonal movement, or diagonal movement when the rules allow it
+        let valid_move_direction = self.rules.direction(xd, yd);
         // Only allow moving a distance less than or equal to the number of bots at the source but
         // always more than 0 as this indicates a boom
-        let valid_move_distance = xd + yd > 0 && xd + yd <= source.stack;
+        let valid_move_distance = xd.max(yd) > 0 && xd.max(yd) <= source.stack;
         // Only allow moving up to as many bots as were at the source
         let valid_move_size = action.n <= source.stack;
 
@@ -173,9 +190,7 @@ impl GameState {
 
     fn valid_boom(turn: Team, source: &Bot) -> bool {
         // Only allow booming robots that belong to the turn player
-        let valid_source_team = source.team == turn;
-
-        valid_source_team
+        source.team == turn
     }
 
     pub fn make(&mut self, action: &Action) {
@@ -183,98 +198,32 @@ impl GameState {
         let target = self.get(action.b);
         match (source, target, action.n) {
             // Boom
-            (Some(_source), _, 0) => self.make_boom(&action),
+            (Some(_source), _, 0) => self.make_boom(action),
             // Stack
-            (Some(_source), Some(_target), _) => self.make_stack(&action),
+            (Some(_source), Some(_target), _) => self.make_stack(action),
             // Move
-            (Some(_source), None, _) => self.make_move(&action),
+            (Some(_source), None, _) => self.make_move(action),
             // Invalid
             (None, _, _) => {}
         }
         self.turn = self.turn.next();
     }
 
+    // Booms the source and every robot caught in the chain reaction it starts, which spreads as far
+    // as the blast radius of the rules from every exploding robot
     fn make_boom(&mut self, action: &Action) {
-        let mut booms: Vec<Pos> = vec![action.a];
-
-        while !booms.is_empty() {
-            let boom = booms.pop().expect("no booms could be popped");
-            match (boom.x, boom.y) {
-                // Bottom-Left Corner
-                (0, 0) => {
-                    self.boom_chain(Pos::new(0, 0), &mut booms);
-                    self.boom_chain(Pos::new(0, 1), &mut booms);
-                    self.boom_chain(Pos::new(1, 0), &mut booms);
-                    self.boom_chain(Pos::new(1, 1), &mut booms);
-                }
-                // Top-Left Corner
-                (0, 7) => {
-                    self.boom_chain(Pos::new(0, 7), &mut booms);
-                    self.boom_chain(Pos::new(0, 6), &mut booms);
-                    self.boom_chain(Pos::new(1, 7), &mut booms);
-                    self.boom_chain(Pos::new(1, 6), &mut booms);
-                }
-                // Bottom-Right Corner
-                (7, 0) => {
-                    self.boom_chain(Pos::new(7, 0), &mut booms);
-                    self.boom_chain(Pos::new(7, 1), &mut booms);
-                    self.boom_chain(Pos::new(6, 0), &mut booms);
-                    self.boom_chain(Pos::new(6, 1), &mut booms);
-                }
-                // Top-Right Corner
-                (7, 7) => {
-                    self.boom_chain(Pos::new(7, 7), &mut booms);
-                    self.boom_chain(Pos::new(7, 6), &mut booms);
-                    self.boom_chain(Pos::new(6, 7), &mut booms);
-                    self.boom_chain(Pos::new(6, 6), &mut booms);
-                }
-                // Left edge
-                (0, y) => {
-                    self.boom_chain(Pos::new(0, y - 1), &mut booms);
-                    self.boom_chain(Pos::new(0, y), &mut booms);
-                    self.boom_chain(Pos::new(0, y + 1), &mut booms);
-                    self.boom_chain(Pos::new(1, y - 1), &mut booms);
-                    self.boom_chain(Pos::new(1, y), &mut booms);
-                    self.boom_chain(Pos::new(1, y + 1), &mut booms);
-                }
-                // Bottom edge
-                (x, 0) => {
-                    self.boom_chain(Pos::new(x - 1, 0), &mut booms);
-                    self.boom_chain(Pos::new(x, 0), &mut booms);
-                    self.boom_chain(Pos::new(x + 1, 0), &mut booms);
-                    self.boom_chain(Pos::new(x - 1, 1), &mut booms);
-                    self.boom_chain(Pos::new(x, 1), &mut booms);
-                    self.boom_chain(Pos::new(x + 1, 1), &mut booms);
-                }
-                // Right edge
-                (7, y) => {
-                    self.boom_chain(Pos::new(7, y - 1), &mut booms);
-                    self.boom_chain(Pos::new(7, y), &mut booms);
-                    self.boom_chain(Pos::new(7, y + 1), &mut booms);
-                    self.boom_chain(Pos::new(6, y - 1), &mut booms);
-                    self.boom_chain(Pos::new(6, y), &mut booms);
-                    self.boom_chain(Pos::new(6, y + 1), &mut booms);
-                }
-                // Top edge
-                (x, 7) => {
-                    self.boom_chain(Pos::new(x - 1, 7), &mut booms);
-                    self.boom_chain(Pos::new(x, 7), &mut booms);
-                    self.boom_chain(Pos::new(x + 1, 7), &mut booms);
-                    self.boom_chain(Pos::new(x - 1, 6), &mut booms);
-                    self.boom_chain(Pos::new(x, 6), &mut booms);
-                    self.boom_chain(Pos::new(x + 1, 6), &mut booms);
-                }
-                (x, y) => {
-                    self.boom_chain(Pos::new(x - 1, y - 1), &mut booms);
-                    self.boom_chain(Pos::new(x - 1, y), &mut booms);
-                    self.boom_chain(Pos::new(x - 1, y + 1), &mut booms);
-                    self.boom_chain(Pos::new(x, y - 1), &mut booms);
-                    self.boom_chain(Pos::new(x, y), &mut booms);
-                    self.boom_chain(Pos::new(x, y + 1), &mut booms);
-                    self.boom_chain(Pos::new(x + 1, y - 1), &mut booms);
-                    self.boom_chain(Pos::new(x + 1, y), &mut booms);
-                    self.boom_chain(Pos::new(x + 1, y + 1), &mut booms);
-                }
+        let occupied = self
+            .board
+            .iter()
+            .enumerate()
+            .filter(|(_, bot)| bot.is_some())
+            .fold(0, |occupied, (index, _)| occupied | 1 << index);
+        let blast =
+            boom::flood_fill_radius(occupied, 1 << action.a.index(), self.rules.blast_radius);
+
+        for (index, bot) in self.board.iter_mut().enumerate() {
+            if blast & (1 << index) != 0 {
+                *bot = None;
             }
         }
     }
@@ -1,7 +1,7 @@
-use crate::{Action, GameState};
+use crate::{Action, Bot, GameState, Preset, RuleSet, Team};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct User {
     pub id: u32,
     pub name: String,
@@ -13,19 +13,280 @@ impl User {
     }
 }
 
+/// A Glicko-2 rating, where the deviation is how uncertain the rating is and the volatility is how
+/// much the rating is expected to change between games
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    /// How many rated games the rating is based on
+    pub games: u32,
+}
+
+impl Default for Rating {
+    /// The rating of a player who has not played a rated game yet
+    fn default() -> Self {
+        Rating {
+            rating: 1500.0,
+            deviation: 350.0,
+            volatility: 0.06,
+            games: 0,
+        }
+    }
+}
+
+/// A user in the lobby along with their rating, which guests do not have
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RatedUser {
+    #[serde(flatten)]
+    pub user: User,
+    pub rating: Option<Rating>,
+}
+
+/// A registered account and its rating
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Standing {
+    #[serde(flatten)]
+    pub user: User,
+    pub rating: Rating,
+}
+
+/// The rating of an account after each of its rated games, oldest first
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingHistory {
+    pub id: u32,
+    pub games: Vec<RatedGame>,
+}
+
+/// The rating of an account after the rated game with the id
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RatedGame {
+    pub game: u32,
+    pub rating: Rating,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", content = "data")]
 #[serde(rename_all = "camelCase")]
 pub enum Event {
+    /// Joins as a guest with the name, which can not be the name of a registered account
     Handshake(String),
+    Register(Credentials),
+    Login(Credentials),
+    /// Handshakes with a session token from an earlier registration or login
+    Authenticate(String),
+    /// Sent after a registration or login with a session token for later handshakes
+    Session(Session),
+    /// Sent instead of a handshake to reattach a new socket to a seat in a running game
+    Resume(String),
+    /// Sent to each seated player when their game starts, for resuming it after a disconnect
+    ResumeToken(String),
     SendChallenge(SendChallengeInfo),
     AcceptChallenge(AcceptChallengeInfo),
+    /// Turns down a challenge sent to the connection by its id
+    DeclineChallenge(u32),
+    /// Withdraws a challenge sent by the connection by its id
+    CancelChallenge(u32),
+    /// Sent to both users of a challenge whenever its status changes, and to everyone in the lobby
+    /// for open challenges
     ChallengeBroadcast(ChallengeInfo),
-    LobbyUserBroadcast(u32, Vec<User>),
+    /// Sent after the handshake with the user the connection was given
+    Welcome(User),
+    LobbyUserBroadcast(Vec<RatedUser>),
+    /// Asks for the highest rated players
+    Leaderboard,
+    /// The highest rated players with at least one rated game, best first
+    LeaderboardBroadcast(Vec<Standing>),
+    /// Asks for the rating history of the account with the id
+    RatingHistory(u32),
+    RatingHistoryBroadcast(RatingHistory),
+    /// Waits in the matchmaking queue for an opponent, replacing any earlier request
+    JoinQueue(QueueRequest),
+    LeaveQueue,
+    /// Sent when joining or leaving the queue and while waiting in it
+    QueueStatus(QueueStatus),
 
     GameBroadcast(GameInfo),
+    /// Sent to everyone in a game after each action instead of the whole game
+    GameUpdate(GameUpdate),
+    /// Asks for the whole game again, when a game update did not match the local copy
+    Resync,
     GameAction(Action),
+    /// Gives up the game, which the opponent wins
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    /// Asks the opponent to undo the last action of the player, along with the opponent's reply to
+    /// it when they have already answered
+    RequestTakeback,
+    AcceptTakeback,
+    DeclineTakeback,
+    /// Asks the opponent for another game once the game has ended, from the same starting position
+    /// under the same rules and time control with the colours swapped
+    OfferRematch,
+    AcceptRematch,
+    DeclineRematch,
+    /// Sent to everyone in a game when an offer is made or answered. Offers lapse once an action is
+    /// played, and a new offer replaces any earlier one.
+    OfferBroadcast(Offer),
+    /// Asks for the games being played
+    ListGames,
+    /// The games being played, which lobby users can spectate
+    GameList(Vec<GameSummary>),
+    /// Leaves the lobby to watch the game with the id
+    Spectate(u32),
+    /// Leaves the game being watched, or a game that has ended, back to the lobby
+    LeaveGame,
+
+    SendChat(SendChatInfo),
+    /// A chat message sent to everyone who can read its scope
+    Chat(ChatMessage),
+    /// The latest messages of a chat, sent when joining the lobby or a game
+    ChatHistory(Vec<ChatMessage>),
+    /// Stops receiving chat messages from the user with the id
+    Mute(u32),
+    Unmute(u32),
     Quit,
+
+    /// Sent only to the connection whose event was rejected. The context holds whatever the
+    /// rejected event referred to, such as the malformed text or the notation of an action.
+    Error {
+        code: ErrorCode,
+        message: String,
+        context: Option<String>,
+    },
+}
+
+impl Event {
+    pub fn error(code: ErrorCode, message: &str, context: Option<String>) -> Event {
+        Event::Error {
+            code,
+            message: message.to_string(),
+            context,
+        }
+    }
+}
+
+/// The reason an event was rejected. Codes are serialized by name and are never renamed or
+/// reused, so clients can rely on them while messages are free to change.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The message was not valid event json
+    MalformedEvent,
+    /// The first event of a connection was not a handshake or a resume
+    HandshakeRequired,
+    /// A handshake was sent after the connection had already performed one
+    AlreadyHandshaked,
+    /// The event is only ever sent by the server
+    UnexpectedEvent,
+    /// The challenge has rules or a starting position that can not be played
+    InvalidChallenge,
+    /// The challenged user is not in the lobby
+    UnknownUser,
+    /// The challenge does not exist or was already accepted
+    UnknownChallenge,
+    /// The challenge was sent to a different user, or the user tried to accept their own challenge
+    NotChallenged,
+    /// The challenge was sent by a different user
+    NotChallenger,
+    /// The connection sent a game action without being seated in a game
+    NotInGame,
+    /// The game has not started yet
+    GameNotStarted,
+    /// The action is not legal in the current position
+    IllegalAction,
+    /// The connection is watching the game rather than playing in it
+    NotSeated,
+    /// The action was sent by the player whose turn it is not
+    NotYourTurn,
+    /// The resume token does not belong to a seat in any game
+    UnknownResumeToken,
+    /// The game has already ended
+    GameOver,
+    /// The game does not exist or has already ended
+    UnknownGame,
+    /// Seated players can not leave a game until it ends
+    GameInProgress,
+    /// Chat messages are 1 to 200 characters
+    InvalidMessage,
+    /// Too many chat messages were sent in a short time
+    RateLimited,
+    /// The chat scope is for spectators, and the connection is playing in the game
+    NotSpectating,
+    /// The event is for users in the lobby, such as lobby chat, challenges, the queue or spectating,
+    /// and the connection is playing or watching a game
+    NotInLobby,
+    /// There is no offer from the opponent to answer
+    NoOffer,
+    /// One of the players has left the finished game, so there is nobody to play a rematch with
+    RematchUnavailable,
+    /// The player has not played an action that can be taken back
+    NothingToTakeBack,
+    /// Account names are 1 to 24 letters, digits, underscores or dashes
+    InvalidName,
+    /// Account passwords are at least 8 characters
+    InvalidPassword,
+    /// The name belongs to a registered account
+    NameTaken,
+    /// There is no account with the name and password
+    InvalidCredentials,
+    /// The session token is forged, malformed or expired
+    InvalidToken,
+    /// The server failed to read or write its database
+    StorageError,
+    /// Rated games are only played between registered accounts
+    NotRegistered,
+    /// Another socket is already connected as the account, or is already playing in the seat
+    AlreadyConnected,
+}
+
+/// Who can read a chat message
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChatScope {
+    /// Everyone in the lobby
+    Lobby,
+    /// The players of a game, which spectators of the game can also read
+    Players,
+    /// Only the spectators of a game, so that they do not distract the players
+    Spectators,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SendChatInfo {
+    pub scope: ChatScope,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    pub scope: ChatScope,
+    pub user: User,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Credentials {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub user: User,
+    pub token: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -33,25 +294,139 @@ pub enum Event {
 pub struct ChallengeInfo {
     pub id: u32,
     pub source: User,
-    pub target: User,
-    pub accepted: bool,
+    /// The challenged user, which is None for open challenges until someone accepts them
+    pub target: Option<User>,
+    pub status: ChallengeStatus,
+    pub rules: RuleSet,
+    pub position: Option<StartingPosition>,
+    pub rated: bool,
+    /// The colour the challenger plays, where a random colour is settled once the challenge is
+    /// accepted
+    pub colour: ColourChoice,
+    /// The time control the players were paired by, for games from the matchmaking queue
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
 }
 impl ChallengeInfo {
-    pub fn new(id: u32, source: User, target: User) -> ChallengeInfo {
+    pub fn new(
+        id: u32,
+        source: User,
+        target: Option<User>,
+        rules: RuleSet,
+        position: Option<StartingPosition>,
+        rated: bool,
+        colour: ColourChoice,
+    ) -> ChallengeInfo {
         ChallengeInfo {
             id,
             source,
             target,
-            accepted: false,
+            status: ChallengeStatus::Pending,
+            rules,
+            position,
+            rated,
+            colour,
+            time_control: None,
+        }
+    }
+
+    /// The white and black players of the challenge, once it has a target and its colour is settled
+    pub fn seats(&self) -> Option<(User, User)> {
+        let target = self.target.clone()?;
+        match self.colour {
+            ColourChoice::White => Some((self.source.clone(), target)),
+            ColourChoice::Black => Some((target, self.source.clone())),
+            ColourChoice::Random => None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ColourChoice {
+    White,
+    Black,
+    #[default]
+    Random,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChallengeStatus {
+    /// Waiting for the challenged user, or anyone for open challenges, to answer
+    Pending,
+    Accepted,
+    Declined,
+    /// Withdrawn by the challenger, or dropped because either user left the lobby
+    Cancelled,
+    /// Nobody answered the challenge in time
+    Expired,
+}
+
+/// The position a challenge starts from, either written in position notation or chosen by name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "data")]
+#[serde(rename_all = "camelCase")]
+pub enum StartingPosition {
+    Notation(String),
+    Preset(Preset),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct SendChallengeInfo {
     pub source: Option<u32>,
-    pub target: u32,
+    /// The challenged user, where anyone in the lobby can accept the challenge when left out
+    #[serde(default)]
+    pub target: Option<u32>,
+    /// The rules the game will be played under, which are the standard rules when left out
+    #[serde(default)]
+    pub rules: RuleSet,
+    /// The position the game starts from, which is the starting layout of the rules when left out
+    #[serde(default)]
+    pub position: Option<StartingPosition>,
+    /// Whether the result of the game updates the ratings of both players, which is casual when
+    /// left out
+    #[serde(default)]
+    pub rated: bool,
+    /// The colour the challenger wants to play, which is random when left out
+    #[serde(default)]
+    pub colour: ColourChoice,
+}
+
+/// How long each player has for the game, as the time they start with and the time added after each
+/// of their actions, in seconds
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeControl {
+    pub initial: u32,
+    pub increment: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueRequest {
+    pub time_control: TimeControl,
+    /// How far the rating of the opponent can be from the player's own rating, which widens the
+    /// longer the player waits. Any opponent is accepted when left out.
+    #[serde(default)]
+    pub rating_range: Option<f64>,
+    /// Whether to only be paired for rated games, which need a registered account
+    #[serde(default)]
+    pub rated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStatus {
+    /// The request the player is waiting with, which is None once they leave the queue
+    pub request: Option<QueueRequest>,
+    /// How far the rating range has widened to by now
+    pub rating_range: Option<f64>,
+    /// How many seconds the player has been waiting
+    pub waiting: u64,
+    /// How many players are waiting in the queue
+    pub players: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
@@ -68,4 +443,90 @@ pub struct GameInfo {
     pub white: User,
     pub black: User,
     pub gamestate: GameState,
+    /// How the game ended, which is None while it is still being played
+    pub result: Option<GameResult>,
+    pub rated: bool,
+    /// How many actions have been played, which game updates count on from
+    pub moves: u32,
+    /// The time control the players were paired by, which games are not clocked by yet
+    pub time_control: Option<TimeControl>,
+}
+
+/// What an action changed in a game. Clients apply it to their copy of the game and send a resync
+/// when the move number does not follow on from theirs or the checksums differ.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GameUpdate {
+    pub id: u32,
+    pub action: Action,
+    /// Every square whose contents changed, including the squares emptied by a chain of booms
+    pub changes: Vec<SquareChange>,
+    /// How many actions have been played, counting this one
+    pub moves: u32,
+    pub result: Option<GameResult>,
+    /// The checksum of the game state after the action
+    pub checksum: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SquareChange {
+    /// The index of the square on the board, counting along each row from the first
+    pub index: u8,
+    pub bot: Option<Bot>,
+}
+
+/// A game being played, as listed for spectators
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSummary {
+    pub id: u32,
+    pub white: User,
+    pub black: User,
+    pub rated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GameResult {
+    /// The winning team, or None for a draw
+    pub winner: Option<Team>,
+    pub reason: EndReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EndReason {
+    /// A team lost all of its robots
+    Boomed,
+    /// A player stayed disconnected for longer than the grace period
+    Abandoned,
+    Resigned,
+    /// Both players agreed to a draw
+    DrawAgreed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Offer {
+    pub kind: OfferKind,
+    /// The team that made the offer
+    pub team: Team,
+    pub status: OfferStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OfferKind {
+    Draw,
+    Takeback,
+    Rematch,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OfferStatus {
+    Pending,
+    Accepted,
+    Declined,
 }
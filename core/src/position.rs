@@ -0,0 +1,127 @@
+use crate::{Action, Bot, GameState, Pos, RuleSet};
+use boombots_rules::{self as rules, Position, Stack, Team};
+
+// Squares are indexed the same way by the rules and by positions on the board
+fn pos(square: u8) -> Pos {
+    Pos::new(square % 8, square / 8)
+}
+
+// Every direction a stack could move in, including the diagonals
+const DIRECTIONS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+impl From<Action> for rules::Action {
+    fn from(action: Action) -> Self {
+        rules::Action {
+            source: action.a.index() as u8,
+            target: if action.n == 0 {
+                0
+            } else {
+                action.b.index() as u8
+            },
+            robots: action.n,
+        }
+    }
+}
+
+impl From<rules::Action> for Action {
+    fn from(action: rules::Action) -> Self {
+        // Booms have no target, so point them back at their source
+        let target = if action.robots == 0 {
+            action.source
+        } else {
+            action.target
+        };
+        Action {
+            a: pos(action.source),
+            b: pos(target),
+            n: action.robots,
+        }
+    }
+}
+
+impl Position for GameState {
+    /// The whole game state is small enough to copy, so undoing restores the previous one
+    type Undo = GameState;
+
+    fn turn(&self) -> Team {
+        self.turn
+    }
+
+    fn stack(&self, square: u8) -> Option<Stack> {
+        self.board[square as usize].map(|bot| Stack {
+            team: bot.team,
+            height: bot.stack,
+        })
+    }
+
+    /// Positions built from stacks are always played under the standard rules
+    fn from_stacks(stacks: &[Option<Stack>; 64], turn: Team) -> Self {
+        GameState {
+            turn,
+            board: stacks.map(|stack| stack.map(|s| Bot::new(s.team, s.height))),
+            rules: RuleSet::standard(),
+        }
+    }
+
+    fn legal_actions(&self) -> Vec<rules::Action> {
+        let mut actions = Vec::new();
+        for (square, bot) in self.board.iter().enumerate() {
+            let bot = match bot {
+                Some(bot) if bot.team == self.turn => bot,
+                _ => continue,
+            };
+            let source = pos(square as u8);
+            actions.push(rules::Action {
+                source: square as u8,
+                target: 0,
+                robots: 0,
+            });
+
+            // Try every target within reach and leave the rest of the checks to valid
+            for (dx, dy) in DIRECTIONS {
+                if !self.rules.direction(dx.unsigned_abs(), dy.unsigned_abs()) {
+                    continue;
+                }
+                for distance in 1..=bot.stack as i8 {
+                    let x = source.x as i8 + dx * distance;
+                    let y = source.y as i8 + dy * distance;
+                    if !(0..self.rules.width as i8).contains(&x)
+                        || !(0..self.rules.height as i8).contains(&y)
+                    {
+                        break;
+                    }
+                    for n in 1..=bot.stack {
+                        let action = Action {
+                            a: source,
+                            b: Pos::new(x as u8, y as u8),
+                            n,
+                        };
+                        if self.valid(&action) {
+                            actions.push(action.into());
+                        }
+                    }
+                }
+            }
+        }
+        actions
+    }
+
+    fn apply(&mut self, action: &rules::Action) -> GameState {
+        let previous = *self;
+        self.make(&Action::from(*action));
+        previous
+    }
+
+    fn undo(&mut self, undo: GameState) {
+        *self = undo;
+    }
+}
@@ -0,0 +1,150 @@
+use crate::net::StartingPosition;
+use crate::{Bot, GameState, Pos};
+use boombots_rules::{Position, Team, MAX_STACK, START, START_LAYOUT};
+use serde::{Deserialize, Serialize};
+
+/// The variant of the rules a game is played under. Boards of up to 8x8 squares sit in the corner
+/// of the 8x8 frame used by `GameState`, so the square on file x and rank y keeps index x + 8y and
+/// every square past the width or height of the board is never occupied.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleSet {
+    pub width: u8,
+    pub height: u8,
+    /// The squares white starts with a single robot on. Black starts on the same squares seen from
+    /// the other end of the board.
+    pub layout: u64,
+    /// Whether stacks can also move diagonally, as far as they could move orthogonally
+    pub diagonal: bool,
+    /// How many squares away from an exploding robot other robots are caught in the explosion
+    pub blast_radius: u8,
+    /// The tallest stack of robots allowed on a single square
+    pub max_stack: u8,
+}
+
+/// A named starting position which can be chosen instead of writing out its notation
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Preset {
+    /// The starting position of the standard rules
+    Standard,
+    /// Two robots for each team in the middle of the board
+    Skirmish,
+    /// A single stack of twelve robots for each team
+    Towers,
+}
+
+impl Preset {
+    pub fn notation(&self) -> &'static str {
+        match self {
+            Preset::Standard => START,
+            Preset::Skirmish => "8/8/2a2a2/8/8/2A2A2/8/8 w",
+            Preset::Towers => "8/8/8/3l4/8/8/4L3/8 w",
+        }
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl RuleSet {
+    /// The standard rules, played on an 8x8 board from `START` with twelve robots on each side
+    pub fn standard() -> RuleSet {
+        RuleSet {
+            width: 8,
+            height: 8,
+            layout: START_LAYOUT,
+            diagonal: false,
+            blast_radius: 1,
+            max_stack: MAX_STACK,
+        }
+    }
+
+    /// Checks that the board fits in the frame, that stacks can be written in position notation,
+    /// which only has letters for heights up to `MAX_STACK`, and that both teams start with robots
+    /// on separate squares of the board
+    pub fn valid(&self) -> bool {
+        if !(1..=8).contains(&self.width) || !(1..=8).contains(&self.height) {
+            return false;
+        }
+        let board = (0..self.width).fold(0, |board, x| {
+            (0..self.height).fold(board, |board, y| board | 1 << Pos::new(x, y).index())
+        });
+        let white = self.layout;
+        let black = self.black();
+
+        (1..=MAX_STACK).contains(&self.max_stack)
+            && white != 0
+            && white & !board == 0
+            && white & black == 0
+    }
+
+    /// Checks whether the position is on the board
+    pub fn contains(&self, pos: Pos) -> bool {
+        pos.x < self.width && pos.y < self.height
+    }
+
+    /// Checks whether a stack can move by the distances along each axis, which is orthogonally or
+    /// diagonally when it is allowed
+    pub fn direction(&self, xd: u8, yd: u8) -> bool {
+        xd == 0 || yd == 0 || (self.diagonal && xd == yd)
+    }
+
+    // Finds the squares black starts on by flipping the layout across the middle rank of the board
+    fn black(&self) -> u64 {
+        (0..self.height).fold(0, |black, rank| {
+            let row = (self.layout >> (8 * rank)) & 0xff;
+            black | row << (8 * (self.height - 1 - rank) as u32)
+        })
+    }
+
+    /// Sets up the starting position of a game under these rules
+    pub fn start(&self) -> GameState {
+        let mut board = [None; 64];
+        let black = self.black();
+        for (index, square) in board.iter_mut().enumerate() {
+            if self.layout & (1 << index) != 0 {
+                *square = Some(Bot::new(Team::White, 1));
+            } else if black & (1 << index) != 0 {
+                *square = Some(Bot::new(Team::Black, 1));
+            }
+        }
+        GameState {
+            turn: Team::White,
+            board,
+            rules: *self,
+        }
+    }
+
+    /// Sets up a game under these rules from the starting position, or from the starting layout of
+    /// the rules when there is no starting position. Returns None if the position is malformed,
+    /// has stacks off the board or above the maximum stack size, or has already been won.
+    pub fn setup(&self, position: Option<&StartingPosition>) -> Option<GameState> {
+        let notation = match position {
+            None => return Some(self.start()),
+            Some(StartingPosition::Notation(notation)) => notation.as_str(),
+            Some(StartingPosition::Preset(preset)) => preset.notation(),
+        };
+        let mut gamestate = GameState::from_notation(notation)?;
+        gamestate.rules = *self;
+
+        let fits = gamestate
+            .board
+            .iter()
+            .enumerate()
+            .all(|(index, bot)| match bot {
+                Some(bot) => {
+                    let pos = Pos::new(index as u8 % 8, index as u8 / 8);
+                    self.contains(pos) && bot.stack <= self.max_stack
+                }
+                None => true,
+            });
+        match fits && gamestate.outcome().is_none() {
+            true => Some(gamestate),
+            false => None,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use engine::action::Action;
+use engine::book::Book;
+use engine::constants::*;
+use engine::format::Format;
+use engine::record::Record;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+
+/// Builds a book from the opening actions of played games, such as those recorded by the match
+/// runner. Each action earns 2 weight when the player went on to win, 1 for a draw and nothing
+/// for a loss, so that actions which only ever lost are left out of the book.
+pub fn generate(path: &str, plies: usize) -> io::Result<Book> {
+    let mut book = Book::new();
+
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = Record::parse(line).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, format!("Invalid record on line {}", number + 1))
+        })?;
+
+        let mut bitboard = record.opening.clone();
+        for action in record.actions.iter().take(plies) {
+            // A record that does not follow on from its opening would fill the book with the wrong
+            // positions
+            if !Action::generate(&bitboard, None).iter().any(|scored| scored.action == *action) {
+                let message =
+                    format!("Illegal action {} on line {}", Format::action(action), number + 1);
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+            let result = if bitboard.turn == WHITE { record.result } else { 1.0 - record.result };
+            let weight = (2.0 * result).round() as u32;
+            if weight > 0 {
+                book.add(&bitboard, *action, weight);
+            }
+            let delta = bitboard.delta(*action);
+            bitboard.make(&delta);
+        }
+    }
+
+    Ok(book)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::bitboard::Bitboard;
+
+    fn generate_from(name: &str, lines: &str) -> io::Result<Book> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, lines)?;
+        let book = generate(path.to_str().unwrap(), 2);
+        fs::remove_file(&path)?;
+        book
+    }
+
+    #[test]
+    fn records_with_illegal_actions_are_rejected() {
+        let opening = Format::notation(&Bitboard::new());
+        let legal = format!("{};b2b3/1 g7g6/1;1\n", opening);
+        let book = generate_from("book_legal_records.txt", &legal).unwrap();
+        assert!(!book.is_empty());
+
+        let illegal = format!("{}{};b2b3/1 b3b4/1;1\n", legal, opening);
+        let error = generate_from("book_illegal_records.txt", &illegal).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Illegal action b3b4/1 on line 2");
+    }
+}
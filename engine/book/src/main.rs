@@ -0,0 +1,35 @@
+mod games;
+mod search;
+
+use engine::book::Book;
+use engine::tool::Args;
+
+const USAGE: &str = "Usage:
+    book search <output> [--plies N] [--depth N] [--width N] [--margin N] [--params FILE]
+    book games <output> <records> [--plies N]";
+
+fn main() {
+    let args = Args::from_env(USAGE);
+
+    let (book, output): (Book, &str) =
+        match args.positional.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+            ["search", output] => {
+                let settings = search::Settings {
+                    plies: args.option("plies", 4),
+                    depth: args.option("depth", 4),
+                    width: args.option("width", 4),
+                    margin: args.option("margin", 50),
+                    params: args.params(),
+                };
+                (search::generate(settings), output)
+            }
+            ["games", output, records] => {
+                let plies = args.option("plies", 8);
+                (games::generate(records, plies).expect("Failed to read records"), output)
+            }
+            _ => args.exit("Unknown command"),
+        };
+
+    book.save(output).expect("Failed to save book");
+    println!("Saved {} book entries to {}", book.len(), output);
+}
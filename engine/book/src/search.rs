@@ -0,0 +1,105 @@
+use engine::action::Action;
+use engine::bitboard::Bitboard;
+use engine::book::Book;
+use engine::constants::*;
+use engine::evaluate::{EvalParams, Evaluate};
+use engine::format::Format;
+use engine::search::{Limit, Search};
+use engine::transpose::TranspositionTable;
+use std::collections::HashSet;
+
+pub struct Settings {
+    /// Number of plies from the start position to cover
+    pub plies: usize,
+    /// Depth used to score each candidate action
+    pub depth: u8,
+    /// Number of candidate actions scored in each position, taken in move ordering
+    pub width: usize,
+    /// How far below the best score a candidate can be while still being added to the book
+    pub margin: i16,
+    /// Evaluation parameters used to score candidates
+    pub params: EvalParams,
+}
+
+struct Generator {
+    settings: Settings,
+    search: Search<Evaluate>,
+    tt: TranspositionTable,
+    book: Book,
+    expanded: HashSet<u64>,
+}
+
+impl Generator {
+    /// Scores an action by searching the reply to it
+    fn score(&mut self, bitboard: &mut Bitboard, action: Action) -> i16 {
+        let delta = bitboard.delta(action);
+        let hash = bitboard.make(&delta);
+        let limit = Limit::Depth(self.settings.depth.saturating_sub(1).max(1));
+        let score = match self.search.think(bitboard, &mut self.tt, limit) {
+            Some(reply) => -reply.score,
+            None => WIN,
+        };
+        bitboard.undo(&delta, hash);
+        score
+    }
+
+    /// Adds the best scoring candidates of the position to the book and then expands the
+    /// positions they lead to. Each candidate within the margin of the best is weighted by how
+    /// close it is to the best.
+    fn expand(&mut self, bitboard: &mut Bitboard, plies: usize) {
+        if plies == 0
+            || Evaluate::outcome(bitboard).is_some()
+            || !self.expanded.insert(bitboard.canonical().0)
+        {
+            return;
+        }
+
+        let candidates: Vec<Action> = Action::generate(bitboard, None)
+            .iter()
+            .take(self.settings.width)
+            .map(|scored| scored.action)
+            .collect();
+        let scored: Vec<(Action, i16)> =
+            candidates.into_iter().map(|action| (action, self.score(bitboard, action))).collect();
+        let best = match scored.iter().map(|(_, score)| *score).max() {
+            Some(best) => best,
+            None => return,
+        };
+
+        for (action, score) in scored {
+            let behind = best.saturating_sub(score);
+            if behind > self.settings.margin {
+                continue;
+            }
+            let weight = (self.settings.margin - behind) as u32 + 1;
+            self.book.add(bitboard, action, weight);
+            println!(
+                "[plies={}] {} {} weight={}",
+                plies,
+                Format::notation(bitboard),
+                Format::action(&action),
+                weight
+            );
+
+            let delta = bitboard.delta(action);
+            let hash = bitboard.make(&delta);
+            self.expand(bitboard, plies - 1);
+            bitboard.undo(&delta, hash);
+        }
+    }
+}
+
+/// Builds a book by scoring the most promising actions with deep searches, starting from the start
+/// position and expanding every action that is close to the best one
+pub fn generate(settings: Settings) -> Book {
+    let plies = settings.plies;
+    let mut generator = Generator {
+        search: Search::new(Evaluate::new(settings.params)),
+        settings,
+        tt: TranspositionTable::new(22),
+        book: Book::new(),
+        expanded: HashSet::new(),
+    };
+    generator.expand(&mut Bitboard::new(), plies);
+    generator.book
+}
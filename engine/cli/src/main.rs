@@ -1,16 +1,22 @@
+mod protocol;
+
 use engine::{
     bitboard::Bitboard,
+    book::Book,
     constants::{LOSS, WIN},
+    evaluate::{EvalParams, Evaluate},
     search::Search,
     transpose::TranspositionTable,
 };
+use std::env;
 
-fn search(n: u8) {
+fn search(n: u8, params: EvalParams) {
     let mut tt = TranspositionTable::new(28);
     let mut bb = Bitboard::new();
+    let search = Search::new(Evaluate::new(params));
 
     for i in 1..n {
-        let result = Search::negamax_move(&mut bb, &mut tt, i, LOSS, WIN);
+        let result = search.negamax_move(&mut bb, &mut tt, i, LOSS, WIN);
         println!(
             "[depth={}] {} <= {:?} || t={} n={}",
             result.depth, result.score, result.action, result.trans, result.nodes
@@ -18,6 +24,26 @@ fn search(n: u8) {
     }
 }
 
+// Optionally loads the evaluation parameters from a json file
+fn params(path: Option<String>) -> EvalParams {
+    match path {
+        Some(path) if path != "default" => {
+            EvalParams::load(&path).expect("Failed to load evaluation parameters")
+        }
+        _ => EvalParams::default(),
+    }
+}
+
 fn main() {
-    search(20);
+    let mut args = env::args().skip(1);
+    match args.next() {
+        // Speak the engine text protocol, such as when playing in the match runner, optionally
+        // followed by the evaluation parameters (or "default") and an opening book
+        Some(arg) if arg == "protocol" => {
+            let params = params(args.next());
+            let book = args.next().map(|path| Book::load(path).expect("Failed to load book"));
+            protocol::run(params, book).expect("Failed to run the engine protocol")
+        }
+        path => search(20, params(path)),
+    }
 }
@@ -0,0 +1,66 @@
+use engine::{
+    bitboard::Bitboard,
+    book::Book,
+    evaluate::{EvalParams, Evaluate},
+    format::Format,
+    search::{Limit, Search},
+    transpose::TranspositionTable,
+};
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+/// Runs the engine text protocol over stdin and stdout so that other programs, such as the match
+/// runner, can play against this build of the engine. Each command is a single line:
+///
+/// - `isready` replies with `readyok` once all previous commands have been handled
+/// - `newgame` clears the transposition table
+/// - `position <notation>` sets the position to search from
+/// - `go depth <n>` or `go movetime <ms>` searches the position and replies with
+///   `bestmove <action>`, or `bestmove none` when there are no actions available
+/// - `quit` exits
+pub fn run(params: EvalParams, book: Option<Book>) -> io::Result<()> {
+    let mut search = Search::new(Evaluate::new(params));
+    if let Some(book) = book {
+        search = search.with_book(book, 1.0, 1);
+    }
+    let mut tt = TranspositionTable::new(24);
+    let mut bitboard = Bitboard::new();
+    let mut stdout = io::stdout();
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("isready"), _, _) => writeln!(stdout, "readyok")?,
+            (Some("newgame"), _, _) => tt = TranspositionTable::new(24),
+            (Some("position"), Some(_), Some(_)) => {
+                let notation = line.trim_start().trim_start_matches("position").trim();
+                match Format::parse(notation) {
+                    Some(parsed) => bitboard = parsed,
+                    None => eprintln!("Invalid position: {}", notation),
+                }
+            }
+            (Some("go"), Some(kind), Some(value)) => {
+                let limit = match (kind, value.parse::<u64>()) {
+                    ("depth", Ok(depth)) => Limit::Depth(depth.clamp(1, 64) as u8),
+                    ("movetime", Ok(millis)) => Limit::Time(Duration::from_millis(millis)),
+                    _ => {
+                        eprintln!("Invalid limit: {}", line);
+                        continue;
+                    }
+                };
+                match search.think(&mut bitboard, &mut tt, limit) {
+                    Some(result) => {
+                        writeln!(stdout, "bestmove {}", Format::action(&result.action))?
+                    }
+                    None => writeln!(stdout, "bestmove none")?,
+                }
+            }
+            (Some("quit"), _, _) => break,
+            _ => eprintln!("Unknown command: {}", line),
+        }
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
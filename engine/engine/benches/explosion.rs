@@ -0,0 +1,22 @@
+use boombots_rules::boom;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use engine::bitwise::Bitwise;
+
+// Occupancies with chains of different sizes, booming from a1: the start position, a long snake
+// winding across the board, and a completely full board
+const BOARDS: [(&str, u64); 3] =
+    [("start", 0xdbdb00000000dbdb), ("snake", 0x01ff80ff01ff80ff), ("full", u64::MAX)];
+
+fn criterion_benchmark(c: &mut Criterion) {
+    for (name, occupied) in BOARDS {
+        c.bench_function(&format!("explosion dfs {}", name), |b| {
+            b.iter(|| Bitwise::dfs(black_box(occupied), black_box(0)))
+        });
+        c.bench_function(&format!("explosion flood fill {}", name), |b| {
+            b.iter(|| boom::flood_fill(black_box(occupied), black_box(1)))
+        });
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
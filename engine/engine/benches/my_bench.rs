@@ -9,9 +9,10 @@ use engine::{
 fn search(n: u8) {
     let mut tt = TranspositionTable::new(20);
     let mut bb = Bitboard::new();
+    let search = Search::default();
 
     for i in 1..n {
-        Search::negamax_move(&mut bb, &mut tt, i, LOSS, WIN);
+        search.negamax_move(&mut bb, &mut tt, i, LOSS, WIN);
     }
 }
 
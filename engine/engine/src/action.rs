@@ -115,15 +115,42 @@ impl Action {
         }
     }
 
+    /// Checks that an action is legal for the turn player. Booming any allied robot is legal even
+    /// though only booms next to opponents are generated.
+    pub fn legal(bitboard: &Bitboard, action: &Action) -> bool {
+        match action.robots {
+            0 => bitboard.board[bitboard.turn] & Bitwise::pos(action.source) != 0,
+            _ => Action::generate(bitboard, None).iter().any(|scored| scored.action == *action),
+        }
+    }
+
     pub fn generate(bitboard: &Bitboard, pv_move: Option<Action>) -> ArrayVec<[ScoredAction; 256]> {
         let mut actions = ArrayVec::<[ScoredAction; 256]>::new();
 
-        Action::generate_booms(&bitboard, &mut actions);
+        // Find all possible boom locations that could provide net value. This only occurs when the
+        // boom source is touching an enemy robot.
+        let sources =
+            bitboard.board[bitboard.turn] & Bitwise::adj_any(bitboard.board[bitboard.opponent]);
+        Action::generate_booms(sources, &mut actions);
         for height in (1..=12).rev() {
-            Action::generate_for_height(&bitboard, height, &mut actions);
+            Action::generate_for_height(bitboard, height, &mut actions);
         }
 
-        Action::sort(&bitboard, &mut actions, pv_move);
+        Action::sort(bitboard, &mut actions, pv_move);
+
+        actions
+    }
+
+    /// Generates every legal action, including the booms that only take allied robots with them
+    /// which generate leaves out. Booming an allied robot can still chain into opponents, so this is
+    /// needed wherever every action has to be considered, such as when solving endgames exactly.
+    pub fn generate_all(bitboard: &Bitboard) -> ArrayVec<[ScoredAction; 256]> {
+        let mut actions = ArrayVec::<[ScoredAction; 256]>::new();
+
+        Action::generate_booms(bitboard.board[bitboard.turn], &mut actions);
+        for height in (1..=12).rev() {
+            Action::generate_for_height(bitboard, height, &mut actions);
+        }
 
         actions
     }
@@ -174,7 +201,7 @@ impl Action {
     ) {
         actions.iter_mut().for_each(|scored_action| {
             // Score depending on the direction and distance moved
-            match Action::direction(&bitboard, &scored_action.action) {
+            match Action::direction(bitboard, &scored_action.action) {
                 Direction::Forward => {
                     scored_action.score += 15 * Action::distance_sideways(&scored_action.action)
                 }
@@ -185,7 +212,7 @@ impl Action {
             }
 
             // Score depending on stacking
-            scored_action.score += 10 * Action::stacking(&bitboard, &scored_action.action);
+            scored_action.score += 10 * Action::stacking(bitboard, &scored_action.action);
 
             // Score depending on booming
             if Action::booming(&scored_action.action) {
@@ -193,7 +220,7 @@ impl Action {
             }
 
             // Score depending on checking
-            if Action::checking(&bitboard, &scored_action.action) {
+            if Action::checking(bitboard, &scored_action.action) {
                 scored_action.score += 300;
             }
 
@@ -207,23 +234,13 @@ impl Action {
         actions.sort_unstable_by(|a, b| b.cmp(a));
     }
 
-    /// Generates all good booms.
-    // NOTE: Only considers booms which could have a positive net value to the turn player.
-    // This is decided by ensuring that the boom takes at least 1 enemy robot with it and
-    // calculates this by checking for adjacent enemy robots to the source.
+    /// Generates a boom for each of the sources.
     // NOTE: Multiple booms which could result in the same gamestate, could be eliminated by calculating islands within
     // possible boom sources, but hopefully the TT will eliminate us having to
     // search these branches (unless the TT gets overwritten).
     // NOTE: It might be smart to value booms higher in the TT as their might often be multiple
     // booms which lead the same game state, and thus reducing lots of branching.
-    fn generate_booms(bitboard: &Bitboard, generated: &mut ArrayVec<[ScoredAction; 256]>) {
-        let bots = bitboard.board[bitboard.turn];
-        let opps = bitboard.board[bitboard.opponent];
-
-        // Find all possible boom locations that could provide net value. This only occurs when the
-        // boom source is touching an enemy robot.
-        let mut sources = bots & Bitwise::adj_any(opps);
-
+    fn generate_booms(mut sources: u64, generated: &mut ArrayVec<[ScoredAction; 256]>) {
         // We could compute the boom islands to elimite booms that would lead to the same game
         // state but hopefully the transition table will solve this issue for us.
 
@@ -262,9 +279,9 @@ impl Action {
             let source = Bitwise::lsb(bots);
             bots ^= source;
 
-            // Generate all possible target squares using a lookup table
+            // Generate all target squares that are within reach and not blocked by other robots
             let source_pos = Bitwise::idx(source);
-            let mut actions = MOVES_LOOKUP[height][source_pos];
+            let mut actions = Action::targets(bitboard, source_pos, height);
             // Remove all invalid positions to move to which are those occupied by opponents robots
             actions &= !bitboard.board[bitboard.opponent];
 
@@ -292,6 +309,30 @@ impl Action {
         }
     }
 
+    /// Finds the squares a stack can reach by sliding along each ray from the source. Robots can
+    /// never be jumped over, so each ray stops at its first occupied square, which is included so
+    /// that stacks can still land on allied robots.
+    pub fn targets(bitboard: &Bitboard, source_pos: usize, height: usize) -> u64 {
+        let occupied = bitboard.board[WHITE] | bitboard.board[BLACK];
+        let reach = MOVES_LOOKUP[height][source_pos];
+        let mut targets = 0;
+        for (direction, rays) in RAYS_LOOKUP.iter().enumerate() {
+            let ray = rays[source_pos] & reach;
+            let blockers = ray & occupied;
+            targets |= match (blockers, direction) {
+                (0, _) => ray,
+                // Rays towards higher squares are blocked by their lowest occupied square
+                (_, RAY_RANK_UP | RAY_FILE_UP) => {
+                    let blocker = Bitwise::lsb(blockers);
+                    ray & (blocker | (blocker - 1))
+                }
+                // And rays towards lower squares are blocked by their highest occupied square
+                _ => ray & !(Bitwise::msb(blockers) - 1),
+            };
+        }
+        targets
+    }
+
     fn is_attack(target_adj: u64, enemies_unattacked: u64) -> bool {
         (target_adj & enemies_unattacked) != 0
     }
@@ -304,3 +345,43 @@ impl Action {
         bitboard.board[bitboard.turn] & target != 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn robots_block_moves() {
+        let bitboard = Format::parse("8/8/8/8/8/8/A7/Ba6 w").unwrap();
+        let mut targets: Vec<String> = Action::generate(&bitboard, None)
+            .iter()
+            .filter(|scored| scored.action.source == 0 && scored.action.robots != 0)
+            .map(|scored| Format::action(&scored.action))
+            .collect();
+        targets.sort();
+        assert_eq!(targets, ["a1a2/1", "a1a2/2"]);
+    }
+
+    #[test]
+    fn open_rays_reach_the_full_height() {
+        let bitboard = Format::parse("8/8/8/8/3C4/8/8/8 w").unwrap();
+        let source = Format::parse_square("d4").unwrap();
+        let moves = Action::generate(&bitboard, None);
+        let targets = moves.iter().filter(|scored| scored.action.source == source);
+        assert_eq!(targets.count(), 12 * 3);
+    }
+
+    #[test]
+    fn every_boom_is_generated_for_endgames() {
+        // Only the robot touching the opponent is worth booming in search
+        let bitboard = Format::parse("8/8/8/8/8/A7/8/Ba5A w").unwrap();
+        let booms = |actions: ArrayVec<[ScoredAction; 256]>| {
+            actions.iter().filter(|scored| scored.action.robots == 0).count()
+        };
+        assert_eq!(booms(Action::generate(&bitboard, None)), 1);
+        assert_eq!(booms(Action::generate_all(&bitboard)), 3);
+        assert!(Action::generate_all(&bitboard)
+            .iter()
+            .all(|scored| Action::legal(&bitboard, &scored.action)));
+    }
+}
@@ -1,6 +1,8 @@
 use crate::action::Action;
 use crate::bitwise::Bitwise;
 use crate::constants::*;
+use crate::symmetry::Symmetry;
+use boombots_rules::boom;
 
 #[derive(Debug)]
 pub struct DeltaDirectional {
@@ -24,7 +26,7 @@ pub enum Delta {
     Directional(DeltaDirectional),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Bitboard {
     pub hash: u64,
     pub turn: usize,
@@ -141,7 +143,8 @@ impl Bitboard {
     }
 
     fn delta_explosion(&self, action: Action) -> DeltaExplosion {
-        let explosion = Bitwise::dfs(self.board[WHITE] | self.board[BLACK], action.source);
+        let explosion =
+            boom::flood_fill(self.board[WHITE] | self.board[BLACK], Bitwise::pos(action.source));
         DeltaExplosion {
             board: [
                 explosion & self.board[WHITE],
@@ -184,13 +187,13 @@ impl Bitboard {
         let mut hash_delta: u64 = 0;
 
         // Calculate for the change in height board frames
-        for height in 1..=12 {
+        for (height, keys) in ZORBIST_KEY.iter().enumerate().take(13).skip(1) {
             let mut bots = delta.board[height];
             while bots != 0 {
                 let bot = Bitwise::lsb(bots);
                 bots ^= bot;
                 let pos = Bitwise::idx(bot);
-                hash_delta ^= ZORBIST_KEY[height][pos];
+                hash_delta ^= keys[pos];
             }
         }
         // Calculate for the change in colour board frames
@@ -220,13 +223,23 @@ impl Bitboard {
         self.update_robot_counts();
     }
 
-    fn update_robot_counts(&mut self) {}
+    /// Recounts the robots of each team after robots have been removed or restored. The material
+    /// term of the evaluation and the outcome of the game are read from these counts.
+    fn update_robot_counts(&mut self) {
+        let mut white = 0;
+        let mut black = 0;
+        for (height, frame) in self.board.iter().enumerate().take(13).skip(1) {
+            white += height as i16 * Bitwise::pcnt(frame & self.board[WHITE]) as i16;
+            black += height as i16 * Bitwise::pcnt(frame & self.board[BLACK]) as i16;
+        }
+        self.robots_white = white;
+        self.robots_black = black;
+        self.robots_total = white + black;
+    }
 
     // Toggles the turn player
     fn toggle_turn(&mut self) {
-        let previous = self.turn;
-        self.turn = self.opponent;
-        self.opponent = previous;
+        std::mem::swap(&mut self.turn, &mut self.opponent);
         self.hash ^= ZORBIST_TURN;
     }
 
@@ -290,7 +303,7 @@ impl Bitboard {
             board: [0; 14],
             turn: WHITE,
             opponent: BLACK,
-            hash: ZORBIST_INITIAL,
+            hash: ZORBIST_TURN,
             robots_white: 0,
             robots_black: 0,
             robots_total: 0,
@@ -300,11 +313,59 @@ impl Bitboard {
     pub fn with(mut self, pos: u64, height: usize, team: usize) -> Self {
         self.board[team] |= pos;
         self.board[height] |= pos;
-        self.hash ^= ZORBIST_KEY[team][Bitwise::idx(pos) as usize];
-        self.robots_white += if team == WHITE { 1 } else { 0 };
-        self.robots_black += if team == BLACK { 1 } else { 0 };
+        self.hash ^= ZORBIST_KEY[team][Bitwise::idx(pos)];
+        self.hash ^= ZORBIST_KEY[height][Bitwise::idx(pos)];
+        self.update_robot_counts();
+        self
+    }
+
+    /// Sets the turn player, updating the hash if the turn changes
+    pub fn with_turn(mut self, turn: usize) -> Self {
+        if self.turn != turn {
+            self.toggle_turn();
+        }
         self
     }
+
+    /// Mirrors the position from left to right
+    pub fn mirror(&self) -> Bitboard {
+        Symmetry::Mirror.bitboard(self)
+    }
+
+    /// Flips the position from top to bottom, swapping the colours of every robot and the turn
+    pub fn flip(&self) -> Bitboard {
+        Symmetry::Flip.bitboard(self)
+    }
+
+    /// Finds the smallest hash among the positions symmetric to this one, along with the symmetry
+    /// that transforms this position into it, so that symmetric positions share a single hash
+    pub fn canonical(&self) -> (u64, Symmetry) {
+        Symmetry::ALL
+            .into_iter()
+            .map(|symmetry| (symmetry.hash(self), symmetry))
+            .min_by_key(|(hash, _)| *hash)
+            .expect("There is always the identity")
+    }
+
+    /// Computes the zorbist hash of the bitboard from scratch rather than incrementally
+    pub fn zorbist(&self) -> u64 {
+        let mut hash = if self.turn == WHITE { ZORBIST_TURN } else { 0 };
+        for (frame, keys) in self.board.iter().zip(ZORBIST_KEY.iter()) {
+            let mut bots = *frame;
+            while bots != 0 {
+                let bot = Bitwise::lsb(bots);
+                bots ^= bot;
+                hash ^= keys[Bitwise::idx(bot)];
+            }
+        }
+        hash
+    }
+}
+
+impl Default for Bitboard {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -313,10 +374,57 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn incremental_hash_matches_zorbist() {
+        let mut board = Bitboard::new();
+        assert_eq!(board.hash, board.zorbist());
+        for action in [
+            Action { source: 0, target: 1, robots: 1 },
+            Action { source: 48, target: 40, robots: 1 },
+            Action { source: 1, target: 25, robots: 2 },
+            Action { source: 40, target: 0, robots: 0 },
+        ] {
+            let delta = board.delta(action);
+            board.make(&delta);
+            assert_eq!(board.hash, board.zorbist());
+        }
+    }
+
+    #[test]
+    fn robot_counts_follow_booms_and_undos() {
+        let mut board = Format::parse("8/8/8/3a4/3A4/8/8/A7 w").unwrap();
+        let counts = |board: &Bitboard| (board.robots_white, board.robots_black);
+        assert_eq!(counts(&board), (2, 1));
+        for scored in Action::generate(&board.clone(), None) {
+            let delta = board.delta(scored.action);
+            let hash = board.make(&delta);
+            let (white, black) = counts(&board);
+            if scored.action.robots == 0 {
+                assert!(white + black < 3);
+            }
+            board.undo(&delta, hash);
+            assert_eq!(counts(&board), (2, 1));
+        }
+    }
+
+    #[test]
+    fn symmetric_positions_share_a_canonical_hash() {
+        let board = Bitboard::new();
+        assert_eq!(board.mirror(), board);
+        assert_eq!(board.flip().flip(), board);
+
+        let action = Action { source: 0, target: 8, robots: 1 };
+        let mut moved = board.clone();
+        moved.make(&moved.delta(action));
+        let (hash, _) = moved.canonical();
+        assert_eq!(moved.mirror().canonical().0, hash);
+        assert_eq!(moved.flip().canonical().0, hash);
+        assert_eq!(moved.flip().mirror().canonical().0, hash);
+    }
+
     #[test]
     fn undo_after_stacking_height_1_onto_1() {
         let mut board = Bitboard::new();
-        let ihash = board.hash;
         let action = Action { source: 0, target: 1, robots: 1 };
         let delta = board.delta(action);
         let hash = board.make(&delta);
@@ -327,7 +435,6 @@ mod tests {
     #[test]
     fn undo_after_moving_height_1_onto_empty() {
         let mut board = Bitboard::new();
-        let ihash = board.hash;
         let action = Action { source: 1, target: 2, robots: 1 };
         let delta = board.delta(action);
         let hash = board.make(&delta);
@@ -341,7 +448,7 @@ mod tests {
         let mut board = Bitboard::new();
         let action1 = Action { source: 0, target: 1, robots: 1 };
         let delta1 = board.delta(action1);
-        let hash1 = board.make(&delta1);
+        board.make(&delta1);
         let action2 = Action { source: 1, target: 2, robots: 1 };
         let delta2 = board.delta(action2);
         let hash2 = board.make(&delta2);
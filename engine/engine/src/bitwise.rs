@@ -16,10 +16,11 @@ impl Bitwise {
     pub fn pcnt(frame: u64) -> u64 {
         let frame = frame - ((frame >> 1) & KNUTH_K1);
         let frame = (frame & KNUTH_K2) + (frame >> 2 & KNUTH_K2);
-        ((frame + (frame >> 4)) & KNUTH_K4) * KNUTH_KF >> 56
+        ((frame + (frame >> 4)) & KNUTH_K4).wrapping_mul(KNUTH_KF) >> 56
     }
 
-    /// Finds all set bits that are recursively adjacent to the source using a dfs
+    /// Finds all set bits that are recursively adjacent to the source using a dfs. Explosions use
+    /// the flood fill shared with the core rules instead, and this is kept to benchmark against.
     pub fn dfs(frame: u64, source: u8) -> u64 {
         let mut mask: u64 = 0;
         let mut explore: u64 = 1 << source;
@@ -40,11 +41,31 @@ impl Bitwise {
         mask
     }
 
+    /// Mirrors the frame from left to right by reversing the bits within each rank
+    pub fn flip_file(frame: u64) -> u64 {
+        let frame = ((frame >> 1) & KNUTH_K1) | ((frame & KNUTH_K1) << 1);
+        let frame = ((frame >> 2) & KNUTH_K2) | ((frame & KNUTH_K2) << 2);
+        ((frame >> 4) & KNUTH_K4) | ((frame & KNUTH_K4) << 4)
+    }
+
+    /// Mirrors the frame from top to bottom by reversing the order of the ranks
+    pub fn flip_rank(frame: u64) -> u64 {
+        frame.swap_bytes()
+    }
+
     /// Finds the least significant set bit
     pub fn lsb(frame: u64) -> u64 {
         frame ^ (frame & (frame - 1))
     }
 
+    /// Finds the most significant set bit
+    pub fn msb(frame: u64) -> u64 {
+        match frame {
+            0 => 0,
+            _ => 1 << (63 - frame.leading_zeros()),
+        }
+    }
+
     /// Finds the 0-indexed position of the only set bit using DeBruijn's multiply and lookup method
     pub fn idx(frame: u64) -> usize {
         DEBRUIJN_LOOKUP[((DEBRUIJN_MULTIPLY.wrapping_mul(frame & ((!frame) + 1))) >> 58) as usize]
@@ -86,4 +107,12 @@ mod tests {
             assert_eq!(Bitwise::idx(1 << i), i, "Bitwise index of {} should be {}", 2 << i, i);
         }
     }
+
+    #[test]
+    fn flips() {
+        assert_eq!(Bitwise::flip_file(BOARD_ROW0 & 0x0f), 0xf0);
+        assert_eq!(Bitwise::flip_file(0x0000000000000102), 0x0000000000008040);
+        assert_eq!(Bitwise::flip_rank(BOARD_ROW0), BOARD_ROW7);
+        assert_eq!(Bitwise::flip_rank(0x0000000000000102), 0x0201000000000000);
+    }
 }
@@ -0,0 +1,193 @@
+use crate::action::Action;
+use crate::bitboard::Bitboard;
+use crate::random::Rng;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+/// The size of each entry on disk, which is the big-endian hash followed by the action source,
+/// target and robots, a padding byte and the big-endian weight
+const ENTRY_SIZE: usize = 16;
+
+/// A weighted action stored in the opening book for the position with the hash
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BookEntry {
+    pub hash: u64,
+    pub action: Action,
+    pub weight: u32,
+}
+
+/// An opening book of weighted actions keyed by the canonical hash of each position, so that
+/// symmetric positions share their entries. Entries are kept sorted by hash so that probing is a
+/// binary search and the book can be written to disk as a flat list of fixed size entries.
+#[derive(Debug, Default, PartialEq)]
+pub struct Book {
+    entries: Vec<BookEntry>,
+}
+
+impl Book {
+    pub fn new() -> Book {
+        Book { entries: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds weight to an action for the position, creating the entry if it is new
+    pub fn add(&mut self, bitboard: &Bitboard, action: Action, weight: u32) {
+        let (hash, symmetry) = bitboard.canonical();
+        let action = symmetry.action(&action);
+        let start = self.entries.partition_point(|entry| entry.hash < hash);
+        let end = self.entries.partition_point(|entry| entry.hash <= hash);
+        match self.entries[start..end].iter_mut().find(|entry| entry.action == action) {
+            Some(entry) => entry.weight = entry.weight.saturating_add(weight),
+            None => self.entries.insert(end, BookEntry { hash, action, weight }),
+        }
+    }
+
+    /// Finds every entry for the canonical hash, with actions for the canonical position
+    pub fn probe(&self, hash: u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|entry| entry.hash < hash);
+        let end = self.entries.partition_point(|entry| entry.hash <= hash);
+        &self.entries[start..end]
+    }
+
+    /// Chooses a book action for the position. With no randomness the heaviest action is always
+    /// chosen, with a randomness of 1 actions are chosen in proportion to their weight, and higher
+    /// randomness flattens the weights further towards a uniform choice.
+    pub fn choose(&self, bitboard: &Bitboard, randomness: f64, rng: &mut Rng) -> Option<Action> {
+        let (hash, symmetry) = bitboard.canonical();
+        let entries = self.probe(hash);
+        let action = Book::pick(entries, randomness, rng)?;
+        Some(symmetry.action(&action))
+    }
+
+    // Picks one of the entries in proportion to their weight flattened by the randomness
+    fn pick(entries: &[BookEntry], randomness: f64, rng: &mut Rng) -> Option<Action> {
+        if randomness <= 0.0 {
+            return entries.iter().max_by_key(|entry| entry.weight).map(|entry| entry.action);
+        }
+
+        let weights: Vec<f64> =
+            entries.iter().map(|entry| (entry.weight as f64).powf(1.0 / randomness)).collect();
+        let mut choice = rng.unit() * weights.iter().sum::<f64>();
+        for (entry, weight) in entries.iter().zip(weights) {
+            if choice < weight {
+                return Some(entry.action);
+            }
+            choice -= weight;
+        }
+        entries.last().map(|entry| entry.action)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Book> {
+        let bytes = fs::read(path)?;
+        if bytes.len() % ENTRY_SIZE != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Book has a partial entry"));
+        }
+
+        let mut entries: Vec<BookEntry> = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|bytes| BookEntry {
+                hash: u64::from_be_bytes(bytes[0..8].try_into().expect("Hash is 8 bytes")),
+                action: Action { source: bytes[8], target: bytes[9], robots: bytes[10] },
+                weight: u32::from_be_bytes(bytes[12..16].try_into().expect("Weight is 4 bytes")),
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.hash);
+        Ok(Book { entries })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(self.entries.len() * ENTRY_SIZE);
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.hash.to_be_bytes());
+            bytes.extend_from_slice(&[
+                entry.action.source,
+                entry.action.target,
+                entry.action.robots,
+            ]);
+            bytes.push(0);
+            bytes.extend_from_slice(&entry.weight.to_be_bytes());
+        }
+        fs::write(path, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use std::env;
+
+    const A: Action = Action { source: 0, target: 8, robots: 1 };
+    const B: Action = Action { source: 1, target: 17, robots: 1 };
+
+    fn position(notation: &str) -> Bitboard {
+        Format::parse(notation).unwrap()
+    }
+
+    fn book() -> Book {
+        let start = Bitboard::new();
+        let mut book = Book::new();
+        book.add(&start, A, 1);
+        book.add(&position("8/8/8/8/8/8/8/AA6 w"), B, 5);
+        book.add(&start, B, 10);
+        book.add(&start, A, 2);
+        book
+    }
+
+    #[test]
+    fn entries_accumulate_weight() {
+        let book = book();
+        assert_eq!(book.len(), 3);
+        let (hash, symmetry) = Bitboard::new().canonical();
+        let mut entries: Vec<(Action, u32)> = book
+            .probe(hash)
+            .iter()
+            .map(|entry| (symmetry.action(&entry.action), entry.weight))
+            .collect();
+        entries.sort_by_key(|(_, weight)| *weight);
+        assert_eq!(entries, [(A, 3), (B, 10)]);
+        assert!(book.probe(Bitboard::empty().hash).is_empty());
+    }
+
+    #[test]
+    fn choose_without_randomness_is_heaviest() {
+        let mut rng = Rng::new(1);
+        assert_eq!(book().choose(&Bitboard::new(), 0.0, &mut rng), Some(B));
+        assert_eq!(book().choose(&Bitboard::empty(), 1.0, &mut rng), None);
+    }
+
+    #[test]
+    fn choose_with_randomness_picks_every_action() {
+        let book = book();
+        let mut rng = Rng::new(1);
+        let choices: Vec<Action> =
+            (0..100).filter_map(|_| book.choose(&Bitboard::new(), 1.0, &mut rng)).collect();
+        assert!(choices.contains(&A));
+        assert!(choices.contains(&B));
+    }
+
+    #[test]
+    fn symmetric_positions_share_entries() {
+        let book = book();
+        let mut rng = Rng::new(1);
+        let mirrored = position("8/8/8/8/8/8/8/6AA w");
+        let action = book.choose(&mirrored, 0.0, &mut rng).unwrap();
+        assert_eq!(Format::action(&action), "g1g3/1");
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join("boombots-book-test.bin");
+        book().save(&path).unwrap();
+        assert_eq!(Book::load(&path).unwrap(), book());
+        fs::remove_file(path).unwrap();
+    }
+}
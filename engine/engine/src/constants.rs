@@ -9,6 +9,9 @@ pub const WIN: i16 = 10000;
 pub const LOSS: i16 = -10000;
 pub const DRAW: i16 = 0;
 
+// Search
+pub const MAX_DEPTH: u8 = 64;
+
 // Popcount using Donald Knuth's method
 pub const KNUTH_K1: u64 = 0x5555555555555555;
 pub const KNUTH_K2: u64 = 0x3333333333333333;
@@ -48,7 +51,31 @@ pub const ZORBIST_INITIAL: u64 = ZORBIST_TURN
     ^ ZORBIST_KEY[BLACK][59]
     ^ ZORBIST_KEY[BLACK][60]
     ^ ZORBIST_KEY[BLACK][62]
-    ^ ZORBIST_KEY[BLACK][63];
+    ^ ZORBIST_KEY[BLACK][63]
+    ^ ZORBIST_KEY[1][0]
+    ^ ZORBIST_KEY[1][1]
+    ^ ZORBIST_KEY[1][3]
+    ^ ZORBIST_KEY[1][4]
+    ^ ZORBIST_KEY[1][6]
+    ^ ZORBIST_KEY[1][7]
+    ^ ZORBIST_KEY[1][8]
+    ^ ZORBIST_KEY[1][9]
+    ^ ZORBIST_KEY[1][11]
+    ^ ZORBIST_KEY[1][12]
+    ^ ZORBIST_KEY[1][14]
+    ^ ZORBIST_KEY[1][15]
+    ^ ZORBIST_KEY[1][48]
+    ^ ZORBIST_KEY[1][49]
+    ^ ZORBIST_KEY[1][51]
+    ^ ZORBIST_KEY[1][52]
+    ^ ZORBIST_KEY[1][54]
+    ^ ZORBIST_KEY[1][55]
+    ^ ZORBIST_KEY[1][56]
+    ^ ZORBIST_KEY[1][57]
+    ^ ZORBIST_KEY[1][59]
+    ^ ZORBIST_KEY[1][60]
+    ^ ZORBIST_KEY[1][62]
+    ^ ZORBIST_KEY[1][63];
 pub const ZORBIST_KEY: [[u64; 64]; 14] = [
     [
         5935439724887331764,
@@ -221,14 +248,15 @@ pub const BOARD_ROW4: u64 = 0x000000ff00000000;
 pub const BOARD_ROW5: u64 = 0x0000ff0000000000;
 pub const BOARD_ROW6: u64 = 0x00ff000000000000;
 pub const BOARD_ROW7: u64 = 0xff00000000000000;
-pub const BOARD_COL0: u64 = 0x8080808080808080;
-pub const BOARD_COL1: u64 = 0x4040404040404040;
-pub const BOARD_COL2: u64 = 0x2020202020202020;
-pub const BOARD_COL3: u64 = 0x1010101010101010;
-pub const BOARD_COL4: u64 = 0x0808080808080808;
-pub const BOARD_COL5: u64 = 0x0404040404040404;
-pub const BOARD_COL6: u64 = 0x0202020202020202;
-pub const BOARD_COL7: u64 = 0x0101010101010101;
+pub const BOARD_COL0: u64 = 0x0101010101010101;
+pub const BOARD_COL1: u64 = 0x0202020202020202;
+pub const BOARD_COL2: u64 = 0x0404040404040404;
+pub const BOARD_COL3: u64 = 0x0808080808080808;
+pub const BOARD_COL4: u64 = 0x1010101010101010;
+pub const BOARD_COL5: u64 = 0x2020202020202020;
+pub const BOARD_COL6: u64 = 0x4040404040404040;
+pub const BOARD_COL7: u64 = 0x8080808080808080;
+pub const BOARD_CENTRE: u64 = 0x00003c3c3c3c0000;
 
 // Precomputed adjacent square lookup table
 pub const ADJACENT_LOOKUP: [u64; 64] = [
@@ -1098,3 +1126,35 @@ pub const MOVES_LOOKUP: [[u64; 64]; 13] = [
         9187484529235886208,
     ],
 ];
+
+// Precomputed ray lookup tables of every square in a straight line from each square, towards
+// increasing rank, increasing file, decreasing rank and decreasing file
+pub const RAY_RANK_UP: usize = 0;
+pub const RAY_FILE_UP: usize = 1;
+pub const RAY_RANK_DOWN: usize = 2;
+pub const RAY_FILE_DOWN: usize = 3;
+pub const RAYS_LOOKUP: [[u64; 64]; 4] = {
+    let mut rays = [[0; 64]; 4];
+    let mut square = 0;
+    while square < 64 {
+        let (file, rank) = (square % 8, square / 8);
+        let mut i = 1;
+        while i < 8 {
+            if rank + i < 8 {
+                rays[RAY_RANK_UP][square] |= 1 << (square + 8 * i);
+            }
+            if file + i < 8 {
+                rays[RAY_FILE_UP][square] |= 1 << (square + i);
+            }
+            if rank >= i {
+                rays[RAY_RANK_DOWN][square] |= 1 << (square - 8 * i);
+            }
+            if file >= i {
+                rays[RAY_FILE_DOWN][square] |= 1 << (square - i);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    rays
+};
@@ -1,6 +1,14 @@
+use crate::action::Action;
 use crate::bitboard::Bitboard;
 use crate::bitwise::Bitwise;
 use crate::constants::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A single evaluation term computed relative to the turn player
+type Term = fn(&Bitboard) -> i32;
 
 #[derive(Debug)]
 pub enum Outcome {
@@ -9,10 +17,112 @@ pub enum Outcome {
     Draw,
 }
 
+/// Scores a bitboard from the perspective of the turn player
+pub trait Evaluator {
+    fn evaluate(&self, bitboard: &Bitboard) -> i16;
+}
+
+/// The weights applied to each evaluation term. A weight of 0 disables the term entirely so that
+/// unused terms cost nothing during search.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvalParams {
+    /// Robots remaining
+    pub robots: i16,
+    /// Squares adjacent to at least one robot
+    pub surface_area: i16,
+    /// Squared height of each stack up to a height of 8
+    pub stack_power: i16,
+    /// Squares that stacks could move to
+    pub mobility: i16,
+    /// Robots in stacks that are adjacent to an opponents robot
+    pub threatened: i16,
+    /// Robots in the centre 4x4 of the board
+    pub centralisation: i16,
+    /// Stacks with no adjacent allied stacks
+    pub isolated: i16,
+    /// Stacks with at least one adjacent allied stack
+    pub clustered: i16,
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self {
+            robots: 1000,
+            surface_area: 1,
+            stack_power: 1,
+            mobility: 0,
+            threatened: 0,
+            centralisation: 0,
+            isolated: 0,
+            clustered: 0,
+        }
+    }
+}
+
+impl EvalParams {
+    /// The names of each weight, in the order used by `weights` and `from_weights`
+    pub const NAMES: [&'static str; 8] = [
+        "robots",
+        "surface_area",
+        "stack_power",
+        "mobility",
+        "threatened",
+        "centralisation",
+        "isolated",
+        "clustered",
+    ];
+
+    /// Lists every weight so that they can be adjusted generically, such as when tuning
+    pub fn weights(&self) -> [i16; 8] {
+        [
+            self.robots,
+            self.surface_area,
+            self.stack_power,
+            self.mobility,
+            self.threatened,
+            self.centralisation,
+            self.isolated,
+            self.clustered,
+        ]
+    }
+
+    /// Creates evaluation parameters from a list of weights in the same order as `weights`
+    pub fn from_weights(weights: [i16; 8]) -> EvalParams {
+        let [robots, surface_area, stack_power, mobility, threatened, centralisation, isolated, clustered] =
+            weights;
+        EvalParams {
+            robots,
+            surface_area,
+            stack_power,
+            mobility,
+            threatened,
+            centralisation,
+            isolated,
+            clustered,
+        }
+    }
+
+    /// Loads evaluation parameters from a json file, where any missing weights use their defaults
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<EvalParams> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Saves the evaluation parameters to a json file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+    }
+}
+
 impl Evaluate {
-    fn surface_area(bitboard: &Bitboard) -> i16 {
-        let white = Bitwise::pcnt(Bitwise::adj_any(bitboard.board[WHITE])) as i16;
-        let black = Bitwise::pcnt(Bitwise::adj_any(bitboard.board[BLACK])) as i16;
+    pub fn new(params: EvalParams) -> Self {
+        Self { params }
+    }
+
+    /// Converts a pair of white and black term values into a value relative to the turn player
+    fn relative(bitboard: &Bitboard, white: i32, black: i32) -> i32 {
         match bitboard.turn {
             WHITE => white - black,
             BLACK => black - white,
@@ -20,7 +130,22 @@ impl Evaluate {
         }
     }
 
-    fn stack_power(bitboard: &Bitboard) -> i16 {
+    /// Counts the robots in all stacks within the mask
+    fn robots_within(bitboard: &Bitboard, mask: u64) -> i32 {
+        let mut robots = 0;
+        for (height, frame) in bitboard.board.iter().enumerate().take(13).skip(1) {
+            robots += height as i32 * Bitwise::pcnt(frame & mask) as i32;
+        }
+        robots
+    }
+
+    fn surface_area(bitboard: &Bitboard) -> i32 {
+        let white = Bitwise::pcnt(Bitwise::adj_any(bitboard.board[WHITE])) as i32;
+        let black = Bitwise::pcnt(Bitwise::adj_any(bitboard.board[BLACK])) as i32;
+        Evaluate::relative(bitboard, white, black)
+    }
+
+    fn stack_power(bitboard: &Bitboard) -> i32 {
         let mut white = 0;
         let mut black = 0;
 
@@ -33,38 +158,84 @@ impl Evaluate {
             }
         }
 
-        match bitboard.turn {
-            WHITE => white as i16 - black as i16,
-            BLACK => black as i16 - white as i16,
-            _ => panic!("Never reaches here"),
-        }
+        Evaluate::relative(bitboard, white as i32, black as i32)
     }
 
-    fn robots(bitboard: &Bitboard) -> i16 {
-        match bitboard.turn {
-            WHITE => 1000 * (bitboard.robots_white - bitboard.robots_black),
-            BLACK => 1000 * (bitboard.robots_black - bitboard.robots_white),
-            _ => panic!("Never reaches here"),
-        }
+    fn robots(bitboard: &Bitboard) -> i32 {
+        Evaluate::relative(bitboard, bitboard.robots_white as i32, bitboard.robots_black as i32)
     }
 
-    fn constrain(score: i16) -> i16 {
-        if score > MAX {
-            MAX
-        } else if score < MIN {
-            MIN
-        } else {
-            score
+    /// Counts the squares reachable by every stack of a team, ignoring how many robots are moved.
+    /// Rays stop at the first robot in the way, the same as in move generation.
+    fn reach(bitboard: &Bitboard, team: usize, opponent: usize) -> i32 {
+        let mut reach = 0;
+        for height in 1..MOVES_LOOKUP.len() {
+            let mut bots = bitboard.board[height] & bitboard.board[team];
+            while bots != 0 {
+                let bot = Bitwise::lsb(bots);
+                bots ^= bot;
+                let targets =
+                    Action::targets(bitboard, Bitwise::idx(bot), height) & !bitboard.board[opponent];
+                reach += Bitwise::pcnt(targets) as i32;
+            }
         }
+        reach
+    }
+
+    fn mobility(bitboard: &Bitboard) -> i32 {
+        let white = Evaluate::reach(bitboard, WHITE, BLACK);
+        let black = Evaluate::reach(bitboard, BLACK, WHITE);
+        Evaluate::relative(bitboard, white, black)
+    }
+
+    fn threatened(bitboard: &Bitboard) -> i32 {
+        let whites = bitboard.board[WHITE] & Bitwise::adj_any(bitboard.board[BLACK]);
+        let blacks = bitboard.board[BLACK] & Bitwise::adj_any(bitboard.board[WHITE]);
+        let white = Evaluate::robots_within(bitboard, whites);
+        let black = Evaluate::robots_within(bitboard, blacks);
+        Evaluate::relative(bitboard, white, black)
     }
 
-    /// Evaluates the material advantage of the bitboard
-    pub fn evaluate(bitboard: &Bitboard) -> i16 {
-        Evaluate::constrain(
-            Evaluate::robots(&bitboard)
-                + Evaluate::surface_area(&bitboard)
-                + Evaluate::stack_power(&bitboard),
-        )
+    fn centralisation(bitboard: &Bitboard) -> i32 {
+        let white = Evaluate::robots_within(bitboard, bitboard.board[WHITE] & BOARD_CENTRE);
+        let black = Evaluate::robots_within(bitboard, bitboard.board[BLACK] & BOARD_CENTRE);
+        Evaluate::relative(bitboard, white, black)
+    }
+
+    fn isolated(bitboard: &Bitboard) -> i32 {
+        let whites = bitboard.board[WHITE] & !Bitwise::adj_any(bitboard.board[WHITE]);
+        let blacks = bitboard.board[BLACK] & !Bitwise::adj_any(bitboard.board[BLACK]);
+        Evaluate::relative(bitboard, Bitwise::pcnt(whites) as i32, Bitwise::pcnt(blacks) as i32)
+    }
+
+    fn clustered(bitboard: &Bitboard) -> i32 {
+        let whites = bitboard.board[WHITE] & Bitwise::adj_any(bitboard.board[WHITE]);
+        let blacks = bitboard.board[BLACK] & Bitwise::adj_any(bitboard.board[BLACK]);
+        Evaluate::relative(bitboard, Bitwise::pcnt(whites) as i32, Bitwise::pcnt(blacks) as i32)
+    }
+
+    /// Computes the weighted sum of every enabled evaluation term
+    fn weighted(&self, bitboard: &Bitboard) -> i32 {
+        let terms: [(i16, Term); 8] = [
+            (self.params.robots, Evaluate::robots),
+            (self.params.surface_area, Evaluate::surface_area),
+            (self.params.stack_power, Evaluate::stack_power),
+            (self.params.mobility, Evaluate::mobility),
+            (self.params.threatened, Evaluate::threatened),
+            (self.params.centralisation, Evaluate::centralisation),
+            (self.params.isolated, Evaluate::isolated),
+            (self.params.clustered, Evaluate::clustered),
+        ];
+
+        terms
+            .iter()
+            .filter(|(weight, _)| *weight != 0)
+            .map(|(weight, term)| *weight as i32 * term(bitboard))
+            .sum()
+    }
+
+    fn constrain(score: i32) -> i16 {
+        score.clamp(MIN as i32, MAX as i32) as i16
     }
 
     /// Evaluates the outcome of the bitboard
@@ -79,15 +250,51 @@ impl Evaluate {
             _ => None,
         }
     }
+}
 
-    /// Evaluates the outcome of the bitboard
-    /// TODO: Move this into bitboard
-    fn attacking(bitboard: &Bitboard) -> bool {
-        // Calculates white attacking black and black attacking white
-        (Bitwise::adj_any(bitboard.board[WHITE]) & bitboard.board[BLACK])
-            | (Bitwise::adj_any(bitboard.board[BLACK]) & bitboard.board[WHITE])
-            != 0
+impl Evaluator for Evaluate {
+    /// Evaluates the material and positional advantage of the bitboard
+    fn evaluate(&self, bitboard: &Bitboard) -> i16 {
+        Evaluate::constrain(self.weighted(bitboard))
     }
 }
 
-pub struct Evaluate {}
+#[derive(Debug, Default)]
+pub struct Evaluate {
+    pub params: EvalParams,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_is_balanced() {
+        let params = EvalParams {
+            mobility: 1,
+            threatened: 1,
+            centralisation: 1,
+            isolated: 1,
+            clustered: 1,
+            ..EvalParams::default()
+        };
+        assert_eq!(Evaluate::new(params).evaluate(&Bitboard::new()), 0);
+    }
+
+    #[test]
+    fn disabled_terms_are_ignored() {
+        let bitboard = Bitboard::empty().with(1 << 27, 1, WHITE).with(1 << 0, 1, BLACK);
+        let params =
+            EvalParams { robots: 0, surface_area: 0, stack_power: 0, ..EvalParams::default() };
+        assert_eq!(Evaluate::new(params).evaluate(&bitboard), 0);
+        let params = EvalParams { centralisation: 5, ..params };
+        assert_eq!(Evaluate::new(params).evaluate(&bitboard), 5);
+    }
+
+    #[test]
+    fn reach_stops_at_blocking_robots() {
+        // The stack of 3 in the corner slides freely along one ray but stops at its ally on the other
+        let bitboard = Bitboard::empty().with(1 << 0, 3, WHITE).with(1 << 1, 1, WHITE);
+        assert_eq!(Evaluate::reach(&bitboard, WHITE, BLACK), 4 + 3);
+    }
+}
@@ -1,4 +1,8 @@
+use crate::action::Action;
+use crate::bitboard::Bitboard;
+use crate::bitwise::Bitwise;
 use crate::constants::*;
+use boombots_rules::{notation, Position};
 
 pub struct Format {}
 
@@ -6,14 +10,85 @@ impl Format {
     pub fn frame(frame: u64, title: &str) {
         println!("{}", title);
         println!("╔════════╗");
-        println!("║{:08b}║", (frame & BOARD_ROW7) >> 56);
-        println!("║{:08b}║", (frame & BOARD_ROW6) >> 48);
-        println!("║{:08b}║", (frame & BOARD_ROW5) >> 40);
-        println!("║{:08b}║", (frame & BOARD_ROW4) >> 32);
-        println!("║{:08b}║", (frame & BOARD_ROW3) >> 24);
-        println!("║{:08b}║", (frame & BOARD_ROW2) >> 16);
-        println!("║{:08b}║", (frame & BOARD_ROW1) >> 8);
-        println!("║{:08b}║", (frame & BOARD_ROW0) >> 0);
+        // Ranks are printed from 8 down to 1 with the a file on the left, which is the lowest bit
+        for rank in (0..8).rev() {
+            println!("║{:08b}║", ((frame >> (8 * rank)) as u8).reverse_bits());
+        }
         println!("╚════════╝");
     }
+
+    /// Converts a bitboard into position notation, as described in the shared rules
+    pub fn notation(bitboard: &Bitboard) -> String {
+        Position::notation(bitboard)
+    }
+
+    /// Parses position notation into a bitboard, returning None if the notation is malformed
+    pub fn parse(notation: &str) -> Option<Bitboard> {
+        Bitboard::from_notation(notation)
+    }
+
+    /// Converts a square index into its name, such as "a1" for 0 or "h8" for 63
+    pub fn square(idx: u8) -> String {
+        notation::square(idx)
+    }
+
+    /// Parses a square name into its index
+    pub fn parse_square(square: &str) -> Option<u8> {
+        notation::parse_square(square)
+    }
+
+    /// Converts an action into action notation, such as "d4*" for a boom or "d4d6/2" for a move
+    pub fn action(action: &Action) -> String {
+        notation::action(&(*action).into())
+    }
+
+    /// Parses action notation into an action, returning None if the notation is malformed
+    pub fn parse_action(notation: &str) -> Option<Action> {
+        notation::parse_action(notation).map(Action::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "aa1aa1aa/aa1aa1aa/8/8/8/8/AA1AA1AA/AA1AA1AA w";
+
+    #[test]
+    fn notation_of_start() {
+        assert_eq!(Format::notation(&Bitboard::new()), START);
+        assert_eq!(Format::parse(START), Some(Bitboard::new()));
+    }
+
+    #[test]
+    fn notation_round_trips() {
+        let notation = "8/2l5/8/3a4/8/1D6/8/7A b";
+        let bitboard = Format::parse(notation).expect("Notation should parse");
+        assert_eq!(bitboard.robots_white, 5);
+        assert_eq!(bitboard.robots_black, 13);
+        assert_eq!(bitboard.hash, bitboard.zorbist());
+        assert_eq!(Format::notation(&bitboard), notation);
+    }
+
+    #[test]
+    fn malformed_notation() {
+        assert_eq!(Format::parse("8/8/8/8/8/8/8 w"), None);
+        assert_eq!(Format::parse("8/8/8/8/8/8/8/9 w"), None);
+        assert_eq!(Format::parse("8/8/8/8/8/8/8/AA7 w"), None);
+        assert_eq!(Format::parse("8/8/8/8/8/8/8/M7 w"), None);
+        assert_eq!(Format::parse("8/8/8/8/8/8/8/8 x"), None);
+    }
+
+    #[test]
+    fn action_round_trips() {
+        let boom = Action { source: 27, target: 0, robots: 0 };
+        let stack = Action { source: 0, target: 56, robots: 12 };
+        assert_eq!(Format::action(&boom), "d4*");
+        assert_eq!(Format::action(&stack), "a1a8/12");
+        assert_eq!(Format::parse_action("d4*"), Some(boom));
+        assert_eq!(Format::parse_action("a1a8/12"), Some(stack));
+        assert_eq!(Format::parse_action("a1a8/0"), None);
+        assert_eq!(Format::parse_action("a1i8/1"), None);
+        assert_eq!(Format::parse_action("a9*"), None);
+    }
 }
@@ -10,8 +10,15 @@ use crate::{
 pub mod action;
 pub mod bitboard;
 pub mod bitwise;
+pub mod book;
 pub mod constants;
 pub mod evaluate;
 pub mod format;
+pub mod position;
+pub mod random;
+pub mod record;
 pub mod search;
+pub mod symmetry;
+pub mod tablebase;
+pub mod tool;
 pub mod transpose;
@@ -0,0 +1,147 @@
+use crate::action::Action;
+use crate::bitboard::{Bitboard, Delta};
+use crate::bitwise::Bitwise;
+use crate::constants::*;
+use boombots_rules::{Outcome, Position, Stack, Team};
+
+impl From<Action> for boombots_rules::Action {
+    fn from(action: Action) -> Self {
+        boombots_rules::Action {
+            source: action.source,
+            target: action.target,
+            robots: action.robots,
+        }
+    }
+}
+
+impl From<boombots_rules::Action> for Action {
+    fn from(action: boombots_rules::Action) -> Self {
+        Action { source: action.source, target: action.target, robots: action.robots }
+    }
+}
+
+fn frame(team: Team) -> usize {
+    match team {
+        Team::White => WHITE,
+        Team::Black => BLACK,
+    }
+}
+
+impl Position for Bitboard {
+    type Undo = (Delta, u64);
+
+    fn turn(&self) -> Team {
+        match self.turn {
+            WHITE => Team::White,
+            _ => Team::Black,
+        }
+    }
+
+    fn stack(&self, square: u8) -> Option<Stack> {
+        let pos = Bitwise::pos(square);
+        let team = match (self.board[WHITE] & pos, self.board[BLACK] & pos) {
+            (0, 0) => return None,
+            (0, _) => Team::Black,
+            _ => Team::White,
+        };
+        Some(Stack { team, height: self.height(pos) })
+    }
+
+    fn from_stacks(stacks: &[Option<Stack>; 64], turn: Team) -> Self {
+        let bitboard = stacks.iter().enumerate().fold(
+            Bitboard::empty(),
+            |bitboard, (idx, stack)| match stack {
+                Some(stack) => {
+                    bitboard.with(Bitwise::pos(idx as u8), stack.height as usize, frame(stack.team))
+                }
+                None => bitboard,
+            },
+        );
+        bitboard.with_turn(frame(turn))
+    }
+
+    /// The engine only generates booms that reach an opponent, so the rest are added here
+    fn legal_actions(&self) -> Vec<boombots_rules::Action> {
+        let mut bots = self.board[self.turn];
+        let mut actions = Vec::new();
+        while bots != 0 {
+            let bot = Bitwise::lsb(bots);
+            bots ^= bot;
+            actions.push(boombots_rules::Action {
+                source: Bitwise::idx(bot) as u8,
+                target: 0,
+                robots: 0,
+            });
+        }
+        actions.extend(
+            Action::generate(self, None)
+                .iter()
+                .filter(|scored| scored.action.robots != 0)
+                .map(|scored| boombots_rules::Action::from(scored.action)),
+        );
+        actions
+    }
+
+    fn apply(&mut self, action: &boombots_rules::Action) -> Self::Undo {
+        let delta = self.delta(Action::from(*action));
+        let hash = self.make(&delta);
+        (delta, hash)
+    }
+
+    fn undo(&mut self, (delta, hash): Self::Undo) {
+        Bitboard::undo(self, &delta, hash);
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        match (self.robots_white, self.robots_black) {
+            (0, 0) => Some(Outcome::Draw),
+            (_, 0) => Some(Outcome::Winner(Team::White)),
+            (0, _) => Some(Outcome::Winner(Team::Black)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use boombots_core::GameState;
+    use boombots_rules::START;
+
+    fn sorted(mut actions: Vec<boombots_rules::Action>) -> Vec<boombots_rules::Action> {
+        actions.sort_by_key(|action| (action.source, action.target, action.robots));
+        actions
+    }
+
+    #[test]
+    fn start_positions_agree() {
+        assert_eq!(Bitboard::start(), Bitboard::new());
+        assert_eq!(Bitboard::new().notation(), START);
+        assert_eq!(GameState::start().notation(), START);
+        assert_eq!(GameState::new().notation(), START);
+    }
+
+    #[test]
+    fn engine_and_core_agree_on_random_games() {
+        let mut rng = Rng::new(7);
+        for _ in 0..20 {
+            let mut bitboard = Bitboard::start();
+            let mut state = GameState::start();
+            for _ in 0..200 {
+                if bitboard.outcome().is_some() {
+                    break;
+                }
+                assert_eq!(bitboard.notation(), state.notation());
+                let actions = sorted(bitboard.legal_actions());
+                assert_eq!(actions, sorted(state.legal_actions()));
+
+                let action = actions[rng.below(actions.len())];
+                bitboard.apply(&action);
+                state.apply(&action);
+            }
+            assert_eq!(bitboard.notation(), state.notation());
+            assert_eq!(bitboard.outcome(), state.outcome());
+        }
+    }
+}
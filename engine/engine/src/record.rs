@@ -0,0 +1,46 @@
+use crate::action::Action;
+use crate::bitboard::Bitboard;
+use crate::format::Format;
+
+/// A game played from an opening, stored on a single line as the opening position notation, the
+/// space separated action notation of every action played, and the result for white, separated by
+/// semicolons. For example "aa1aa1aa/aa1aa1aa/8/8/8/8/AA1AA1AA/AA1AA1AA w;b2b3/1 g7g6/1;0.5"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub opening: Bitboard,
+    pub actions: Vec<Action>,
+    /// The result for white, where 1 is a win, 0.5 a draw and 0 a loss
+    pub result: f64,
+}
+
+impl Record {
+    pub fn line(&self) -> String {
+        let actions: Vec<String> = self.actions.iter().map(Format::action).collect();
+        format!("{};{};{}", Format::notation(&self.opening), actions.join(" "), self.result)
+    }
+
+    /// Parses a record from a line, returning None if the line is malformed
+    pub fn parse(line: &str) -> Option<Record> {
+        let mut parts = line.split(';');
+        let opening = Format::parse(parts.next()?)?;
+        let actions =
+            parts.next()?.split_whitespace().map(Format::parse_action).collect::<Option<_>>()?;
+        let result = parts.next()?.trim().parse().ok()?;
+        Some(Record { opening, actions, result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trips() {
+        let line = "aa1aa1aa/aa1aa1aa/8/8/8/8/AA1AA1AA/AA1AA1AA w;b2b3/1 g7g6/1 b3*;1";
+        let record = Record::parse(line).expect("Record should parse");
+        assert_eq!(record.opening, Bitboard::new());
+        assert_eq!(record.actions.len(), 3);
+        assert_eq!(record.result, 1.0);
+        assert_eq!(record.line(), line);
+    }
+}
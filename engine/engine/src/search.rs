@@ -1,11 +1,17 @@
 use crate::action::Action;
 use crate::bitboard::Bitboard;
 use crate::bitwise::Bitwise;
+use crate::book::Book;
 use crate::constants::*;
-use crate::evaluate::{Evaluate, Outcome};
+use crate::evaluate::{Evaluate, Evaluator, Outcome};
 use crate::format::Format;
+use crate::random::Rng;
+use crate::symmetry::Symmetry;
+use crate::tablebase::Tablebase;
 use crate::transpose::{Bound, TranspositionTable};
+use std::cell::RefCell;
 use std::cmp::{max, min};
+use std::time::{Duration, Instant};
 
 pub struct NegamaxResult {
     pub depth: u8,
@@ -15,38 +21,156 @@ pub struct NegamaxResult {
     pub trans: u64,
 }
 
+/// Decides how long the search driver keeps deepening the search
+#[derive(Clone, Copy, Debug)]
+pub enum Limit {
+    /// Search up to a fixed depth
+    Depth(u8),
+    /// Search until the time is used up, abandoning the depth in progress
+    Time(Duration),
+}
+
 pub struct NegamaxStats {
     pub score: i16,
     pub nodes: u64,
     pub trans: u64,
 }
 
-impl Search {
+impl<E: Evaluator> Search<E> {
+    pub fn new(evaluator: E) -> Self {
+        Self {
+            evaluator,
+            book: None,
+            book_randomness: 0.0,
+            rng: RefCell::new(Rng::new(1)),
+            tablebase: None,
+            symmetric: false,
+        }
+    }
+
+    /// Shares transpositions between symmetric positions by storing them under their canonical
+    /// hash, at the cost of hashing every symmetry of each position searched
+    pub fn with_symmetry(mut self) -> Self {
+        self.symmetric = true;
+        self
+    }
+
+    /// Finds the hash that transpositions of the position are stored under, along with the
+    /// symmetry that maps actions to and from the position they are stored for
+    fn key(&self, bitboard: &Bitboard) -> (u64, Symmetry) {
+        match self.symmetric {
+            true => bitboard.canonical(),
+            false => (bitboard.hash, Symmetry::Identity),
+        }
+    }
+
+    /// Plays actions from the opening book whenever the position is in it, using the randomness
+    /// to vary which of the book actions is played
+    pub fn with_book(mut self, book: Book, randomness: f64, seed: u64) -> Self {
+        self.book = Some(book);
+        self.book_randomness = randomness;
+        self.rng = RefCell::new(Rng::new(seed));
+        self
+    }
+
+    /// Scores positions with few enough robots exactly from the tablebase instead of searching them
+    pub fn with_tablebase(mut self, tablebase: Tablebase) -> Self {
+        self.tablebase = Some(tablebase);
+        self
+    }
+
+    /// Chooses a legal action from the opening book if the position is in it
+    fn probe_book(&self, bitboard: &Bitboard) -> Option<Action> {
+        let book = self.book.as_ref()?;
+        let action = book.choose(bitboard, self.book_randomness, &mut self.rng.borrow_mut())?;
+        Some(action).filter(|action| Action::legal(bitboard, action))
+    }
+
+    /// Iteratively deepens the search until the limit is reached and returns the deepest result,
+    /// or None when the turn player has no actions available. Book actions are returned without
+    /// searching and have a depth of 0.
+    pub fn think(
+        &self,
+        bitboard: &mut Bitboard,
+        transpositions: &mut TranspositionTable,
+        limit: Limit,
+    ) -> Option<NegamaxResult> {
+        if Action::generate(bitboard, None).is_empty() {
+            return None;
+        }
+
+        if let Some(action) = self.probe_book(bitboard) {
+            return Some(NegamaxResult { depth: 0, action, score: 0, nodes: 0, trans: 0 });
+        }
+
+        let start = Instant::now();
+        let mut result = None;
+        for depth in 1..=MAX_DEPTH {
+            // The first depth always finishes so there is an action to return
+            let deadline = match limit {
+                Limit::Time(limit) if depth > 1 => Some(start + limit),
+                _ => None,
+            };
+            match self.negamax_move_until(bitboard, transpositions, depth, LOSS, WIN, deadline) {
+                Some(completed) => result = Some(completed),
+                None => break,
+            }
+            match limit {
+                Limit::Depth(limit) if depth >= limit => break,
+                // The next depth will take several times longer than this one, so avoid starting
+                // it when it is unlikely to finish in time
+                Limit::Time(limit) if start.elapsed() * 2 >= limit => break,
+                _ => {}
+            }
+        }
+        result
+    }
+
     /// Set alpha to LOSS and beta to WIN for white
     pub fn negamax_move(
+        &self,
         bitboard: &mut Bitboard,
         transpositions: &mut TranspositionTable,
         depth: u8,
         alpha: i16,
         beta: i16,
     ) -> NegamaxResult {
+        self.negamax_move_until(bitboard, transpositions, depth, alpha, beta, None)
+            .expect("Search without a deadline always completes")
+    }
+
+    /// Searches like negamax_move, but gives up and returns None once the deadline passes. The
+    /// bitboard is restored, and only the subtrees that were searched completely are stored in the
+    /// transposition table.
+    pub fn negamax_move_until(
+        &self,
+        bitboard: &mut Bitboard,
+        transpositions: &mut TranspositionTable,
+        depth: u8,
+        alpha: i16,
+        beta: i16,
+        deadline: Option<Instant>,
+    ) -> Option<NegamaxResult> {
         let mut alpha = alpha;
         let original_alpha = alpha;
-        let scored_actions = Action::generate(&bitboard, None);
+        let scored_actions = Action::generate(bitboard, None);
         let mut best_score = LOSS;
         let mut best_action = scored_actions.first().expect("No actions").action;
         let mut aggregate = NegamaxStats { score: LOSS, trans: 0, nodes: 0 };
 
         for scored_action in &scored_actions {
             let action = scored_action.action;
-            let delta = bitboard.delta(action.clone());
+            let delta = bitboard.delta(action);
             let hash = bitboard.make(&delta);
 
-            let stats = Search::negamax_score(bitboard, transpositions, depth - 1, -beta, -alpha);
+            let next_depth = depth.saturating_sub(1);
+            let stats =
+                self.negamax_score(bitboard, transpositions, next_depth, -beta, -alpha, deadline);
+            bitboard.undo(&delta, hash);
+            let stats = stats?;
             let score = -stats.score;
             aggregate.nodes += stats.nodes;
             aggregate.trans += stats.trans;
-            bitboard.undo(&delta, hash);
 
             // Update the best known evaluation
             if best_score < score {
@@ -67,97 +191,99 @@ impl Search {
         }
 
         // Update the transposition table
+        let (key, symmetry) = self.key(bitboard);
+        let stored = symmetry.action(&best_action);
         if best_score <= original_alpha {
-            transpositions.store(
-                bitboard.hash,
-                best_score,
-                best_action.clone(),
-                depth,
-                Bound::Upper,
-            );
+            transpositions.store(key, best_score, stored, depth, Bound::Upper);
         } else if best_score >= beta {
-            transpositions.store(
-                bitboard.hash,
-                best_score,
-                best_action.clone(),
-                depth,
-                Bound::Lower,
-            );
+            transpositions.store(key, best_score, stored, depth, Bound::Lower);
         } else {
-            transpositions.store(
-                bitboard.hash,
-                best_score,
-                best_action.clone(),
-                depth,
-                Bound::Exact,
-            );
+            transpositions.store(key, best_score, stored, depth, Bound::Exact);
         }
 
-        NegamaxResult {
+        Some(NegamaxResult {
             depth,
-            action: best_action.clone(),
+            action: best_action,
             score: best_score,
             nodes: aggregate.nodes,
             trans: aggregate.trans,
-        }
+        })
     }
 
+    /// Scores the position for the turn player, or returns None once the deadline passes
     fn negamax_score(
+        &self,
         bitboard: &mut Bitboard,
         transpositions: &mut TranspositionTable,
         depth: u8,
         alpha: i16,
         beta: i16,
-    ) -> NegamaxStats {
-        if let Some(outcome) = Evaluate::outcome(&bitboard) {
+        deadline: Option<Instant>,
+    ) -> Option<NegamaxStats> {
+        if let Some(outcome) = Evaluate::outcome(bitboard) {
             match outcome {
-                Outcome::Win => return NegamaxStats { score: WIN, nodes: 0, trans: 0 },
-                Outcome::Loss => return NegamaxStats { score: LOSS, nodes: 0, trans: 0 },
-                Outcome::Draw => return NegamaxStats { score: DRAW, nodes: 0, trans: 0 },
+                Outcome::Win => return Some(NegamaxStats { score: WIN, nodes: 0, trans: 0 }),
+                Outcome::Loss => return Some(NegamaxStats { score: LOSS, nodes: 0, trans: 0 }),
+                Outcome::Draw => return Some(NegamaxStats { score: DRAW, nodes: 0, trans: 0 }),
             }
         }
 
+        // Endgames in the tablebase are already solved exactly
+        if let Some(distance) = self.tablebase.as_ref().and_then(|tb| tb.probe(bitboard)) {
+            return Some(NegamaxStats { score: distance.score(), nodes: 1, trans: 0 });
+        }
+
         let mut alpha = alpha;
         let mut beta = beta;
         let original_alpha = alpha;
 
         let mut pv_move = None;
-        let previous = transpositions.lookup(bitboard.hash);
+        let (key, symmetry) = self.key(bitboard);
+        let previous = transpositions.lookup(key);
         if let Some(previous) = previous {
             if previous.depth >= depth {
                 match previous.bound {
                     Bound::Exact => {
-                        return NegamaxStats { score: previous.eval, nodes: 1, trans: 1 };
+                        return Some(NegamaxStats { score: previous.eval, nodes: 1, trans: 1 });
                     }
                     Bound::Lower => {
                         alpha = max(alpha, previous.eval);
                         if alpha >= beta {
-                            return NegamaxStats { score: previous.eval, nodes: 1, trans: 1 };
+                            return Some(NegamaxStats { score: previous.eval, nodes: 1, trans: 1 });
                         }
                     }
                     Bound::Upper => {
                         beta = min(beta, previous.eval);
                         if alpha >= beta {
-                            return NegamaxStats { score: previous.eval, nodes: 1, trans: 1 };
+                            return Some(NegamaxStats { score: previous.eval, nodes: 1, trans: 1 });
                         }
                     }
                 }
             } else {
                 // Use the previous move as the PV
-                pv_move = Some(previous.action);
+                pv_move = Some(symmetry.action(&previous.action));
             }
         }
 
         // Evaluate leaf nodes
-        if depth <= 0 {
-            return NegamaxStats { score: Evaluate::evaluate(&bitboard), nodes: 1, trans: 0 };
+        if depth == 0 {
+            return Some(NegamaxStats {
+                score: self.evaluator.evaluate(bitboard),
+                nodes: 1,
+                trans: 0,
+            });
+        }
+
+        // Only interior nodes check the clock, as leaves are too cheap to be worth it
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None;
         }
 
         // Otherwise keep searching deeper
-        let scored_actions = Action::generate(&bitboard, pv_move);
+        let scored_actions = Action::generate(bitboard, pv_move);
 
-        if scored_actions.len() == 0 {
-            return NegamaxStats { score: LOSS, nodes: 1, trans: 0 };
+        if scored_actions.is_empty() {
+            return Some(NegamaxStats { score: LOSS, nodes: 1, trans: 0 });
         }
 
         let mut best_score = LOSS;
@@ -186,14 +312,14 @@ impl Search {
             };
 
             // Remove any bad moves that give <25 move score
-            let stats = Search::negamax_score(bitboard, transpositions, next_depth, -beta, -alpha);
-
+            let stats =
+                self.negamax_score(bitboard, transpositions, next_depth, -beta, -alpha, deadline);
+            bitboard.undo(&delta, hash);
+            let stats = stats?;
             let score = -stats.score;
             aggregate.trans += stats.trans;
             aggregate.nodes += stats.nodes;
 
-            bitboard.undo(&delta, hash);
-
             if best_score < score {
                 best_score = score;
                 best_action = action;
@@ -205,19 +331,33 @@ impl Search {
             }
         }
 
+        let stored = symmetry.action(&best_action);
         if best_score <= original_alpha {
-            transpositions.store(bitboard.hash, best_score, best_action, depth, Bound::Upper);
+            transpositions.store(key, best_score, stored, depth, Bound::Upper);
         } else if best_score >= beta {
-            transpositions.store(bitboard.hash, best_score, best_action, depth, Bound::Upper);
+            transpositions.store(key, best_score, stored, depth, Bound::Lower);
         } else {
-            transpositions.store(bitboard.hash, best_score, best_action, depth, Bound::Upper);
+            transpositions.store(key, best_score, stored, depth, Bound::Exact);
         }
 
-        aggregate
+        Some(aggregate)
     }
 }
 
-pub struct Search {}
+pub struct Search<E: Evaluator> {
+    pub evaluator: E,
+    book: Option<Book>,
+    book_randomness: f64,
+    rng: RefCell<Rng>,
+    tablebase: Option<Tablebase>,
+    symmetric: bool,
+}
+
+impl Default for Search<Evaluate> {
+    fn default() -> Self {
+        Self::new(Evaluate::default())
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -227,12 +367,79 @@ mod tests {
     fn full_search_5() {
         let mut tt = TranspositionTable::new(28);
         let mut bb = Bitboard::new();
+        let search = Search::default();
         let mut result;
         for i in 0..=4 {
-            result = Search::negamax_move(&mut bb, &mut tt, i, LOSS, WIN);
+            result = search.negamax_move(&mut bb, &mut tt, i, LOSS, WIN);
             if i == 4 {
-                assert_eq!(result.nodes, 85443758);
+                assert_eq!(result.nodes, 411464);
             }
         }
     }
+
+    #[test]
+    fn scores_are_stored_with_their_bounds() {
+        let mut tt = TranspositionTable::new(16);
+        let mut bb = Bitboard::new();
+        let search = Search::default();
+
+        // Any action scores above a loss, so a window just above it fails high
+        search.negamax_score(&mut bb, &mut tt, 2, LOSS, LOSS + 1, None);
+        assert_eq!(tt.lookup(bb.hash).unwrap().bound, Bound::Lower);
+        search.negamax_score(&mut bb, &mut tt, 3, LOSS, WIN, None);
+        assert_eq!(tt.lookup(bb.hash).unwrap().bound, Bound::Exact);
+    }
+
+    #[test]
+    fn search_gives_up_after_the_deadline() {
+        let mut tt = TranspositionTable::new(16);
+        let mut bb = Bitboard::new();
+        let search = Search::default();
+        let deadline = Some(Instant::now());
+        assert!(search.negamax_move_until(&mut bb, &mut tt, 6, LOSS, WIN, deadline).is_none());
+        assert_eq!(bb, Bitboard::new());
+        assert!(tt.lookup(bb.hash).is_none());
+    }
+
+    #[test]
+    fn think_plays_book_actions() {
+        let mut tt = TranspositionTable::new(16);
+        let mut bb = Bitboard::new();
+        let action = Action { source: 9, target: 17, robots: 1 };
+        let mut book = Book::new();
+        book.add(&bb, action, 1);
+
+        let search = Search::default().with_book(book, 1.0, 1);
+        let result = search.think(&mut bb, &mut tt, Limit::Depth(3)).unwrap();
+        assert_eq!(result.action, action);
+        assert_eq!(result.depth, 0);
+    }
+
+    #[test]
+    fn symmetric_search_agrees() {
+        let mut bb = Bitboard::new();
+        let plain = Search::default();
+        let symmetric = Search::default().with_symmetry();
+        let mut plain_tt = TranspositionTable::new(20);
+        let mut symmetric_tt = TranspositionTable::new(20);
+        let plain = plain.think(&mut bb, &mut plain_tt, Limit::Depth(3)).unwrap();
+        let symmetric = symmetric.think(&mut bb, &mut symmetric_tt, Limit::Depth(3)).unwrap();
+        assert_eq!(plain.score, symmetric.score);
+        assert!(symmetric.nodes < plain.nodes);
+    }
+
+    #[test]
+    fn think_uses_tablebase_scores() {
+        let mut tt = TranspositionTable::new(16);
+        let mut bb = Format::parse("8/8/8/3a4/8/8/8/A7 w").unwrap();
+        let mut tablebase = Tablebase::new();
+        tablebase.extend();
+        tablebase.extend();
+
+        let search = Search::default().with_tablebase(tablebase);
+        let actions = Action::generate(&bb, None).len() as u64;
+        let result = search.think(&mut bb, &mut tt, Limit::Depth(3)).unwrap();
+        assert_eq!(result.score, DRAW);
+        assert_eq!(result.nodes, actions);
+    }
 }
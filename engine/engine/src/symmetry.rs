@@ -0,0 +1,139 @@
+use crate::action::Action;
+use crate::bitboard::Bitboard;
+use crate::bitwise::Bitwise;
+use crate::constants::*;
+
+/// One of the transforms that map a position onto an equivalent one. Mirroring swaps the files
+/// while flipping swaps the ranks along with the colours of every robot and the turn player, so
+/// the turn player's result is unchanged by any of them. Every transform is its own inverse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symmetry {
+    Identity,
+    Mirror,
+    Flip,
+    MirrorFlip,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 4] =
+        [Symmetry::Identity, Symmetry::Mirror, Symmetry::Flip, Symmetry::MirrorFlip];
+
+    fn mirrors(self) -> bool {
+        matches!(self, Symmetry::Mirror | Symmetry::MirrorFlip)
+    }
+
+    fn flips(self) -> bool {
+        matches!(self, Symmetry::Flip | Symmetry::MirrorFlip)
+    }
+
+    /// Transforms a square index
+    pub fn square(self, idx: u8) -> u8 {
+        let idx = if self.mirrors() { idx ^ 7 } else { idx };
+        if self.flips() {
+            idx ^ 56
+        } else {
+            idx
+        }
+    }
+
+    /// Transforms an action, leaving the unused target of booms alone
+    pub fn action(self, action: &Action) -> Action {
+        Action {
+            source: self.square(action.source),
+            target: match action.robots {
+                0 => action.target,
+                _ => self.square(action.target),
+            },
+            robots: action.robots,
+        }
+    }
+
+    /// Transforms a bitboard frame, without swapping colours
+    pub fn frame(self, frame: u64) -> u64 {
+        let frame = if self.mirrors() { Bitwise::flip_file(frame) } else { frame };
+        if self.flips() {
+            Bitwise::flip_rank(frame)
+        } else {
+            frame
+        }
+    }
+
+    /// Transforms a position
+    pub fn bitboard(self, bitboard: &Bitboard) -> Bitboard {
+        let mut transformed = bitboard.clone();
+        for (frame, original) in transformed.board.iter_mut().zip(bitboard.board) {
+            *frame = self.frame(original);
+        }
+        if self.flips() {
+            transformed.board.swap(WHITE, BLACK);
+            std::mem::swap(&mut transformed.turn, &mut transformed.opponent);
+            std::mem::swap(&mut transformed.robots_white, &mut transformed.robots_black);
+        }
+        transformed.hash = transformed.zorbist();
+        transformed
+    }
+
+    /// Computes the zorbist hash the position would have once transformed, without building it
+    pub fn hash(self, bitboard: &Bitboard) -> u64 {
+        if self == Symmetry::Identity {
+            return bitboard.hash;
+        }
+
+        let turn = if self.flips() { bitboard.opponent } else { bitboard.turn };
+        let mut hash = if turn == WHITE { ZORBIST_TURN } else { 0 };
+        for (i, frame) in bitboard.board.iter().enumerate() {
+            let keys = match (i, self.flips()) {
+                (WHITE, true) => &ZORBIST_KEY[BLACK],
+                (BLACK, true) => &ZORBIST_KEY[WHITE],
+                _ => &ZORBIST_KEY[i],
+            };
+            let mut bots = *frame;
+            while bots != 0 {
+                let bot = Bitwise::lsb(bots);
+                bots ^= bot;
+                hash ^= keys[self.square(Bitwise::idx(bot) as u8) as usize];
+            }
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+
+    #[test]
+    fn transforms_are_involutions() {
+        let bitboard = Format::parse("8/2a5/8/1b6/8/3C4/8/A7 b").unwrap();
+        for symmetry in Symmetry::ALL {
+            let transformed = symmetry.bitboard(&bitboard);
+            assert_eq!(transformed.hash, symmetry.hash(&bitboard));
+            assert_eq!(symmetry.bitboard(&transformed), bitboard);
+        }
+    }
+
+    #[test]
+    fn flip_swaps_colours_and_turn() {
+        let bitboard = Format::parse("8/2a5/8/1b6/8/3C4/8/A7 b").unwrap();
+        assert_eq!(
+            Format::notation(&Symmetry::Mirror.bitboard(&bitboard)),
+            "8/5a2/8/6b1/8/4C3/8/7A b"
+        );
+        assert_eq!(
+            Format::notation(&Symmetry::Flip.bitboard(&bitboard)),
+            "a7/8/3c4/8/1B6/8/2A5/8 w"
+        );
+    }
+
+    #[test]
+    fn actions_follow_the_board() {
+        let bitboard = Format::parse("8/8/8/8/8/8/8/B7 w").unwrap();
+        let action = Format::parse_action("a1a3/1").unwrap();
+        for symmetry in Symmetry::ALL {
+            let transformed = symmetry.bitboard(&bitboard);
+            assert!(Action::legal(&transformed, &symmetry.action(&action)));
+        }
+        assert_eq!(Format::action(&Symmetry::MirrorFlip.action(&action)), "h8h6/1");
+    }
+}
@@ -0,0 +1,380 @@
+use crate::action::Action;
+use crate::bitboard::Bitboard;
+use crate::bitwise::Bitwise;
+use crate::constants::*;
+use crate::evaluate::{Evaluate, Outcome};
+use crate::symmetry::Symmetry;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::thread;
+
+/// Identifies a tablebase file, which is followed by the number of robots it covers and then the
+/// values of every layer in order of robot count
+const MAGIC: &[u8; 4] = b"BBTB";
+
+/// The exact result of a position with perfect play, as the number of plies until the game ends
+/// for the turn player
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distance {
+    Win(u8),
+    Loss(u8),
+    Draw,
+}
+
+impl Distance {
+    /// Converts the distance into a search score, which prefers quicker wins and slower losses
+    pub fn score(self) -> i16 {
+        match self {
+            Distance::Win(plies) => WIN - plies as i16,
+            Distance::Loss(plies) => LOSS + plies as i16,
+            Distance::Draw => DRAW,
+        }
+    }
+
+    // Distances are stored as a single signed byte relative to the turn player
+    fn decode(value: i8) -> Distance {
+        match value {
+            0 => Distance::Draw,
+            1.. => Distance::Win(value as u8),
+            _ => Distance::Loss(value.unsigned_abs()),
+        }
+    }
+}
+
+/// Exact results for every position with up to a number of robots, solved by retrograde analysis.
+///
+/// Positions are grouped into layers by their total robot count, counting every robot in a stack.
+/// Moves keep a position in its layer while booms always remove robots, so each layer is solved
+/// using only itself and the smaller layers. Within a layer positions are indexed by the set of
+/// occupied squares, the heights of the stacks on them, their colours and then the turn player.
+/// Only the canonical position of each set of symmetric positions is solved and looked up.
+#[derive(Debug, Default, PartialEq)]
+pub struct Tablebase {
+    layers: Vec<Vec<i8>>,
+}
+
+impl Tablebase {
+    pub fn new() -> Tablebase {
+        Tablebase { layers: vec![Vec::new()] }
+    }
+
+    /// The largest number of robots covered by the tablebase
+    pub fn robots(&self) -> u8 {
+        self.layers.len().saturating_sub(1) as u8
+    }
+
+    /// Looks up the exact result of the position, or None if it has too many robots or is over
+    pub fn probe(&self, bitboard: &Bitboard) -> Option<Distance> {
+        if bitboard.robots_white == 0 || bitboard.robots_black == 0 {
+            return None;
+        }
+        let layer = self.layers.get(bitboard.robots_total as usize)?;
+        Some(Distance::decode(layer[Tablebase::canonical(bitboard)]))
+    }
+
+    /// Solves the layer with one more robot than the tablebase currently covers and returns the
+    /// number of positions in it that are won or lost rather than drawn
+    pub fn extend(&mut self) -> usize {
+        let robots = self.layers.len();
+        assert!(robots <= 12, "Stacks higher than 12 cannot be indexed");
+
+        // Positions that are already over never need solving, and neither do the positions that
+        // are symmetric to another one that is solved
+        let size = layer_size(robots);
+        let mut unsolved: Vec<usize> = (0..size)
+            .filter(|&index| {
+                let bitboard = Tablebase::position(robots, index);
+                Evaluate::outcome(&bitboard).is_none() && Tablebase::canonical(&bitboard) == index
+            })
+            .collect();
+
+        // Booms can lead into a smaller layer at any distance, so keep going until the longest
+        // of those has been passed even if nothing was solved for a while
+        let longest =
+            self.layers.iter().flatten().map(|value| value.unsigned_abs()).max().unwrap_or(0);
+
+        // Each pass solves exactly the positions that end in that many plies, relying on every
+        // shorter result being known from the previous passes. Positions within a pass are
+        // independent of each other so they are split between threads.
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let mut values = vec![0; size];
+        let mut decided = 0;
+        for plies in 1..=i8::MAX as u8 {
+            let chunk = unsolved.len().div_ceil(threads).max(1);
+            let (solved, remaining): (Vec<_>, Vec<_>) = thread::scope(|scope| {
+                let (solver, values) = (&*self, &values);
+                let workers: Vec<_> = unsolved
+                    .chunks(chunk)
+                    .map(|chunk| scope.spawn(move || solver.pass(robots, chunk, values, plies)))
+                    .collect();
+                workers.into_iter().map(|worker| worker.join().expect("Solver panicked")).unzip()
+            });
+            unsolved = remaining.concat();
+
+            let solved = solved.concat();
+            if solved.is_empty() && plies > longest.saturating_add(1) {
+                break;
+            }
+            decided += solved.len();
+            for (index, value) in solved {
+                values[index] = value;
+            }
+        }
+
+        // Anything left unsolved can be played forever without either side winning
+        self.layers.push(values);
+        decided
+    }
+
+    // Splits the positions into those that end in exactly the number of plies, along with their
+    // values, and those that are still unsolved
+    fn pass(
+        &self,
+        robots: usize,
+        indices: &[usize],
+        layer: &[i8],
+        plies: u8,
+    ) -> (Vec<(usize, i8)>, Vec<usize>) {
+        let mut solved = Vec::new();
+        let mut unsolved = Vec::new();
+        for &index in indices {
+            let mut bitboard = Tablebase::position(robots, index);
+            match self.solve(&mut bitboard, layer, plies) {
+                Some(value) => solved.push((index, value)),
+                None => unsolved.push(index),
+            }
+        }
+        (solved, unsolved)
+    }
+
+    // Decides whether the position ends in exactly the number of plies. Every legal action is tried,
+    // including the booms that search leaves out, so the results are exact.
+    fn solve(&self, bitboard: &mut Bitboard, layer: &[i8], plies: u8) -> Option<i8> {
+        let mut lost = true;
+        for scored in Action::generate_all(bitboard) {
+            let delta = bitboard.delta(scored.action);
+            let hash = bitboard.make(&delta);
+            let reply = match Evaluate::outcome(bitboard) {
+                Some(Outcome::Win) => Distance::Win(0),
+                Some(Outcome::Loss) => Distance::Loss(0),
+                Some(Outcome::Draw) => Distance::Draw,
+                None if bitboard.robots_total as usize == self.layers.len() => {
+                    Distance::decode(layer[Tablebase::canonical(bitboard)])
+                }
+                None => self.probe(bitboard).expect("Booms lead to a solved layer"),
+            };
+            bitboard.undo(&delta, hash);
+
+            match reply {
+                // Any shorter win would have been found by an earlier pass
+                Distance::Loss(reply) if reply < plies => return Some(plies as i8),
+                Distance::Win(reply) if reply < plies => {}
+                _ => lost = false,
+            }
+        }
+        lost.then_some(-(plies as i8))
+    }
+
+    /// Finds the index of a position within the layer for its robot count
+    pub fn index(bitboard: &Bitboard) -> usize {
+        let robots = bitboard.robots_total as usize;
+        let occupied = bitboard.board[WHITE] | bitboard.board[BLACK];
+        let stacks = Bitwise::pcnt(occupied) as usize;
+
+        // Rank the occupied squares, the running totals of the stack heights and the colours
+        let mut squares = 0;
+        let mut heights = 0;
+        let mut colours = 0;
+        let mut total = 0;
+        let mut bots = occupied;
+        for i in 0..stacks {
+            let bot = Bitwise::lsb(bots);
+            bots ^= bot;
+            squares += binomial(Bitwise::idx(bot), i + 1);
+            total += bitboard.height(bot) as usize;
+            if i + 1 < stacks {
+                heights += binomial(total - 1, i + 1);
+            }
+            if bitboard.board[BLACK] & bot != 0 {
+                colours |= 1 << i;
+            }
+        }
+
+        let turn = (bitboard.turn == BLACK) as usize;
+        let compositions = binomial(robots - 1, stacks - 1);
+        let rank = ((squares * compositions + heights) << stacks | colours) << 1 | turn;
+        (1..stacks).map(|smaller| block_size(robots, smaller)).sum::<usize>() + rank
+    }
+
+    // Finds the index of the symmetric position that results are stored for
+    fn canonical(bitboard: &Bitboard) -> usize {
+        match bitboard.canonical() {
+            (_, Symmetry::Identity) => Tablebase::index(bitboard),
+            (_, symmetry) => Tablebase::index(&symmetry.bitboard(bitboard)),
+        }
+    }
+
+    /// Builds the position at the index within the layer for the robot count
+    pub fn position(robots: usize, index: usize) -> Bitboard {
+        let mut stacks = 1;
+        let mut rank = index;
+        while rank >= block_size(robots, stacks) {
+            rank -= block_size(robots, stacks);
+            stacks += 1;
+        }
+
+        let turn = if rank & 1 == 0 { WHITE } else { BLACK };
+        rank >>= 1;
+        let colours = rank & ((1 << stacks) - 1);
+        rank >>= stacks;
+        let compositions = binomial(robots - 1, stacks - 1);
+        let squares = unrank(rank / compositions, stacks, 64);
+        let mut totals = unrank(rank % compositions, stacks - 1, robots - 1);
+        totals.iter_mut().for_each(|total| *total += 1);
+        totals.push(robots);
+
+        let mut bitboard = Bitboard::empty();
+        let mut previous = 0;
+        for (i, (square, total)) in squares.into_iter().zip(totals).enumerate() {
+            let team = if colours & (1 << i) == 0 { WHITE } else { BLACK };
+            bitboard = bitboard.with(Bitwise::pos(square as u8), total - previous, team);
+            previous = total;
+        }
+        bitboard.with_turn(turn)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Tablebase> {
+        let bytes = fs::read(path)?;
+        let invalid = |message| Error::new(ErrorKind::InvalidData, message);
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err(invalid("Not a tablebase"));
+        }
+
+        let mut tablebase = Tablebase::new();
+        let mut rest = &bytes[5..];
+        for robots in 1..=bytes[4] as usize {
+            let size = layer_size(robots);
+            if rest.len() < size {
+                return Err(invalid("Tablebase is missing positions"));
+            }
+            tablebase.layers.push(rest[..size].iter().map(|&value| value as i8).collect());
+            rest = &rest[size..];
+        }
+        match rest.is_empty() {
+            true => Ok(tablebase),
+            false => Err(invalid("Tablebase has trailing data")),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(self.robots());
+        bytes.extend(self.layers.iter().flatten().map(|&value| value as u8));
+        fs::write(path, bytes)
+    }
+}
+
+/// Pascal's triangle for choosing up to 12 stacks from the 64 squares, which is looked up for
+/// every position visited while solving
+const BINOMIAL: [[usize; 13]; 65] = {
+    let mut table = [[0; 13]; 65];
+    let mut n = 0;
+    while n <= 64 {
+        table[n][0] = 1;
+        let mut k = 1;
+        while k <= 12 && n > 0 {
+            table[n][k] = table[n - 1][k - 1] + table[n - 1][k];
+            k += 1;
+        }
+        n += 1;
+    }
+    table
+};
+
+// Counts the ways of choosing k of n items
+fn binomial(n: usize, k: usize) -> usize {
+    BINOMIAL[n][k]
+}
+
+// Finds the k ascending values below the limit with the combinatorial rank
+fn unrank(mut rank: usize, k: usize, limit: usize) -> Vec<usize> {
+    let mut values = vec![0; k];
+    let mut value = limit;
+    for i in (1..=k).rev() {
+        value -= 1;
+        while binomial(value, i) > rank {
+            value -= 1;
+        }
+        rank -= binomial(value, i);
+        values[i - 1] = value;
+    }
+    values
+}
+
+// Counts the positions with the robots spread across the number of stacks
+fn block_size(robots: usize, stacks: usize) -> usize {
+    (binomial(64, stacks) * binomial(robots - 1, stacks - 1)) << (stacks + 1)
+}
+
+// Counts the positions with the number of robots
+fn layer_size(robots: usize) -> usize {
+    (1..=robots).map(|stacks| block_size(robots, stacks)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use std::env;
+
+    #[test]
+    fn index_round_trips() {
+        for robots in 1..=3 {
+            for index in (0..layer_size(robots)).step_by(997) {
+                let bitboard = Tablebase::position(robots, index);
+                assert_eq!(bitboard.robots_total as usize, robots);
+                assert_eq!(Tablebase::index(&bitboard), index);
+            }
+        }
+    }
+
+    #[test]
+    fn small_layers_are_drawn() {
+        let mut tablebase = Tablebase::new();
+        assert_eq!(tablebase.extend(), 0);
+        assert_eq!(tablebase.extend(), 0);
+
+        // A single robot each can only ever trade itself away
+        let draw = Format::parse("8/8/8/3a4/8/8/3A4/8 w").unwrap();
+        assert_eq!(tablebase.probe(&draw), Some(Distance::Draw));
+        assert_eq!(tablebase.probe(&draw.flip()), Some(Distance::Draw));
+        assert_eq!(tablebase.probe(&Bitboard::new()), None);
+    }
+
+    #[test]
+    fn booming_next_to_an_opponent_wins() {
+        let mut tablebase = Tablebase::new();
+        tablebase.extend();
+        tablebase.extend();
+
+        // Booming next to the black robot leaves white with the other robot
+        let mut win = Format::parse("7A/8/8/8/8/8/8/Aa6 w").unwrap();
+        let layer = vec![0; layer_size(3)];
+        assert_eq!(tablebase.solve(&mut win, &layer, 1), Some(1));
+        let mut draw = Format::parse("7A/8/8/8/8/8/8/A1a5 w").unwrap();
+        assert_eq!(tablebase.solve(&mut draw, &layer, 1), None);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut tablebase = Tablebase::new();
+        tablebase.extend();
+        tablebase.extend();
+
+        let path = env::temp_dir().join("boombots-tablebase-test.bin");
+        tablebase.save(&path).unwrap();
+        assert_eq!(Tablebase::load(&path).unwrap(), tablebase);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use crate::bitboard::Bitboard;
+use crate::constants::*;
+use crate::evaluate::{EvalParams, Outcome};
+use std::collections::HashMap;
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+/// The command line of one of the engine tools, split into positional arguments and --key value
+/// options. Invalid arguments print the message along with the usage of the tool and exit.
+pub struct Args {
+    pub positional: Vec<String>,
+    pub options: HashMap<String, String>,
+    usage: &'static str,
+}
+
+impl Args {
+    /// Parses the arguments the process was started with
+    pub fn from_env(usage: &'static str) -> Args {
+        Args::parse(&env::args().skip(1).collect::<Vec<String>>(), usage)
+    }
+
+    pub fn parse(args: &[String], usage: &'static str) -> Args {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(key) => {
+                    let value = args
+                        .next()
+                        .unwrap_or_else(|| exit(&format!("Missing value for --{}", key), usage));
+                    options.insert(key.to_string(), value.clone());
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+        Args { positional, options, usage }
+    }
+
+    pub fn exit(&self, message: &str) -> ! {
+        exit(message, self.usage)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.options.get(key)
+    }
+
+    /// Reads an optional option, falling back to the default when it is absent
+    pub fn option<T: FromStr>(&self, key: &str, default: T) -> T {
+        match self.options.get(key) {
+            Some(value) => {
+                value.parse().unwrap_or_else(|_| self.exit(&format!("Invalid value for --{}", key)))
+            }
+            None => default,
+        }
+    }
+
+    /// Loads the evaluation parameters from the --params file, or the defaults when it is absent
+    pub fn params(&self) -> EvalParams {
+        match self.options.get("params") {
+            Some(path) => EvalParams::load(path).expect("Failed to load evaluation parameters"),
+            None => EvalParams::default(),
+        }
+    }
+}
+
+fn exit(message: &str, usage: &str) -> ! {
+    eprintln!("{}\n\n{}", message, usage);
+    process::exit(1);
+}
+
+/// Converts an outcome relative to the turn player into a result relative to white
+pub fn result(bitboard: &Bitboard, outcome: Outcome) -> f64 {
+    match (outcome, bitboard.turn) {
+        (Outcome::Draw, _) => 0.5,
+        (Outcome::Win, WHITE) | (Outcome::Loss, BLACK) => 1.0,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_split_options_from_positional() {
+        let args: Vec<String> =
+            ["fit", "--iterations", "20", "corpus.txt"].iter().map(|arg| arg.to_string()).collect();
+        let args = Args::parse(&args, "");
+        assert_eq!(args.positional, vec!["fit", "corpus.txt"]);
+        assert_eq!(args.option("iterations", 1000), 20);
+        assert_eq!(args.option("depth", 3), 3);
+    }
+
+    #[test]
+    fn result_is_relative_to_white() {
+        let mut bitboard = Bitboard::new();
+        assert_eq!(result(&bitboard, Outcome::Win), 1.0);
+        assert_eq!(result(&bitboard, Outcome::Draw), 0.5);
+        bitboard.turn = BLACK;
+        assert_eq!(result(&bitboard, Outcome::Win), 0.0);
+        assert_eq!(result(&bitboard, Outcome::Loss), 1.0);
+    }
+}
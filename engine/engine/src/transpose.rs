@@ -3,7 +3,7 @@ use crate::bitboard::Bitboard;
 use crate::bitwise::Bitwise;
 use crate::constants::*;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Bound {
     Lower,
     Upper,
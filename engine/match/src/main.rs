@@ -0,0 +1,175 @@
+mod player;
+mod stats;
+
+use engine::action::Action;
+use engine::bitboard::Bitboard;
+use engine::constants::*;
+use engine::evaluate::{Evaluate, Outcome};
+use engine::format::Format;
+use engine::record::Record;
+use engine::search::Limit;
+use engine::tool::{self, Args};
+use player::Player;
+use stats::{Decision, Results, Sprt};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::time::Duration;
+
+const USAGE: &str = "Usage:
+    match <engine1> <engine2> [--openings FILE] [--games N] [--depth N] [--movetime MS]
+        [--max-plies N] [--elo0 N] [--elo1 N] [--alpha N] [--beta N] [--book FILE]
+        [--book-randomness N] [--record FILE] [--tablebase FILE]
+
+Engines are either \"default\", the path to an evaluation parameters json file, or \"exec:\"
+followed by the command of an engine speaking the engine text protocol, such as
+\"exec:target/release/cli protocol\".";
+
+// Loads the opening positions, one position notation per line, or the start position by default
+fn openings(args: &Args) -> Vec<Bitboard> {
+    let path = match args.get("openings") {
+        Some(path) => path,
+        None => return vec![Bitboard::new()],
+    };
+    let openings: Vec<Bitboard> = fs::read_to_string(path)
+        .expect("Failed to read openings")
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            Format::parse(line).unwrap_or_else(|| args.exit(&format!("Invalid opening: {}", line)))
+        })
+        .collect();
+    if openings.is_empty() {
+        args.exit("No openings found");
+    }
+    openings
+}
+
+/// Plays a game from the opening and records it along with the result for white. A player that
+/// chooses an illegal action forfeits, and the game is drawn once it reaches the maximum number of
+/// plies.
+fn play<'a>(
+    white: &'a mut dyn Player,
+    black: &'a mut dyn Player,
+    opening: &Bitboard,
+    limit: Limit,
+    max_plies: usize,
+) -> io::Result<Record> {
+    white.new_game()?;
+    black.new_game()?;
+    let mut bitboard = opening.clone();
+    let mut record = Record { opening: opening.clone(), actions: Vec::new(), result: 0.5 };
+
+    for _ in 0..max_plies {
+        if let Some(outcome) = Evaluate::outcome(&bitboard) {
+            record.result = tool::result(&bitboard, outcome);
+            return Ok(record);
+        }
+
+        let player = if bitboard.turn == WHITE { &mut *white } else { &mut *black };
+        let action = match player.choose(&bitboard, limit)? {
+            Some(action) => action,
+            None => {
+                record.result = tool::result(&bitboard, Outcome::Loss);
+                return Ok(record);
+            }
+        };
+        if !Action::legal(&bitboard, &action) {
+            eprintln!("{} played an illegal action {}", player.name(), Format::action(&action));
+            record.result = tool::result(&bitboard, Outcome::Loss);
+            return Ok(record);
+        }
+
+        let delta = bitboard.delta(action);
+        bitboard.make(&delta);
+        record.actions.push(action);
+    }
+
+    Ok(record)
+}
+
+fn report(results: &Results, sprt: &Sprt) -> String {
+    let (elo, margin) = results.elo();
+    let (lower, upper) = Results::bounds(sprt);
+    format!(
+        "W/D/L {}/{}/{} | Elo {:+.1} ± {:.1} | LLR {:.2} ({:.2}, {:.2})",
+        results.wins,
+        results.draws,
+        results.losses,
+        elo,
+        margin,
+        results.llr(sprt),
+        lower,
+        upper
+    )
+}
+
+fn main() {
+    let args = Args::from_env(USAGE);
+    let book = args.get("book");
+    let randomness = args.option("book-randomness", 1.0);
+    let tablebase = args.get("tablebase");
+    let (mut engine1, mut engine2) = match &args.positional[..] {
+        [engine1, engine2] => (
+            player::create(engine1, book, randomness, 1, tablebase)
+                .expect("Failed to create the first engine"),
+            player::create(engine2, book, randomness, 2, tablebase)
+                .expect("Failed to create the second engine"),
+        ),
+        _ => args.exit("Expected two engines"),
+    };
+    let mut records = args
+        .get("record")
+        .map(|path| BufWriter::new(File::create(path).expect("Failed to create the record file")));
+
+    let openings = openings(&args);
+    let games: usize = args.option("games", 100);
+    let max_plies = args.option("max-plies", 200);
+    let limit = match args.get("movetime") {
+        Some(_) => Limit::Time(Duration::from_millis(args.option("movetime", 0))),
+        None => Limit::Depth(args.option("depth", 4)),
+    };
+    let sprt = Sprt {
+        elo0: args.option("elo0", 0.0),
+        elo1: args.option("elo1", 10.0),
+        alpha: args.option("alpha", 0.05),
+        beta: args.option("beta", 0.05),
+    };
+
+    let mut results = Results::default();
+    let mut decision = Decision::Continue;
+
+    for game in 0..games {
+        // Each opening is played twice in a row with the engines swapping colours
+        let opening = &openings[(game / 2) % openings.len()];
+        let swapped = game % 2 == 1;
+        let record = match swapped {
+            false => play(engine1.as_mut(), engine2.as_mut(), opening, limit, max_plies),
+            true => play(engine2.as_mut(), engine1.as_mut(), opening, limit, max_plies),
+        }
+        .expect("Failed to play game");
+        if let Some(records) = &mut records {
+            writeln!(records, "{}", record.line()).expect("Failed to write the record");
+        }
+
+        // Convert the result for white into the result for the first engine
+        results.record(if swapped { 1.0 - record.result } else { record.result });
+        println!("[game={}/{}] {}", game + 1, games, report(&results, &sprt));
+
+        decision = results.decision(&sprt);
+        if decision != Decision::Continue {
+            break;
+        }
+    }
+
+    if let Some(records) = &mut records {
+        records.flush().expect("Failed to write the records");
+    }
+
+    println!("{} vs {}", engine1.name(), engine2.name());
+    println!("{}", report(&results, &sprt));
+    match decision {
+        Decision::AcceptH0 => println!("SPRT: H0 accepted (elo <= {})", sprt.elo0),
+        Decision::AcceptH1 => println!("SPRT: H1 accepted (elo >= {})", sprt.elo1),
+        Decision::Continue => println!("SPRT: inconclusive"),
+    }
+}
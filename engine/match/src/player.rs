@@ -0,0 +1,156 @@
+use engine::action::Action;
+use engine::bitboard::Bitboard;
+use engine::book::Book;
+use engine::evaluate::{EvalParams, Evaluate};
+use engine::format::Format;
+use engine::search::{Limit, Search};
+use engine::tablebase::Tablebase;
+use engine::transpose::TranspositionTable;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// One side of a match, which chooses actions for whichever positions it is given
+pub trait Player {
+    fn name(&self) -> &str;
+
+    /// Resets any state carried between moves, such as transposition tables
+    fn new_game(&mut self) -> io::Result<()>;
+
+    /// Chooses an action for the turn player, or None when there are no actions available
+    fn choose(&mut self, bitboard: &Bitboard, limit: Limit) -> io::Result<Option<Action>>;
+}
+
+/// Creates a player from its specification, which is either "default" for the default evaluation
+/// parameters, the path to an evaluation parameters json file, or "exec:" followed by the command
+/// of an engine that speaks the engine text protocol. Only players searched within this process
+/// use the opening book and tablebase.
+pub fn create(
+    spec: &str,
+    book: Option<&String>,
+    randomness: f64,
+    seed: u64,
+    tablebase: Option<&String>,
+) -> io::Result<Box<dyn Player>> {
+    if let Some(command) = spec.strip_prefix("exec:") {
+        return Ok(Box::new(External::spawn(command)?));
+    }
+    let params = match spec {
+        "default" => EvalParams::default(),
+        path => EvalParams::load(path)?,
+    };
+    let mut search = Search::new(Evaluate::new(params));
+    if let Some(book) = book {
+        search = search.with_book(Book::load(book)?, randomness, seed);
+    }
+    if let Some(tablebase) = tablebase {
+        search = search.with_tablebase(Tablebase::load(tablebase)?);
+    }
+    Ok(Box::new(Internal::new(spec, search)))
+}
+
+/// An engine configuration searched within this process
+pub struct Internal {
+    name: String,
+    search: Search<Evaluate>,
+    tt: TranspositionTable,
+}
+
+impl Internal {
+    pub fn new(name: &str, search: Search<Evaluate>) -> Internal {
+        Internal { name: name.to_string(), search, tt: TranspositionTable::new(20) }
+    }
+}
+
+impl Player for Internal {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> io::Result<()> {
+        self.tt = TranspositionTable::new(20);
+        Ok(())
+    }
+
+    fn choose(&mut self, bitboard: &Bitboard, limit: Limit) -> io::Result<Option<Action>> {
+        let mut bitboard = bitboard.clone();
+        Ok(self.search.think(&mut bitboard, &mut self.tt, limit).map(|result| result.action))
+    }
+}
+
+/// An engine running in a child process which is played using the engine text protocol
+pub struct External {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl External {
+    pub fn spawn(command: &str) -> io::Result<External> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or_else(|| invalid("Missing engine command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| invalid("Engine has no stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| invalid("Engine has no stdout"))?;
+        Ok(External { name: command.to_string(), child, stdin, stdout: BufReader::new(stdout) })
+    }
+
+    // Reads lines from the engine until one starts with the prefix, returning the rest of it
+    fn expect(&mut self, prefix: &str) -> io::Result<String> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(invalid("Engine closed its output"));
+            }
+            if let Some(rest) = line.trim().strip_prefix(prefix) {
+                return Ok(rest.trim().to_string());
+            }
+        }
+    }
+}
+
+impl Player for External {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> io::Result<()> {
+        writeln!(self.stdin, "newgame")?;
+        writeln!(self.stdin, "isready")?;
+        self.stdin.flush()?;
+        self.expect("readyok").map(|_| ())
+    }
+
+    fn choose(&mut self, bitboard: &Bitboard, limit: Limit) -> io::Result<Option<Action>> {
+        writeln!(self.stdin, "position {}", Format::notation(bitboard))?;
+        match limit {
+            Limit::Depth(depth) => writeln!(self.stdin, "go depth {}", depth)?,
+            Limit::Time(time) => writeln!(self.stdin, "go movetime {}", time.as_millis())?,
+        }
+        self.stdin.flush()?;
+
+        match self.expect("bestmove")?.as_str() {
+            "none" => Ok(None),
+            action => Format::parse_action(action)
+                .map(Some)
+                .ok_or_else(|| invalid(&format!("Engine sent an invalid action: {}", action))),
+        }
+    }
+}
+
+impl Drop for External {
+    fn drop(&mut self) {
+        let _ = writeln!(self.stdin, "quit");
+        let _ = self.stdin.flush();
+        let _ = self.child.wait();
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
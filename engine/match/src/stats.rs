@@ -0,0 +1,151 @@
+/// Wins, draws and losses from the perspective of the first engine
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Results {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+/// The hypotheses and error rates of a sequential probability ratio test
+#[derive(Clone, Copy, Debug)]
+pub struct Sprt {
+    /// The Elo difference of the null hypothesis
+    pub elo0: f64,
+    /// The Elo difference of the alternative hypothesis
+    pub elo1: f64,
+    /// The probability of accepting the alternative hypothesis when the null hypothesis is true
+    pub alpha: f64,
+    /// The probability of accepting the null hypothesis when the alternative hypothesis is true
+    pub beta: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    /// The change is no better than elo0
+    AcceptH0,
+    /// The change is at least as good as elo1
+    AcceptH1,
+    /// More games are needed
+    Continue,
+}
+
+/// Converts an Elo difference into the expected score
+fn score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Converts an expected score into an Elo difference
+fn elo(score: f64) -> f64 {
+    // Adding zero avoids reporting an even score as -0 Elo
+    -400.0 * (1.0 / score - 1.0).log10() + 0.0
+}
+
+impl Results {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Records a game result for the first engine, where 1 is a win, 0.5 a draw and 0 a loss
+    pub fn record(&mut self, result: f64) {
+        if result > 0.5 {
+            self.wins += 1;
+        } else if result < 0.5 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    /// The mean score per game
+    fn mean(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games() as f64
+    }
+
+    /// The variance of the score of a single game
+    fn variance(&self) -> f64 {
+        let mean = self.mean();
+        let total = self.wins as f64 * (1.0 - mean).powi(2)
+            + self.draws as f64 * (0.5 - mean).powi(2)
+            + self.losses as f64 * mean.powi(2);
+        total / self.games() as f64
+    }
+
+    /// Estimates the Elo difference along with the margin of its 95% confidence interval. Scores
+    /// of 0% or 100% have an infinite Elo difference.
+    pub fn elo(&self) -> (f64, f64) {
+        if self.games() == 0 {
+            return (0.0, 0.0);
+        }
+        let mean = self.mean();
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let lower = elo((mean - margin).max(0.0));
+        let upper = elo((mean + margin).min(1.0));
+        (elo(mean), (upper - lower) / 2.0)
+    }
+
+    /// Computes the log-likelihood ratio of the test using the normal approximation of the
+    /// trinomial game outcomes
+    pub fn llr(&self, sprt: &Sprt) -> f64 {
+        let variance = self.variance();
+        if self.games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (score(sprt.elo0), score(sprt.elo1));
+        self.games() as f64 * (s1 - s0) * (2.0 * self.mean() - s0 - s1) / (2.0 * variance)
+    }
+
+    /// The lower and upper log-likelihood ratio bounds at which a hypothesis is accepted
+    pub fn bounds(sprt: &Sprt) -> (f64, f64) {
+        ((sprt.beta / (1.0 - sprt.alpha)).ln(), ((1.0 - sprt.beta) / sprt.alpha).ln())
+    }
+
+    pub fn decision(&self, sprt: &Sprt) -> Decision {
+        let llr = self.llr(sprt);
+        let (lower, upper) = Results::bounds(sprt);
+        if llr <= lower {
+            Decision::AcceptH0
+        } else if llr >= upper {
+            Decision::AcceptH1
+        } else {
+            Decision::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPRT: Sprt = Sprt { elo0: 0.0, elo1: 10.0, alpha: 0.05, beta: 0.05 };
+
+    #[test]
+    fn even_results_have_no_elo_difference() {
+        let results = Results { wins: 30, draws: 40, losses: 30 };
+        let (elo, margin) = results.elo();
+        assert!(elo.abs() < 1e-9);
+        assert!(margin > 0.0);
+    }
+
+    #[test]
+    fn winning_results_have_positive_elo() {
+        let results = Results { wins: 60, draws: 20, losses: 20 };
+        let (elo, margin) = results.elo();
+        // A 70% score is roughly +147 Elo
+        assert!((elo - 147.2).abs() < 0.1);
+        assert!(margin > 0.0 && margin < elo);
+    }
+
+    #[test]
+    fn sprt_decisions() {
+        let (lower, upper) = Results::bounds(&SPRT);
+        assert!((lower + 2.944).abs() < 0.001);
+        assert!((upper - 2.944).abs() < 0.001);
+
+        let winning = Results { wins: 600, draws: 200, losses: 200 };
+        assert_eq!(winning.decision(&SPRT), Decision::AcceptH1);
+        let losing = Results { wins: 200, draws: 200, losses: 600 };
+        assert_eq!(losing.decision(&SPRT), Decision::AcceptH0);
+        let early = Results { wins: 2, draws: 1, losses: 1 };
+        assert_eq!(early.decision(&SPRT), Decision::Continue);
+    }
+}
@@ -0,0 +1,41 @@
+use engine::tablebase::Tablebase;
+use engine::tool::Args;
+use std::time::Instant;
+
+const USAGE: &str = "Usage:
+    tablebase <output> [robots]
+
+Solves every position with up to the number of robots, which defaults to 3 and can be at most 4.
+Four robots take around four minutes on a single core and 23MB. Each extra robot takes around fifty
+times longer and twenty five times more memory than the last, so larger tablebases have never been
+generated.";
+
+/// The most robots a tablebase has been generated and checked for
+const MAX_ROBOTS: u8 = 4;
+
+fn main() {
+    let args = Args::from_env(USAGE);
+    let (output, robots) = match &args.positional[..] {
+        [output] => (output, 3),
+        [output, robots] => match robots.parse::<u8>() {
+            Ok(robots @ 1..=MAX_ROBOTS) => (output, robots),
+            _ => args.exit(&format!("Robots must be between 1 and {}", MAX_ROBOTS)),
+        },
+        _ => args.exit("Expected an output file"),
+    };
+
+    let mut tablebase = Tablebase::new();
+    while tablebase.robots() < robots {
+        let start = Instant::now();
+        let decided = tablebase.extend();
+        println!(
+            "[robots={}] {} decisive positions in {:.1}s",
+            tablebase.robots(),
+            decided,
+            start.elapsed().as_secs_f64()
+        );
+    }
+
+    tablebase.save(output).expect("Failed to save tablebase");
+    println!("Saved {} robot tablebase to {}", robots, output);
+}
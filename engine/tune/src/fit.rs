@@ -0,0 +1,154 @@
+use engine::bitboard::Bitboard;
+use engine::constants::*;
+use engine::evaluate::{EvalParams, Evaluate, Evaluator};
+use engine::format::Format;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+
+/// A position labelled with the result of the game it was played in, relative to white
+pub struct Sample {
+    pub bitboard: Bitboard,
+    pub result: f64,
+}
+
+/// Loads a corpus of "notation;result" lines
+pub fn load(path: &str) -> io::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid line {}", number + 1));
+        let (notation, result) = line.split_once(';').ok_or_else(invalid)?;
+        let bitboard = Format::parse(notation).ok_or_else(invalid)?;
+        let result = result.trim().parse().map_err(|_| invalid())?;
+        samples.push(Sample { bitboard, result });
+    }
+    Ok(samples)
+}
+
+/// Maps an evaluation onto the logit of the expected score, which is 1 / (1 + 10^(-k * eval / 400))
+fn logit(k: f64, eval: f64) -> f64 {
+    k * eval * 10f64.ln() / 400.0
+}
+
+/// Evaluates the position relative to white rather than the turn player
+fn white_eval(evaluate: &Evaluate, bitboard: &Bitboard) -> f64 {
+    let eval = evaluate.evaluate(bitboard) as f64;
+    match bitboard.turn {
+        WHITE => eval,
+        _ => -eval,
+    }
+}
+
+/// The logistic loss, or cross entropy, between the results and the scores expected from the
+/// evaluations. It is computed from the logit of the expected score rather than the score itself,
+/// which would round to exactly 0 or 1 for large evaluations.
+fn error(corpus: &[Sample], params: EvalParams, k: f64) -> f64 {
+    let evaluate = Evaluate::new(params);
+    let total: f64 = corpus
+        .iter()
+        .map(|sample| {
+            let logit = logit(k, white_eval(&evaluate, &sample.bitboard));
+            let softplus = logit.max(0.0) + (-logit.abs()).exp().ln_1p();
+            softplus - sample.result * logit
+        })
+        .sum();
+    total / corpus.len() as f64
+}
+
+/// Finds the scaling constant that best maps the initial evaluations onto the results using a
+/// golden section search. The constant is then fixed while the weights are tuned.
+fn scale(corpus: &[Sample], params: EvalParams) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut lower, mut upper) = (0.0001, 10.0);
+    while upper - lower > 0.0001 {
+        let a = upper - ratio * (upper - lower);
+        let b = lower + ratio * (upper - lower);
+        if error(corpus, params, a) < error(corpus, params, b) {
+            upper = b;
+        } else {
+            lower = a;
+        }
+    }
+    (lower + upper) / 2.0
+}
+
+/// Tunes the evaluation weights by minimising the logistic loss over the corpus with a local
+/// search. Each weight is nudged up and down by a step, keeping any change that lowers the error,
+/// and the step is halved whenever no weight can be improved.
+pub fn fit(corpus: &[Sample], params: EvalParams, iterations: usize) -> EvalParams {
+    if corpus.is_empty() {
+        return params;
+    }
+
+    let k = scale(corpus, params);
+    let mut weights = params.weights();
+    let mut best = error(corpus, params, k);
+    let mut step: i16 = 64;
+    println!("[k={:.4}] initial error={:.6}", k, best);
+
+    for iteration in 1..=iterations {
+        let mut improved = false;
+
+        for i in 0..weights.len() {
+            for delta in [step, -step] {
+                let mut candidate = weights;
+                candidate[i] = candidate[i].saturating_add(delta);
+                let candidate_error = error(corpus, EvalParams::from_weights(candidate), k);
+                if candidate_error < best {
+                    best = candidate_error;
+                    weights = candidate;
+                    improved = true;
+                    println!(
+                        "[iteration={} step={}] {}={} error={:.6}",
+                        iteration,
+                        step,
+                        EvalParams::NAMES[i],
+                        weights[i],
+                        best
+                    );
+                    break;
+                }
+            }
+        }
+
+        if !improved {
+            if step == 1 {
+                break;
+            }
+            step /= 2;
+        }
+    }
+
+    EvalParams::from_weights(weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_reduces_error() {
+        // White is always winning when it has more robots in the centre
+        let corpus: Vec<Sample> = [
+            ("8/8/8/3A4/8/8/8/a7 w", 1.0),
+            ("8/8/8/3A4/8/8/8/a7 b", 1.0),
+            ("8/8/8/3a4/8/8/8/A7 w", 0.0),
+            ("8/8/8/3a4/8/8/8/A7 b", 0.0),
+        ]
+        .iter()
+        .map(|(notation, result)| Sample {
+            bitboard: Format::parse(notation).unwrap(),
+            result: *result,
+        })
+        .collect();
+
+        let params =
+            EvalParams { robots: 0, surface_area: 0, stack_power: 0, ..EvalParams::default() };
+        let tuned = fit(&corpus, params, 100);
+        let k = scale(&corpus, params);
+        assert!(tuned.centralisation > 0);
+        assert!(error(&corpus, tuned, k) < error(&corpus, params, k));
+    }
+}
@@ -0,0 +1,82 @@
+use engine::action::Action;
+use engine::bitboard::Bitboard;
+use engine::bitwise::Bitwise;
+use engine::constants::*;
+use engine::evaluate::{EvalParams, Evaluate, Outcome};
+use engine::format::Format;
+use engine::random::Rng;
+use engine::search::Search;
+use engine::tool;
+use engine::transpose::TranspositionTable;
+use std::io::{self, Write};
+
+pub struct Settings {
+    /// Number of self-play games to play
+    pub games: usize,
+    /// Fixed search depth used for each move
+    pub depth: u8,
+    /// Number of random moves played at the start of each game for variety
+    pub random_plies: usize,
+    /// Number of moves after which the game is declared a draw
+    pub max_plies: usize,
+    /// Seed for the random opening moves
+    pub seed: u64,
+    /// Evaluation parameters used by both sides
+    pub params: EvalParams,
+}
+
+/// Quiet positions have no robots in range of a boom, so their static evaluation is meaningful
+fn quiet(bitboard: &Bitboard) -> bool {
+    Bitwise::adj_any(bitboard.board[WHITE]) & bitboard.board[BLACK] == 0
+}
+
+/// Plays a single self-play game, returning the quiet positions seen and the result for white
+fn play(search: &Search<Evaluate>, rng: &mut Rng, settings: &Settings) -> (Vec<String>, f64) {
+    let mut tt = TranspositionTable::new(20);
+    let mut bitboard = Bitboard::new();
+    let mut positions = Vec::new();
+
+    for ply in 0..settings.max_plies {
+        if let Some(outcome) = Evaluate::outcome(&bitboard) {
+            return (positions, tool::result(&bitboard, outcome));
+        }
+
+        let actions = Action::generate(&bitboard, None);
+        if actions.is_empty() {
+            return (positions, tool::result(&bitboard, Outcome::Loss));
+        }
+
+        let action = if ply < settings.random_plies {
+            actions[rng.below(actions.len())].action
+        } else {
+            if quiet(&bitboard) {
+                positions.push(Format::notation(&bitboard));
+            }
+            search.negamax_move(&mut bitboard, &mut tt, settings.depth, LOSS, WIN).action
+        };
+
+        let delta = bitboard.delta(action);
+        bitboard.make(&delta);
+    }
+
+    (positions, 0.5)
+}
+
+/// Plays self-play games and writes every quiet position labelled with the games result for white
+/// as a "notation;result" line. Returns the number of positions written.
+pub fn generate<W: Write>(settings: &Settings, writer: &mut W) -> io::Result<usize> {
+    let search = Search::new(Evaluate::new(settings.params));
+    let mut rng = Rng::new(settings.seed);
+    let mut total = 0;
+
+    for game in 1..=settings.games {
+        let (positions, result) = play(&search, &mut rng, settings);
+        for position in &positions {
+            writeln!(writer, "{};{}", position, result)?;
+        }
+        total += positions.len();
+        eprintln!("[game={}] result={} positions={}", game, result, positions.len());
+    }
+
+    Ok(total)
+}
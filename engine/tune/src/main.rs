@@ -0,0 +1,41 @@
+mod fit;
+mod generate;
+
+use engine::tool::Args;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const USAGE: &str = "Usage:
+    tune generate <corpus> [--games N] [--depth N] [--random-plies N] [--max-plies N] [--seed N] [--params FILE]
+    tune fit <corpus> <output> [--params FILE] [--iterations N]";
+
+fn main() {
+    let args = Args::from_env(USAGE);
+
+    match args.positional.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        ["generate", corpus] => {
+            let settings = generate::Settings {
+                games: args.option("games", 100),
+                depth: args.option("depth", 3),
+                random_plies: args.option("random-plies", 8),
+                max_plies: args.option("max-plies", 200),
+                seed: args.option("seed", 0x5eed),
+                params: args.params(),
+            };
+            let file = File::create(corpus).expect("Failed to create corpus file");
+            let mut writer = BufWriter::new(file);
+            let positions =
+                generate::generate(&settings, &mut writer).expect("Failed to write corpus");
+            writer.flush().expect("Failed to write corpus");
+            println!("Generated {} positions from {} games", positions, settings.games);
+        }
+        ["fit", corpus, output] => {
+            let corpus = fit::load(corpus).expect("Failed to load corpus");
+            let iterations = args.option("iterations", 1000);
+            let tuned = fit::fit(&corpus, args.params(), iterations);
+            tuned.save(output).expect("Failed to save evaluation parameters");
+            println!("Saved tuned parameters to {}", output);
+        }
+        _ => args.exit("Unknown command"),
+    }
+}
@@ -0,0 +1,79 @@
+// Masks that stop shifted frames from wrapping around the edges of the board
+const NOT_FILE_A: u64 = 0xfefefefefefefefe;
+const NOT_FILE_H: u64 = 0x7f7f7f7f7f7f7f7f;
+
+/// Sets every square that is adjacent to any set square, including diagonally
+pub fn adjacent(frame: u64) -> u64 {
+    // Spread sideways first so that spreading up and down also covers the diagonals
+    let row = frame | ((frame << 1) & NOT_FILE_A) | ((frame >> 1) & NOT_FILE_H);
+    (row | (row << 8) | (row >> 8)) & !frame
+}
+
+/// Finds every robot that explodes when the robot at the source booms. Explosions spread to every
+/// robot in the 3x3 square around an exploding robot, so the blast grows outwards from the source
+/// through all occupied squares at once until it stops reaching new robots.
+pub fn flood_fill(occupied: u64, source: u64) -> u64 {
+    let mut blast = source & occupied;
+    loop {
+        let grown = blast | (adjacent(blast) & occupied);
+        if grown == blast {
+            return blast;
+        }
+        blast = grown;
+    }
+}
+
+/// Sets every square within the radius of any set square, including the set squares themselves
+pub fn reach(frame: u64, radius: u8) -> u64 {
+    (0..radius).fold(frame, |reach, _| reach | adjacent(reach))
+}
+
+/// Finds every robot that explodes when the robot at the source booms, where explosions spread to
+/// every robot within the blast radius of an exploding robot. A radius of 1 is the same as
+/// `flood_fill`, which is faster.
+pub fn flood_fill_radius(occupied: u64, source: u64, radius: u8) -> u64 {
+    let mut blast = source & occupied;
+    loop {
+        let grown = blast | (reach(blast, radius) & occupied);
+        if grown == blast {
+            return blast;
+        }
+        blast = grown;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjacent_does_not_wrap() {
+        // The a1 corner only touches a2, b1 and b2
+        assert_eq!(adjacent(1), 0x302);
+        // The h1 corner only touches h2, g1 and g2
+        assert_eq!(adjacent(0x80), 0xc040);
+        // The h8 corner only touches h7, g8 and g7
+        assert_eq!(adjacent(1 << 63), 0x40c0 << 48);
+    }
+
+    #[test]
+    fn flood_fill_follows_chains() {
+        // A diagonal chain from a1 to d4, with a separate robot on h1
+        let occupied = 0x0000000008040201 | 0x80;
+        assert_eq!(flood_fill(occupied, 1), 0x0000000008040201);
+        assert_eq!(flood_fill(occupied, 0x80), 0x80);
+        assert_eq!(flood_fill(occupied, 1 << 20), 0);
+        assert_eq!(flood_fill(u64::MAX, 1), u64::MAX);
+    }
+
+    #[test]
+    fn flood_fill_radius_skips_gaps() {
+        // Robots on a1, c1 and f1 with a gap of one and then two squares between them
+        let occupied = 0x25;
+        assert_eq!(flood_fill_radius(occupied, 1, 1), 1);
+        assert_eq!(flood_fill_radius(occupied, 1, 2), 0x05);
+        assert_eq!(flood_fill_radius(occupied, 1, 3), 0x25);
+        assert_eq!(flood_fill_radius(occupied, 1, 0), 1);
+        assert_eq!(flood_fill_radius(u64::MAX, 1, 1), flood_fill(u64::MAX, 1));
+    }
+}
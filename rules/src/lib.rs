@@ -0,0 +1,17 @@
+//! The rules of Boombots shared by the game server and the engine. Boards are 64 bit frames where
+//! the square on file x and rank y is bit x + 8y, matching `Pos::index` in the core crate and the
+//! square indices of the engine.
+pub mod boom;
+pub mod notation;
+mod position;
+
+pub use position::{Action, Outcome, Position, Stack, Team};
+
+/// The tallest stack of robots allowed on a single square
+pub const MAX_STACK: u8 = 12;
+
+/// The starting position in position notation, with twelve robots for each team
+pub const START: &str = "aa1aa1aa/aa1aa1aa/8/8/8/8/AA1AA1AA/AA1AA1AA w";
+
+/// The squares white starts with a single robot on in `START`
+pub const START_LAYOUT: u64 = 0xdbdb;
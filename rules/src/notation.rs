@@ -0,0 +1,190 @@
+//! Position notation lists ranks from 8 down to 1 separated by slashes, with files listed from a
+//! to h. White stacks are written as 'A'-'L' and black stacks as 'a'-'l' for heights 1-12, runs of
+//! empty squares are written as their length, and the turn player follows as 'w' or 'b'.
+//!
+//! Action notation writes booms as the source square followed by an asterisk, such as "d4*", and
+//! moves as the source and target squares followed by the number of robots moved, such as
+//! "d4d6/2".
+use crate::{Action, Stack, Team};
+
+/// Converts the stacks on each square and the turn player into position notation
+pub fn write(stacks: &[Option<Stack>; 64], turn: Team) -> String {
+    let mut notation = String::new();
+
+    for rank in (0..8).rev() {
+        let mut empty = 0;
+        for file in 0..8 {
+            let stack = match stacks[8 * rank + file] {
+                Some(stack) => stack,
+                None => {
+                    empty += 1;
+                    continue;
+                }
+            };
+            if empty > 0 {
+                notation.push(char::from(b'0' + empty));
+                empty = 0;
+            }
+            let base = if stack.team == Team::White {
+                b'A'
+            } else {
+                b'a'
+            };
+            notation.push(char::from(base + stack.height - 1));
+        }
+        if empty > 0 {
+            notation.push(char::from(b'0' + empty));
+        }
+        if rank > 0 {
+            notation.push('/');
+        }
+    }
+
+    notation.push_str(if turn == Team::White { " w" } else { " b" });
+    notation
+}
+
+/// Parses position notation into the stacks on each square and the turn player, returning None if
+/// the notation is malformed
+pub fn read(notation: &str) -> Option<([Option<Stack>; 64], Team)> {
+    let mut parts = notation.split_whitespace();
+    let ranks: Vec<&str> = parts.next()?.split('/').collect();
+    let turn = match parts.next()? {
+        "w" => Team::White,
+        "b" => Team::Black,
+        _ => return None,
+    };
+    if ranks.len() != 8 || parts.next().is_some() {
+        return None;
+    }
+
+    let mut stacks = [None; 64];
+    for (row, squares) in ranks.iter().enumerate() {
+        let rank = 7 - row;
+        let mut file = 0;
+        for square in squares.chars() {
+            if file >= 8 {
+                return None;
+            }
+            let (team, base) = match square {
+                '1'..='8' => {
+                    file += (square as u8 - b'0') as usize;
+                    continue;
+                }
+                'A'..='L' => (Team::White, b'A'),
+                'a'..='l' => (Team::Black, b'a'),
+                _ => return None,
+            };
+            stacks[8 * rank + file] = Some(Stack {
+                team,
+                height: square as u8 - base + 1,
+            });
+            file += 1;
+        }
+        if file != 8 {
+            return None;
+        }
+    }
+
+    Some((stacks, turn))
+}
+
+/// Converts a square index into its name, such as "a1" for 0 or "h8" for 63
+pub fn square(idx: u8) -> String {
+    format!(
+        "{}{}",
+        char::from(b'a' + idx % 8),
+        char::from(b'1' + idx / 8)
+    )
+}
+
+/// Parses a square name into its index
+pub fn parse_square(square: &str) -> Option<u8> {
+    match square.as_bytes() {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some(8 * (rank - b'1') + (file - b'a')),
+        _ => None,
+    }
+}
+
+/// Converts an action into action notation
+pub fn action(action: &Action) -> String {
+    match action.robots {
+        0 => format!("{}*", square(action.source)),
+        robots => format!(
+            "{}{}/{}",
+            square(action.source),
+            square(action.target),
+            robots
+        ),
+    }
+}
+
+/// Parses action notation into an action, returning None if the notation is malformed
+pub fn parse_action(notation: &str) -> Option<Action> {
+    if let Some(source) = notation.strip_suffix('*') {
+        let source = parse_square(source)?;
+        return Some(Action {
+            source,
+            target: 0,
+            robots: 0,
+        });
+    }
+    let (squares, robots) = notation.split_once('/')?;
+    let robots = robots.parse().ok().filter(|robots| *robots > 0)?;
+    let source = parse_square(squares.get(0..2)?)?;
+    let target = parse_square(squares.get(2..)?)?;
+    Some(Action {
+        source,
+        target,
+        robots,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{START, START_LAYOUT};
+
+    #[test]
+    fn start_round_trips() {
+        let (stacks, turn) = read(START).unwrap();
+        assert_eq!(stacks.iter().flatten().count(), 24);
+        assert_eq!(
+            stacks[0],
+            Some(Stack {
+                team: Team::White,
+                height: 1
+            })
+        );
+        assert_eq!(stacks[2], None);
+        assert_eq!(
+            stacks[63],
+            Some(Stack {
+                team: Team::Black,
+                height: 1
+            })
+        );
+        assert_eq!(write(&stacks, turn), START);
+
+        let white = (0..64).filter(|&idx| stacks[idx].map(|stack| stack.team) == Some(Team::White));
+        assert_eq!(white.fold(0, |layout, idx| layout | 1 << idx), START_LAYOUT);
+    }
+
+    #[test]
+    fn malformed_positions_are_rejected() {
+        assert!(read("8/8/8/8/8/8/8/9 w").is_none());
+        assert!(read("8/8/8/8/8/8/8/7 w").is_none());
+        assert!(read("8/8/8/8/8/8/8/7m w").is_none());
+        assert!(read("8/8/8/8/8/8/8 w").is_none());
+        assert!(read("8/8/8/8/8/8/8/8 x").is_none());
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        for notation in ["d4*", "a1h1/3", "h8h6/12"] {
+            assert_eq!(action(&parse_action(notation).unwrap()), notation);
+        }
+        assert!(parse_action("d4d6/0").is_none());
+        assert!(parse_action("i4*").is_none());
+    }
+}
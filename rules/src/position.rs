@@ -0,0 +1,99 @@
+use crate::notation;
+use crate::START;
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum Team {
+    White,
+    Black,
+}
+
+impl Team {
+    pub fn next(&self) -> Team {
+        match self {
+            Team::White => Team::Black,
+            Team::Black => Team::White,
+        }
+    }
+}
+
+/// A stack of one or more robots belonging to a team
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stack {
+    pub team: Team,
+    pub height: u8,
+}
+
+/// An action by the turn player, which booms the stack at the source when no robots are moved.
+/// The target of a boom is unused and always 0.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Action {
+    pub source: u8,
+    pub target: u8,
+    pub robots: u8,
+}
+
+/// How a finished game ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Winner(Team),
+    Draw,
+}
+
+/// A representation of the game that follows the rules. Implementations only need to describe
+/// their stacks and actions, and the notation, outcome and starting position are derived from
+/// those so that every implementation agrees on them.
+pub trait Position: Sized {
+    /// Whatever is needed to take back an applied action
+    type Undo;
+
+    fn turn(&self) -> Team;
+
+    /// Finds the stack on the square, if there is one
+    fn stack(&self, square: u8) -> Option<Stack>;
+
+    /// Builds the position from the stack on every square
+    fn from_stacks(stacks: &[Option<Stack>; 64], turn: Team) -> Self;
+
+    /// Generates every legal action for the turn player, including every boom
+    fn legal_actions(&self) -> Vec<Action>;
+
+    /// Applies a legal action and passes the turn to the other team
+    fn apply(&mut self, action: &Action) -> Self::Undo;
+
+    /// Takes back the action that returned the undo
+    fn undo(&mut self, undo: Self::Undo);
+
+    fn is_legal(&self, action: &Action) -> bool {
+        self.legal_actions().contains(action)
+    }
+
+    /// Decides whether the game is over, which is once either team has no robots left
+    fn outcome(&self) -> Option<Outcome> {
+        let stacks = self.stacks();
+        let remaining = |team| stacks.iter().flatten().any(|stack| stack.team == team);
+        match (remaining(Team::White), remaining(Team::Black)) {
+            (false, false) => Some(Outcome::Draw),
+            (true, false) => Some(Outcome::Winner(Team::White)),
+            (false, true) => Some(Outcome::Winner(Team::Black)),
+            (true, true) => None,
+        }
+    }
+
+    fn stacks(&self) -> [Option<Stack>; 64] {
+        std::array::from_fn(|square| self.stack(square as u8))
+    }
+
+    fn notation(&self) -> String {
+        notation::write(&self.stacks(), self.turn())
+    }
+
+    fn from_notation(notation: &str) -> Option<Self> {
+        let (stacks, turn) = notation::read(notation)?;
+        Some(Self::from_stacks(&stacks, turn))
+    }
+
+    fn start() -> Self {
+        Self::from_notation(START).expect("The start position is valid")
+    }
+}
@@ -0,0 +1,227 @@
+use crate::conn::{ConnId, Rejection};
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
+use boombots_core::net::{ErrorCode, User};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// How long a session token can be used to handshake after it was issued
+const SESSION_LENGTH: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_NAME_LENGTH: usize = 24;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Account {
+    id: ConnId,
+    name: String,
+    // The argon2 hash of the password in PHC string format, which includes its salt
+    password: String,
+}
+
+/// Registered accounts stored in the embedded database. Accounts are keyed by their lower case
+/// name so that names are unique regardless of case, and indexed by id to resolve session tokens.
+/// Session tokens are the account id and expiry time signed with a secret kept in the database,
+/// so they stay valid across restarts without being stored.
+#[derive(Debug, Clone)]
+pub struct Accounts {
+    db: sled::Db,
+    names: sled::Tree,
+    ids: sled::Tree,
+    secret: Vec<u8>,
+}
+
+impl Accounts {
+    pub fn open(db: &sled::Db) -> sled::Result<Accounts> {
+        let secret = match db.get("session_secret")? {
+            Some(secret) => secret.to_vec(),
+            None => {
+                let secret = rand::random::<[u8; 32]>().to_vec();
+                db.insert("session_secret", secret.as_slice())?;
+                secret
+            }
+        };
+        Ok(Accounts {
+            db: db.clone(),
+            names: db.open_tree("accounts")?,
+            ids: db.open_tree("account_ids")?,
+            secret,
+        })
+    }
+
+    /// Hands out an id that no account or guest has used before, even across restarts
+    pub fn next_id(&self) -> sled::Result<ConnId> {
+        Ok(self.db.generate_id()? as ConnId + 1)
+    }
+
+    /// Checks whether the name belongs to an account, so that guests can not take it
+    pub fn registered(&self, name: &str) -> sled::Result<bool> {
+        self.names.contains_key(name.to_lowercase())
+    }
+
+    pub fn register(&self, name: &str, password: &str) -> Result<User, Rejection> {
+        if name.is_empty()
+            || name.len() > MAX_NAME_LENGTH
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            let message = "Names are 1 to 24 letters, digits, underscores or dashes";
+            return Err(Rejection::new(ErrorCode::InvalidName, message, Some(name.to_string())));
+        }
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            let message = "Passwords are at least 8 characters";
+            return Err(Rejection::new(ErrorCode::InvalidPassword, message, None));
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Passwords can always be hashed")
+            .to_string();
+        let account = Account {
+            id: self.next_id().map_err(storage)?,
+            name: name.to_string(),
+            password: hash,
+        };
+        let json = serde_json::to_vec(&account).expect("Accounts always serialize");
+
+        // Only insert the account if the name is still free, which keeps names unique under races
+        let key = name.to_lowercase();
+        match self
+            .names
+            .compare_and_swap(&key, None as Option<&[u8]>, Some(json))
+            .map_err(storage)?
+        {
+            Ok(()) => {
+                self.ids.insert(account.id.to_be_bytes(), key.as_bytes()).map_err(storage)?;
+                Ok(User::new(account.id, account.name))
+            }
+            Err(_) => Err(Rejection::new(
+                ErrorCode::NameTaken,
+                "The name is already registered",
+                Some(name.to_string()),
+            )),
+        }
+    }
+
+    pub fn login(&self, name: &str, password: &str) -> Result<User, Rejection> {
+        let invalid =
+            || Rejection::new(ErrorCode::InvalidCredentials, "Wrong name or password", None);
+        let account = self.account(&name.to_lowercase())?.ok_or_else(invalid)?;
+        let hash = PasswordHash::new(&account.password).expect("Stored hashes are valid");
+        match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Ok(User::new(account.id, account.name)),
+            Err(_) => Err(invalid()),
+        }
+    }
+
+    /// Issues a session token for the account which expires after the session length
+    pub fn issue(&self, user: &User) -> String {
+        let expires = now() + SESSION_LENGTH.as_secs();
+        let payload = format!("{}.{}", user.id, expires);
+        format!("{}.{}", payload, hex::encode(self.sign(&payload)))
+    }
+
+    /// Finds the account a session token was issued for, as long as it has not expired
+    pub fn authenticate(&self, token: &str) -> Result<User, Rejection> {
+        let invalid =
+            || Rejection::new(ErrorCode::InvalidToken, "The session token is not valid", None);
+        let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+        let (id, expires) = payload.split_once('.').ok_or_else(invalid)?;
+        let id: ConnId = id.parse().map_err(|_| invalid())?;
+        let expires: u64 = expires.parse().map_err(|_| invalid())?;
+        if expires < now() {
+            let message = "The session token has expired";
+            return Err(Rejection::new(ErrorCode::InvalidToken, message, None));
+        }
+
+        let name = self.ids.get(id.to_be_bytes()).map_err(storage)?.ok_or_else(invalid)?;
+        let account = self.account(&String::from_utf8_lossy(&name))?.ok_or_else(invalid)?;
+        Ok(User::new(account.id, account.name))
+    }
+
+    fn account(&self, key: &str) -> Result<Option<Account>, Rejection> {
+        let json = self.names.get(key).map_err(storage)?;
+        Ok(json.map(|json| serde_json::from_slice(&json).expect("Stored accounts are valid")))
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any length")
+    }
+
+    fn sign(&self, payload: &str) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("The clock is after 1970").as_secs()
+}
+
+fn storage(error: sled::Error) -> Rejection {
+    println!("Account storage error: {}", error);
+    Rejection::new(ErrorCode::StorageError, "The account could not be stored", None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open() -> Accounts {
+        Accounts::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    fn code(result: Result<User, Rejection>) -> ErrorCode {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn register_and_login() {
+        let accounts = open();
+        let user = accounts.register("Ada", "correct horse").unwrap();
+        assert_eq!(accounts.login("ada", "correct horse").unwrap().id, user.id);
+        assert_eq!(code(accounts.login("Ada", "wrong horse")), ErrorCode::InvalidCredentials);
+        assert_eq!(code(accounts.login("Bob", "correct horse")), ErrorCode::InvalidCredentials);
+        assert!(accounts.registered("ADA").unwrap());
+    }
+
+    #[test]
+    fn names_are_unique_and_valid() {
+        let accounts = open();
+        accounts.register("Ada", "correct horse").unwrap();
+        assert_eq!(code(accounts.register("ADA", "battery staple")), ErrorCode::NameTaken);
+        assert_eq!(
+            code(accounts.register("Ada Lovelace", "battery staple")),
+            ErrorCode::InvalidName
+        );
+        assert_eq!(code(accounts.register("", "battery staple")), ErrorCode::InvalidName);
+        assert_eq!(code(accounts.register("Bob", "short")), ErrorCode::InvalidPassword);
+    }
+
+    #[test]
+    fn session_tokens_are_signed() {
+        let accounts = open();
+        let user = accounts.register("Ada", "correct horse").unwrap();
+        let token = accounts.issue(&user);
+        assert_eq!(accounts.authenticate(&token).unwrap().name, "Ada");
+
+        // Changing the id in the token breaks its signature
+        let forged = format!("{}{}", user.id + 1, &token[user.id.to_string().len()..]);
+        assert_eq!(code(accounts.authenticate(&forged)), ErrorCode::InvalidToken);
+        assert_eq!(code(accounts.authenticate("nonsense")), ErrorCode::InvalidToken);
+
+        // Tokens from a database with another secret are not accepted
+        assert_eq!(code(open().authenticate(&token)), ErrorCode::InvalidToken);
+    }
+}
@@ -1,41 +1,153 @@
 use crate::auth::*;
-use std::collections::HashMap;
+use crate::game::Ratings;
+use boombots_core::net::{ErrorCode, Event, Session, User};
 use tokio::sync::mpsc;
+use tokio::task;
 
 #[derive(Debug)]
 pub struct Auth {
-    // Connections
-    connections_count: u32,
-    connections: HashMap<ConnId, Connection>,
+    identities: Identities,
     // Channels
     sender: AuthSender,
     receiver: AuthReceiver,
 }
 
+/// What handshakes are checked against. Each handshake runs on the blocking thread pool with its
+/// own copy, since hashing passwords is slow enough to hold up every other handshake otherwise.
+#[derive(Debug, Clone)]
+struct Identities {
+    accounts: Accounts,
+    ratings: Ratings,
+    sessions: Sessions,
+}
+
 impl Auth {
-    pub fn new() -> Self {
+    pub fn new(db: &sled::Db, sessions: Sessions) -> Self {
         let (sender, receiver) = mpsc::unbounded_channel::<AuthEvent>();
-        Self { connections: HashMap::new(), connections_count: 0, sender, receiver }
+        let accounts = Accounts::open(db).expect("Failed to open the accounts database");
+        let ratings = Ratings::open(db).expect("Failed to open the ratings database");
+        Self { identities: Identities { accounts, ratings, sessions }, sender, receiver }
     }
 
     pub fn sender(&self) -> AuthSender {
         self.sender.clone()
     }
 
-    pub fn next_id(&mut self) -> ConnId {
-        self.connections_count += 1;
-        self.connections_count
-    }
-
     pub async fn listen(&mut self) {
         while let Some(event) = self.receiver.recv().await {
             match event {
-                AuthEvent::Handshake(name, sender, handshake_sender) => {
-                    let id = self.next_id();
-                    let conn = Connection::new(id, name, sender);
-                    handshake_sender.send(conn).unwrap();
+                AuthEvent::Handshake(handshake, sender, handshake_sender) => {
+                    let identities = self.identities.clone();
+                    tokio::spawn(async move {
+                        let handshaked = task::spawn_blocking(move || {
+                            identities.handshake(handshake).and_then(|h| identities.claim(h))
+                        });
+                        let Ok(conn) = handshaked.await else {
+                            return println!("A handshake failed to finish");
+                        };
+                        let conn = conn.map(|(user, session)| {
+                            let conn = Connection::new(user.id, user.name.clone(), sender);
+                            conn.send(&Event::Welcome(user.clone()));
+                            if let Some(token) = session {
+                                conn.send(&Event::Session(Session { user, token }));
+                            }
+                            conn
+                        });
+                        let _ = handshake_sender.send(conn);
+                    });
                 }
             }
         }
     }
 }
+
+impl Identities {
+    // Attaches the socket to the user of a handshake, unless the account is already connected from
+    // another socket
+    fn claim(
+        &self,
+        handshaked: (User, Option<String>),
+    ) -> Result<(User, Option<String>), Rejection> {
+        match self.sessions.claim(handshaked.0.id) {
+            true => Ok(handshaked),
+            false => {
+                let message = "The account is already connected from another socket";
+                Err(Rejection::new(ErrorCode::AlreadyConnected, message, None))
+            }
+        }
+    }
+
+    // Identifies the user of a handshake, along with a new session token for account holders
+    fn handshake(&self, handshake: Handshake) -> Result<(User, Option<String>), Rejection> {
+        let user = match handshake {
+            Handshake::Guest(name) => {
+                let storage = |_| Rejection::new(ErrorCode::StorageError, "Failed to join", None);
+                if self.accounts.registered(&name).map_err(storage)? {
+                    let message = "The name belongs to a registered account";
+                    return Err(Rejection::new(ErrorCode::NameTaken, message, Some(name)));
+                }
+                let id = self.accounts.next_id().map_err(storage)?;
+                return Ok((User::new(id, name), None));
+            }
+            Handshake::Register(credentials) => {
+                self.accounts.register(&credentials.name, &credentials.password)?
+            }
+            Handshake::Login(credentials) => {
+                self.accounts.login(&credentials.name, &credentials.password)?
+            }
+            Handshake::Token(token) => self.accounts.authenticate(&token)?,
+        };
+        // Accounts start with the rating of a new player, including those registered before ratings
+        if let Err(e) = self.ratings.add(&user) {
+            println!("Failed to add a rating: {}", e);
+            return Err(Rejection::new(ErrorCode::StorageError, "Failed to join", None));
+        }
+        let token = self.accounts.issue(&user);
+        Ok((user, Some(token)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials { name: "Ada".to_string(), password: "correct horse".to_string() }
+    }
+
+    fn code(result: Result<(User, Option<String>), Rejection>) -> ErrorCode {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn accounts_are_connected_from_one_socket_at_a_time() {
+        let sessions = Sessions::default();
+        let auth =
+            Auth::new(&sled::Config::new().temporary(true).open().unwrap(), sessions.clone());
+        let auth = &auth.identities;
+        let register = auth.handshake(Handshake::Register(credentials()));
+        let (user, _) = auth.claim(register.unwrap()).unwrap();
+        let login = auth.handshake(Handshake::Login(credentials()));
+        assert_eq!(code(auth.claim(login.unwrap())), ErrorCode::AlreadyConnected);
+        // Guests have their own ids, so they are never connected twice
+        let guest = auth.handshake(Handshake::Guest("Bob".to_string()));
+        assert!(auth.claim(guest.unwrap()).is_ok());
+        sessions.release(user.id);
+        let login = auth.handshake(Handshake::Login(credentials()));
+        assert_eq!(auth.claim(login.unwrap()).unwrap().0.id, user.id);
+    }
+
+    #[tokio::test]
+    async fn handshakes_are_answered_from_their_own_task() {
+        let mut auth =
+            Auth::new(&sled::Config::new().temporary(true).open().unwrap(), Sessions::default());
+        let sender = auth.sender();
+        tokio::spawn(async move { auth.listen().await });
+
+        let (socket, _) = mpsc::unbounded_channel();
+        let (handshake_sender, handshake) = tokio::sync::oneshot::channel();
+        let register = Handshake::Register(credentials());
+        sender.send(AuthEvent::Handshake(register, socket, handshake_sender)).unwrap();
+        assert_eq!(handshake.await.unwrap().unwrap().user.name, "Ada");
+    }
+}
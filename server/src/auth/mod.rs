@@ -1,14 +1,25 @@
 use crate::conn::*;
+use boombots_core::net::Credentials;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+mod accounts;
 mod handler;
+pub use accounts::Accounts;
 pub use handler::Auth;
 
 pub type AuthSender = UnboundedSender<AuthEvent>;
 pub type AuthReceiver = UnboundedReceiver<AuthEvent>;
-pub type HandshakeSender = tokio::sync::oneshot::Sender<Connection>;
+pub type HandshakeSender = tokio::sync::oneshot::Sender<Result<Connection, Rejection>>;
+
+#[derive(Debug)]
+pub enum Handshake {
+    Guest(String),
+    Register(Credentials),
+    Login(Credentials),
+    Token(String),
+}
 
 #[derive(Debug)]
 pub enum AuthEvent {
-    Handshake(String, ConnectionSender, HandshakeSender),
+    Handshake(Handshake, ConnectionSender, HandshakeSender),
 }
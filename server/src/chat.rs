@@ -0,0 +1,121 @@
+use crate::conn::{ConnId, Connection, Rejection};
+use boombots_core::net::{ChatMessage, ChatScope, ErrorCode, Event, User};
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const MAX_MESSAGE_LENGTH: usize = 200;
+// How many messages a user can send within the rate limit window
+const RATE_LIMIT: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+// How many of the latest messages are replayed to people who join a chat
+const HISTORY_LENGTH: usize = 20;
+
+/// The chat state of a connection, which moves with it between the lobby and games
+#[derive(Debug, Default)]
+pub struct Chatter {
+    muted: HashSet<ConnId>,
+    // When each message within the rate limit window was sent, oldest first
+    sent: VecDeque<Instant>,
+}
+
+impl Chatter {
+    /// Checks that a message can be sent now, counting it towards the rate limit if it can
+    pub fn send(&mut self, text: &str, now: Instant) -> Result<(), Rejection> {
+        if text.trim().is_empty() || text.chars().count() > MAX_MESSAGE_LENGTH {
+            let message = "Chat messages are 1 to 200 characters";
+            return Err(Rejection::new(ErrorCode::InvalidMessage, message, None));
+        }
+        while self.sent.front().is_some_and(|sent| now.duration_since(*sent) >= RATE_LIMIT_WINDOW) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= RATE_LIMIT {
+            let message = "Too many chat messages, wait a moment";
+            return Err(Rejection::new(ErrorCode::RateLimited, message, None));
+        }
+        self.sent.push_back(now);
+        Ok(())
+    }
+
+    pub fn mute(&mut self, id: ConnId, muted: bool) {
+        match muted {
+            true => self.muted.insert(id),
+            false => self.muted.remove(&id),
+        };
+    }
+
+    pub fn hears(&self, id: ConnId) -> bool {
+        !self.muted.contains(&id)
+    }
+}
+
+/// Sends a message to every reader that has not muted its sender
+pub fn deliver<'a>(message: &ChatMessage, readers: impl Iterator<Item = &'a Connection>) {
+    let readers = readers.filter(|reader| reader.chat.hears(message.user.id));
+    crate::conn::broadcast(&Event::Chat(message.clone()), readers);
+}
+
+/// A chat with a short history of its latest messages
+#[derive(Debug)]
+pub struct Chat {
+    scope: ChatScope,
+    history: VecDeque<ChatMessage>,
+}
+
+impl Chat {
+    pub fn new(scope: ChatScope) -> Chat {
+        Chat { scope, history: VecDeque::new() }
+    }
+
+    /// Adds a message to the history, which then needs to be delivered to the readers
+    pub fn post(&mut self, user: User, text: String) -> ChatMessage {
+        let message = ChatMessage { scope: self.scope, user, text };
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(message.clone());
+        message
+    }
+
+    /// Replays the history to someone who joined, leaving out the users they muted
+    pub fn replay(&self, conn: &Connection) {
+        let history: Vec<ChatMessage> = self
+            .history
+            .iter()
+            .filter(|message| conn.chat.hears(message.user.id))
+            .cloned()
+            .collect();
+        if !history.is_empty() {
+            conn.send(&Event::ChatHistory(history));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_capped_and_rate_limited() {
+        let mut chatter = Chatter::default();
+        let now = Instant::now();
+        let code = |result: Result<(), Rejection>| result.unwrap_err().code;
+        assert_eq!(code(chatter.send(" ", now)), ErrorCode::InvalidMessage);
+        assert_eq!(code(chatter.send(&"a".repeat(201), now)), ErrorCode::InvalidMessage);
+        for _ in 0..RATE_LIMIT {
+            chatter.send("hi", now).unwrap();
+        }
+        assert_eq!(code(chatter.send("hi", now)), ErrorCode::RateLimited);
+        chatter.send("hi", now + RATE_LIMIT_WINDOW).unwrap();
+    }
+
+    #[test]
+    fn history_keeps_the_latest_messages() {
+        let mut chat = Chat::new(ChatScope::Lobby);
+        for i in 0..HISTORY_LENGTH + 5 {
+            chat.post(User::new(1, "ada".to_string()), i.to_string());
+        }
+        assert_eq!(chat.history.len(), HISTORY_LENGTH);
+        assert_eq!(chat.history[0].text, "5");
+    }
+}
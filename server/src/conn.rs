@@ -1,41 +1,106 @@
+use crate::chat::Chatter;
 use crate::{auth::*, game::*, lobby::*};
 use anyhow::{anyhow, Result};
-use boombots_core::net::{Event, User};
-use futures_util::stream::{SplitSink, StreamExt};
+use boombots_core::net::{ErrorCode, Event, OfferKind, User};
+use futures_util::sink::SinkExt;
+use futures_util::stream::{SplitStream, StreamExt};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpStream;
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 
 pub type ConnId = u32;
 pub type TcpSocketStream = WebSocketStream<TcpStream>;
-pub type ConnectionSender = SplitSink<TcpSocketStream, Message>;
+pub type ConnectionSender = mpsc::UnboundedSender<Message>;
+pub type ConnectionReceiver = SplitStream<TcpSocketStream>;
+
+/// An event from a connection that was rejected, which is answered with an error event
+#[derive(Debug)]
+pub struct Rejection {
+    pub code: ErrorCode,
+    pub message: &'static str,
+    pub context: Option<String>,
+}
+
+impl Rejection {
+    pub fn new(code: ErrorCode, message: &'static str, context: Option<String>) -> Self {
+        Self { code, message, context }
+    }
+
+    pub fn event(self) -> Event {
+        Event::error(self.code, self.message, self.context)
+    }
+}
+
+/// The ids that a socket is attached to, shared between the tasks that attach sockets so that
+/// every account, guest and seat is only used by one socket at a time. Otherwise the sockets would
+/// share one id, and whichever closed first would take the other's place in the lobby or game.
+#[derive(Clone, Debug, Default)]
+pub struct Sessions(Arc<Mutex<HashSet<ConnId>>>);
+
+impl Sessions {
+    /// Attaches a socket to the id, returning false if another socket is already attached
+    pub fn claim(&self, id: ConnId) -> bool {
+        self.0.lock().expect("Sessions are never poisoned").insert(id)
+    }
+
+    pub fn release(&self, id: ConnId) {
+        self.0.lock().expect("Sessions are never poisoned").remove(&id);
+    }
+}
 
 #[derive(Debug)]
 pub struct Connection {
     pub user: User,
     pub sender: ConnectionSender,
+    pub chat: Chatter,
 }
 
 impl Connection {
     pub fn new(id: ConnId, name: String, sender: ConnectionSender) -> Self {
-        Self { sender, user: User::new(id, name) }
+        Self { sender, user: User::new(id, name), chat: Chatter::default() }
     }
 
-    fn try_deserialize(
-        message: Result<Message, tokio_tungstenite::tungstenite::Error>,
-    ) -> Result<Event> {
-        match message {
-            Err(_) => Err(anyhow!("Failed to read message from socket")),
-            Ok(message) => match message.to_text() {
-                Err(_) => Err(anyhow!("Failed to deserialize event to text")),
-                Ok(text) => match serde_json::from_str(text) {
-                    Ok(event) => Ok(event),
-                    Err(_) => Err(anyhow!("Failed to deserialize event text into frame: {}", text)),
-                },
+    // Queues an event to be written to the socket of the connection
+    pub fn send(&self, event: &Event) {
+        send(&self.sender, event);
+    }
+
+    fn try_deserialize(message: &Message) -> Result<Event> {
+        match message.to_text() {
+            Err(_) => Err(anyhow!("Failed to deserialize event to text")),
+            Ok(text) => match serde_json::from_str(text) {
+                Ok(event) => Ok(event),
+                Err(_) => Err(anyhow!("Failed to deserialize event text into frame")),
             },
         }
     }
 
+    // Waits for the next event from the socket, returning None once the socket closes. Messages
+    // that are not events are rejected with an error and skipped.
+    async fn next_event(
+        receiver: &mut ConnectionReceiver,
+        sender: &ConnectionSender,
+    ) -> Option<Event> {
+        while let Some(message) = receiver.next().await {
+            let message = match message {
+                Ok(Message::Close(_)) | Err(_) => return None,
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => continue,
+                Ok(message) => message,
+            };
+            match Connection::try_deserialize(&message) {
+                Ok(event) => return Some(event),
+                Err(e) => {
+                    let context = message.to_text().ok().map(str::to_string);
+                    let error = Event::error(ErrorCode::MalformedEvent, &e.to_string(), context);
+                    send(sender, &error);
+                }
+            }
+        }
+        None
+    }
+
     // Handles the entire lifetime of a users socket connection. The connection must first perform
     // a handshake which validates any tokens. Subsequent messages will then be tagged with the
     // conenctions id and forwarded to other async tasks for processing.
@@ -44,71 +109,182 @@ impl Connection {
         lobby: LobbySender,
         games: GamePoolSender,
         auth: AuthSender,
+        sessions: Sessions,
     ) {
-        let (sender, mut receiver) = stream.split();
-        let mut id: Option<ConnId> = None;
-
-        // The user first needs to authenticate via a handshake in which they are assigned an ID
-        if let Some(msg) = receiver.next().await {
-            if let Ok(event) = Connection::try_deserialize(msg) {
-                if let Event::Handshake(name) = event {
-                    // Try perform a handshake to authenticate the user
-                    let (handshake_sender, handshake_receiver) = oneshot::channel::<Connection>();
-                    auth.send(AuthEvent::Handshake(name, sender, handshake_sender))
-                        .expect("Failed to send auth handshake event");
-                    // Wait for the handshake response from the auth task
-                    match handshake_receiver.await {
-                        Ok(handshaked) => {
-                            // Remember the connection id and move the connection to the lobby
-                            id = Some(handshaked.user.id);
-                            lobby
-                                .send(LobbyEvent::Join(handshaked))
-                                .expect("Failed to send lobby join event");
-                        }
-                        Err(_) => {
-                            // TODO: Return an error
-                            println!("Error handshaking");
-                            return;
+        let (mut sink, mut receiver) = stream.split();
+        let (sender, mut outgoing) = mpsc::unbounded_channel::<Message>();
+
+        // Messages are written to the socket by their own task, so that any task holding a sender
+        // can send events to the user without waiting on the socket
+        tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                if sink.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // The user first needs to authenticate via a handshake in which they are assigned an ID,
+        // either as a guest or with an account, and can keep trying until one succeeds
+        let id = loop {
+            let handshake = match Connection::next_event(&mut receiver, &sender).await {
+                Some(Event::Handshake(name)) => Handshake::Guest(name),
+                Some(Event::Register(credentials)) => Handshake::Register(credentials),
+                Some(Event::Login(credentials)) => Handshake::Login(credentials),
+                Some(Event::Authenticate(token)) => Handshake::Token(token),
+                Some(Event::Resume(token)) => {
+                    // Reattach the socket to the seat in the game the token was given out for
+                    let (resume_sender, resume_receiver) = oneshot::channel();
+                    games
+                        .send(GamePoolEvent::Resume(token, sender.clone(), resume_sender))
+                        .unwrap();
+                    match resume_receiver.await {
+                        Ok(Ok(id)) => break id,
+                        Ok(Err(rejection)) => {
+                            send(&sender, &rejection.event());
+                            continue;
                         }
+                        Err(_) => return,
                     }
-                } else {
-                    // TODO: Return an error here in the future
-                    return;
                 }
-            }
-        }
-        // Deserialize the event from the network
+                Some(_) => {
+                    let error = Event::error(
+                        ErrorCode::HandshakeRequired,
+                        "The first event must be a handshake or resume",
+                        None,
+                    );
+                    send(&sender, &error);
+                    continue;
+                }
+                None => return,
+            };
 
-        // Ensure that we have an id now
-        if id.is_none() {
-            return;
-        }
-        let id = id.expect("id was none");
+            // Try perform a handshake to authenticate the user
+            let (handshake_sender, handshake_receiver) = oneshot::channel();
+            auth.send(AuthEvent::Handshake(handshake, sender.clone(), handshake_sender))
+                .expect("Failed to send auth handshake event");
+            // Wait for the handshake response from the auth task
+            match handshake_receiver.await {
+                Ok(Ok(handshaked)) => {
+                    // Remember the connection id and move the connection to the lobby
+                    let id = handshaked.user.id;
+                    lobby
+                        .send(LobbyEvent::Join(handshaked))
+                        .expect("Failed to send lobby join event");
+                    break id;
+                }
+                Ok(Err(rejection)) => send(&sender, &rejection.event()),
+                Err(_) => return,
+            }
+        };
 
         // Then we can delegete the users events
         // Wait for the next event from the connections socket
-        while let Some(msg) = receiver.next().await {
-            if let Ok(event) = Connection::try_deserialize(msg) {
-                match event {
-                    Event::SendChallenge(mut challenge) => {
-                        challenge.source = Some(id);
-                        lobby.send(LobbyEvent::SendChallenge(challenge)).unwrap();
-                    }
-                    Event::AcceptChallenge(mut challenge) => {
-                        // Tag the challenge as accepted by the current connection's id
-                        challenge.target = Some(id);
-                        lobby.send(LobbyEvent::AcceptChallenge(challenge)).unwrap();
-                    }
-                    Event::GameAction(action) => {
-                        games.send(GamePoolEvent::GameAction(id, action)).unwrap();
-                    }
-                    Event::Handshake(_name) => println!("Already performed a handshake"),
-                    _ => println!("Unknown event"),
+        while let Some(event) = Connection::next_event(&mut receiver, &sender).await {
+            match event {
+                Event::SendChallenge(mut challenge) => {
+                    challenge.source = Some(id);
+                    lobby.send(LobbyEvent::SendChallenge(challenge, sender.clone())).unwrap();
+                }
+                Event::AcceptChallenge(mut challenge) => {
+                    // Tag the challenge as accepted by the current connection's id
+                    challenge.target = Some(id);
+                    lobby.send(LobbyEvent::AcceptChallenge(challenge, sender.clone())).unwrap();
+                }
+                Event::DeclineChallenge(challenge) => {
+                    lobby
+                        .send(LobbyEvent::DeclineChallenge(id, challenge, sender.clone()))
+                        .unwrap();
+                }
+                Event::CancelChallenge(challenge) => {
+                    lobby.send(LobbyEvent::CancelChallenge(id, challenge, sender.clone())).unwrap();
+                }
+                Event::Resign => games.send(GamePoolEvent::Resign(id)).unwrap(),
+                Event::OfferDraw => games.send(GamePoolEvent::Offer(id, OfferKind::Draw)).unwrap(),
+                Event::RequestTakeback => {
+                    games.send(GamePoolEvent::Offer(id, OfferKind::Takeback)).unwrap();
+                }
+                Event::AcceptDraw | Event::DeclineDraw => {
+                    let accepted = matches!(event, Event::AcceptDraw);
+                    games.send(GamePoolEvent::Answer(id, OfferKind::Draw, accepted)).unwrap();
+                }
+                Event::AcceptTakeback | Event::DeclineTakeback => {
+                    let accepted = matches!(event, Event::AcceptTakeback);
+                    games.send(GamePoolEvent::Answer(id, OfferKind::Takeback, accepted)).unwrap();
+                }
+                Event::OfferRematch => {
+                    games.send(GamePoolEvent::Offer(id, OfferKind::Rematch)).unwrap();
+                }
+                Event::AcceptRematch | Event::DeclineRematch => {
+                    let accepted = matches!(event, Event::AcceptRematch);
+                    games.send(GamePoolEvent::Answer(id, OfferKind::Rematch, accepted)).unwrap();
+                }
+                Event::SendChat(chat) => {
+                    lobby.send(LobbyEvent::Chat(id, chat.scope, chat.text)).unwrap();
+                }
+                Event::Mute(target) => lobby.send(LobbyEvent::Mute(id, target, true)).unwrap(),
+                Event::Unmute(target) => lobby.send(LobbyEvent::Mute(id, target, false)).unwrap(),
+                Event::ListGames => games.send(GamePoolEvent::ListGames(sender.clone())).unwrap(),
+                Event::Spectate(game) => {
+                    lobby.send(LobbyEvent::Spectate(id, game, sender.clone())).unwrap();
+                }
+                Event::LeaveGame => games.send(GamePoolEvent::Leave(id)).unwrap(),
+                Event::Resync => games.send(GamePoolEvent::Resync(id)).unwrap(),
+                Event::JoinQueue(request) => {
+                    lobby.send(LobbyEvent::JoinQueue(id, request, sender.clone())).unwrap();
+                }
+                Event::LeaveQueue => lobby.send(LobbyEvent::LeaveQueue(id)).unwrap(),
+                Event::Leaderboard => {
+                    lobby.send(LobbyEvent::Leaderboard(sender.clone())).unwrap();
+                }
+                Event::RatingHistory(account) => {
+                    lobby.send(LobbyEvent::RatingHistory(account, sender.clone())).unwrap();
+                }
+                Event::GameAction(action) => {
+                    games.send(GamePoolEvent::GameAction(id, action)).unwrap();
+                }
+                Event::Handshake(_)
+                | Event::Register(_)
+                | Event::Login(_)
+                | Event::Authenticate(_)
+                | Event::Resume(_) => {
+                    let error = Event::error(
+                        ErrorCode::AlreadyHandshaked,
+                        "Already performed a handshake",
+                        None,
+                    );
+                    send(&sender, &error);
+                }
+                _ => {
+                    let error = Event::error(
+                        ErrorCode::UnexpectedEvent,
+                        "Only the server sends this event",
+                        None,
+                    );
+                    send(&sender, &error);
                 }
             }
         }
 
-        // Clean up when they disconnect
+        // Clean up when they disconnect, which gives players in games a grace period to resume. The
+        // id is only released afterwards, so the events of a new socket for it come after these.
         lobby.send(LobbyEvent::Disconnect(id)).unwrap();
+        games.send(GamePoolEvent::Disconnect(id, sender)).unwrap();
+        sessions.release(id);
+    }
+}
+
+// Queues an event to be written to a socket. Events sent after the socket has closed are dropped, as
+// the connection is cleaned up once its reader sees the socket close.
+pub fn send(sender: &ConnectionSender, event: &Event) {
+    let json = serde_json::to_string(event).expect("Events always serialize");
+    let _ = sender.send(Message::Text(json));
+}
+
+// Queues an event to be written to the sockets of every connection, serializing it only once
+pub fn broadcast<'a>(event: &Event, connections: impl Iterator<Item = &'a Connection>) {
+    let json = serde_json::to_string(event).expect("Events always serialize");
+    for conn in connections {
+        let _ = conn.sender.send(Message::Text(json.clone()));
     }
 }
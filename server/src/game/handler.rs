@@ -1,12 +1,25 @@
 use super::*;
+use crate::chat::{self, Chat};
+use crate::conn::ConnectionSender;
+use crate::conn::Rejection;
+use crate::game::ratings::Ratings;
+use crate::game::store::{GameRecord, GameStore};
 use crate::lobby::*;
 use boombots_core::{
-    net::{Event, GameInfo},
-    GameState,
+    net::{
+        ChatScope, EndReason, ErrorCode, Event, GameInfo, GameResult, GameUpdate, Offer, OfferKind,
+        OfferStatus, TimeControl, User,
+    },
+    GameState, Team,
 };
-use futures_util::SinkExt;
+use boombots_rules::{notation, Action, Outcome, Position};
+use std::collections::HashMap;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::time::{self, Instant};
+
+// How long a seated player can stay disconnected before they forfeit the game
+const GRACE_PERIOD: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 pub struct Game {
@@ -14,23 +27,109 @@ pub struct Game {
     sender: GameSender,
     receiver: GameReceiver,
     players: Vec<Connection>,
+    // The team each seated player controls, which is settled when the game is created. Anyone
+    // else only watches.
+    seats: HashMap<ConnId, Team>,
+    // When each seated player that is currently disconnected lost their connection
+    disconnected: HashMap<ConnId, Instant>,
+    grace: Duration,
     lobby: Option<LobbySender>,
+    pool: Option<GamePoolSender>,
+    white: User,
+    black: User,
+    // The starting position and every action since, which are saved to the store as they change
+    start: GameState,
+    actions: Vec<String>,
+    // The state before each action, which takebacks return to
+    history: Vec<GameState>,
     gamestate: GameState,
+    // The latest offer, as long as it has not been answered and no action was played since
+    offer: Option<Offer>,
+    result: Option<GameResult>,
+    store: Option<GameStore>,
+    // The ratings the result is recorded in, which casual games do not have
+    ratings: Option<Ratings>,
+    players_chat: Chat,
+    spectators_chat: Chat,
     started: bool,
+    // The time control of games from the matchmaking queue
+    time_control: Option<TimeControl>,
 }
 
 impl Game {
-    pub fn new(id: GameId) -> Game {
+    // Creates a game from the starting position, which starts once both seated players have joined
+    pub fn new(id: GameId, gamestate: GameState, white: User, black: User) -> Game {
         let (sender, receiver) = mpsc::unbounded_channel::<GameEvent>();
         Game {
             id,
             sender,
             receiver,
             players: Vec::new(),
+            seats: HashMap::from([(white.id, Team::White), (black.id, Team::Black)]),
+            disconnected: HashMap::new(),
+            grace: GRACE_PERIOD,
             lobby: None,
-            gamestate: GameState::new(),
+            pool: None,
+            white,
+            black,
+            start: gamestate,
+            actions: Vec::new(),
+            history: Vec::new(),
+            gamestate,
+            offer: None,
+            result: None,
+            store: None,
+            ratings: None,
+            players_chat: Chat::new(ChatScope::Players),
+            spectators_chat: Chat::new(ChatScope::Spectators),
             started: false,
+            time_control: None,
+        }
+    }
+
+    // Rebuilds a game that was saved before the server restarted by replaying its actions. Both
+    // players are seated but disconnected, and forfeit unless they resume within the grace period.
+    // Records whose actions can not be replayed are rejected with the reason.
+    pub fn restore(record: GameRecord) -> Result<Game, String> {
+        let mut game = Game::new(record.id, record.start, record.white, record.black);
+        for notation in &record.actions {
+            let action = notation::parse_action(notation)
+                .filter(|action| game.gamestate.is_legal(action))
+                .ok_or_else(|| format!("the stored action {} is not legal", notation))?;
+            game.history.push(game.gamestate);
+            game.gamestate.apply(&action);
         }
+        game.actions = record.actions;
+        game.result = record.result;
+        game.time_control = record.time_control;
+        game.started = true;
+        let now = Instant::now();
+        game.disconnected = HashMap::from([(game.white.id, now), (game.black.id, now)]);
+        Ok(game)
+    }
+
+    // Makes the game rated, so that its result updates the ratings of both players
+    pub fn rated(mut self, ratings: Ratings) -> Game {
+        self.ratings = Some(ratings);
+        self
+    }
+
+    // Saves the game to the store whenever it changes
+    pub fn with_store(mut self, store: GameStore) -> Game {
+        self.store = Some(store);
+        self
+    }
+
+    // Keeps the time control the players were paired by, which the game is not clocked by yet
+    pub fn with_time_control(mut self, time_control: Option<TimeControl>) -> Game {
+        self.time_control = time_control;
+        self
+    }
+
+    // Changes how long seated players can stay disconnected before they forfeit
+    pub fn with_grace(mut self, grace: Duration) -> Game {
+        self.grace = grace;
+        self
     }
 
     // Make a clone of the game sender which can be used to send events to the game
@@ -38,12 +137,24 @@ impl Game {
         self.sender.clone()
     }
 
-    // Add a lobby sender to the game pool to allow communication back to the lobby
-    pub fn add_lobby(&mut self, lobby: &Lobby) {
-        self.lobby = Some(lobby.sender());
+    // Add a lobby sender to the game to allow connections that leave to return to the lobby
+    pub fn with_lobby(mut self, lobby: LobbySender) -> Game {
+        self.lobby = Some(lobby);
+        self
+    }
+
+    // Add a game pool sender to the game to tell the pool when connections leave and the game ends
+    pub fn with_pool(mut self, pool: GamePoolSender) -> Game {
+        self.pool = Some(pool);
+        self
     }
 
     pub async fn listen(&mut self) -> Result<(), ()> {
+        // Players of restored games start out disconnected
+        let disconnected: Vec<ConnId> = self.disconnected.keys().copied().collect();
+        for id in disconnected {
+            self.wait_for(id);
+        }
         while let Some(event) = self.receiver.recv().await {
             match event {
                 GameEvent::Join(conn) => {
@@ -52,48 +163,961 @@ impl Game {
                         self.start_game().await;
                     }
                 }
-                GameEvent::GameAction(_id, action) => match self.gamestate.valid(&action) {
-                    true => {
-                        self.gamestate.make(&action);
-                        self.broadcast_gamestate().await;
-                    }
-                    false => {
-                        println!("invalid action");
+                GameEvent::GameAction(id, action) => match self.act(id, Action::from(action)) {
+                    Ok(()) => {
+                        let update = self.update(action);
+                        self.broadcast(&Event::GameUpdate(update)).await;
                     }
+                    Err(rejection) => self.send_to(id, &rejection.event()),
                 },
+                GameEvent::Resign(id) => {
+                    if let Err(rejection) = self.resign(id).await {
+                        self.send_to(id, &rejection.event());
+                    }
+                }
+                GameEvent::Offer(id, kind) => {
+                    if let Err(rejection) = self.offer(id, kind).await {
+                        self.send_to(id, &rejection.event());
+                    }
+                }
+                GameEvent::Answer(id, kind, accepted) => {
+                    if let Err(rejection) = self.answer(id, kind, accepted).await {
+                        self.send_to(id, &rejection.event());
+                    }
+                }
+                GameEvent::Disconnect(id, sender) => self.disconnect(id, sender),
+                GameEvent::Reconnect(id, sender) => self.reconnect(id, sender),
+                GameEvent::Spectate(conn) => self.spectate(conn),
+                GameEvent::Leave(id) => self.leave(id),
+                GameEvent::Resync(id) => {
+                    if self.started {
+                        self.send_to(id, &Event::GameBroadcast(self.info()));
+                    }
+                }
+                GameEvent::Chat(id, scope, text) => {
+                    if let Err(rejection) = self.chat(id, scope, text) {
+                        self.send_to(id, &rejection.event());
+                    }
+                }
+                GameEvent::Mute(id, target, muted) => {
+                    if let Some(player) =
+                        self.players.iter_mut().find(|player| player.user.id == id)
+                    {
+                        player.chat.mute(target, muted);
+                    }
+                }
+                GameEvent::Abandon(id) => self.abandon(id).await,
             }
         }
         Ok(())
     }
 
-    // Broadcast a message to all players in the room
-    async fn broadcast(&mut self, json: String) {
-        for player in &mut self.players {
-            player.sender.send(Message::Text(json.clone())).await.unwrap();
+    // Applies an action for the connection, as long as it is seated on the side whose turn it is
+    fn act(&mut self, id: ConnId, action: Action) -> Result<(), Rejection> {
+        if !self.started {
+            return Err(Rejection::new(
+                ErrorCode::GameNotStarted,
+                "The game has not started",
+                None,
+            ));
+        }
+        if self.result.is_some() {
+            return Err(Rejection::new(ErrorCode::GameOver, "The game has ended", None));
+        }
+        let context = Some(notation::action(&action));
+        match self.seats.get(&id) {
+            None => Err(Rejection::new(ErrorCode::NotSeated, "Not playing in this game", context)),
+            Some(team) if *team != self.gamestate.turn() => {
+                Err(Rejection::new(ErrorCode::NotYourTurn, "It is not your turn", context))
+            }
+            Some(_) if !self.gamestate.is_legal(&action) => {
+                Err(Rejection::new(ErrorCode::IllegalAction, "The action is not legal", context))
+            }
+            Some(_) => {
+                self.history.push(self.gamestate);
+                self.offer = None;
+                self.gamestate.apply(&action);
+                self.actions.push(notation::action(&action));
+                if let Some(outcome) = self.gamestate.outcome() {
+                    let winner = match outcome {
+                        Outcome::Winner(team) => Some(team),
+                        Outcome::Draw => None,
+                    };
+                    self.end(GameResult { winner, reason: EndReason::Boomed });
+                }
+                self.save();
+                Ok(())
+            }
         }
     }
 
-    async fn broadcast_gamestate(&mut self) {
-        let p1 = &self.players.get(0).unwrap();
-        let p2 = &self.players.get(1).unwrap();
+    // Finds the team of a seated player in a game that is being played
+    fn playing(&self, id: ConnId) -> Result<Team, Rejection> {
+        if !self.started {
+            let message = "The game has not started";
+            return Err(Rejection::new(ErrorCode::GameNotStarted, message, None));
+        }
+        if self.result.is_some() {
+            return Err(Rejection::new(ErrorCode::GameOver, "The game has ended", None));
+        }
+        let not_seated = || Rejection::new(ErrorCode::NotSeated, "Not playing in this game", None);
+        self.seats.get(&id).copied().ok_or_else(not_seated)
+    }
+
+    async fn resign(&mut self, id: ConnId) -> Result<(), Rejection> {
+        let team = self.playing(id)?;
+        self.end(GameResult { winner: Some(team.next()), reason: EndReason::Resigned });
+        self.save();
+        self.broadcast_gamestate().await;
+        Ok(())
+    }
+
+    // Finds the team of a seated player in a game that has ended
+    fn finished(&self, id: ConnId) -> Result<Team, Rejection> {
+        if self.result.is_none() {
+            let message = "Rematches are offered once the game has ended";
+            return Err(Rejection::new(ErrorCode::GameInProgress, message, None));
+        }
+        let not_seated = || Rejection::new(ErrorCode::NotSeated, "Not playing in this game", None);
+        self.seats.get(&id).copied().ok_or_else(not_seated)
+    }
+
+    async fn offer(&mut self, id: ConnId, kind: OfferKind) -> Result<(), Rejection> {
+        let team = match kind {
+            OfferKind::Rematch => self.finished(id)?,
+            _ => self.playing(id)?,
+        };
+        match kind {
+            OfferKind::Takeback => {
+                self.takeback_length(team)?;
+            }
+            OfferKind::Rematch => self.can_rematch()?,
+            OfferKind::Draw => {}
+        }
+        let offer = Offer { kind, team, status: OfferStatus::Pending };
+        self.offer = Some(offer);
+        self.broadcast(&Event::OfferBroadcast(offer)).await;
+        Ok(())
+    }
+
+    // Answers the pending offer of the opponent, where an accepted draw ends the game, an accepted
+    // takeback returns to the position before the last action of the opponent and an accepted
+    // rematch moves both players to a new game
+    async fn answer(
+        &mut self,
+        id: ConnId,
+        kind: OfferKind,
+        accepted: bool,
+    ) -> Result<(), Rejection> {
+        let team = match kind {
+            OfferKind::Rematch => self.finished(id)?,
+            _ => self.playing(id)?,
+        };
+        let mut offer = match self.offer {
+            Some(offer) if offer.kind == kind && offer.team != team => offer,
+            _ => {
+                return Err(Rejection::new(ErrorCode::NoOffer, "There is no offer to answer", None))
+            }
+        };
+        if accepted && kind == OfferKind::Rematch {
+            self.can_rematch()?;
+        }
+        self.offer = None;
+        offer.status = match accepted {
+            true => OfferStatus::Accepted,
+            false => OfferStatus::Declined,
+        };
+        self.broadcast(&Event::OfferBroadcast(offer)).await;
+        if !accepted {
+            return Ok(());
+        }
+
+        match kind {
+            OfferKind::Draw => self.end(GameResult { winner: None, reason: EndReason::DrawAgreed }),
+            OfferKind::Takeback => {
+                for _ in 0..self.takeback_length(offer.team)? {
+                    self.gamestate = self.history.pop().expect("Takebacks fit in the history");
+                    self.actions.pop();
+                }
+            }
+            OfferKind::Rematch => {
+                self.rematch();
+                return Ok(());
+            }
+        }
+        self.save();
+        self.broadcast_gamestate().await;
+        Ok(())
+    }
+
+    // Checks that both players are still connected to the game and that there is a pool to start
+    // the rematch in
+    fn can_rematch(&self) -> Result<(), Rejection> {
+        let present = |id| self.players.iter().any(|player| player.user.id == id);
+        if self.pool.is_none() || !present(self.white.id) || !present(self.black.id) {
+            let message = "Both players have to still be in the game for a rematch";
+            return Err(Rejection::new(ErrorCode::RematchUnavailable, message, None));
+        }
+        Ok(())
+    }
+
+    // Hands both players over to the pool for a new game from the same starting position under the
+    // same rules and time control, with the colours swapped. Spectators stay to watch the finished game.
+    fn rematch(&mut self) {
+        let mut take = |id| {
+            let index = self.players.iter().position(|player| player.user.id == id);
+            self.players.remove(index.expect("Rematches are checked for both players"))
+        };
+        let (white, black) = (take(self.white.id), take(self.black.id));
+        let rated = self.ratings.is_some();
+        let time_control = self.time_control;
+        let rematch =
+            Rematch { white: black, black: white, start: self.start, rated, time_control };
+        let pool = self.pool.as_ref().expect("Rematches are checked for a pool");
+        pool.send(GamePoolEvent::Rematch(Box::new(rematch))).unwrap();
+    }
+
+    // How many actions to undo to take back the last action of the team, which includes the reply
+    // of the opponent when they have already played it
+    fn takeback_length(&self, team: Team) -> Result<usize, Rejection> {
+        let length = match self.gamestate.turn() == team {
+            true => 2,
+            false => 1,
+        };
+        match self.history.len() >= length {
+            true => Ok(length),
+            false => {
+                let message = "There is no action to take back";
+                Err(Rejection::new(ErrorCode::NothingToTakeBack, message, None))
+            }
+        }
+    }
+
+    // Marks a seated player as disconnected when the socket they are playing from closes, and
+    // checks whether they have come back once the grace period is over. Sockets that were already
+    // replaced by a reconnection are ignored.
+    fn disconnect(&mut self, id: ConnId, sender: ConnectionSender) {
+        let current = self
+            .players
+            .iter()
+            .any(|player| player.user.id == id && player.sender.same_channel(&sender));
+        if !current {
+            return;
+        }
+        if !self.seats.contains_key(&id) || self.result.is_some() {
+            // Spectators simply stop watching, as do players once the game has ended
+            self.players.retain(|player| player.user.id != id);
+            self.left(id);
+            return;
+        }
+
+        self.disconnected.insert(id, Instant::now());
+        self.wait_for(id);
+    }
+
+    // Checks whether a disconnected player has come back once their grace period is over
+    fn wait_for(&self, id: ConnId) {
+        let game = self.sender.clone();
+        let grace = self.grace;
+        tokio::spawn(async move {
+            time::sleep(grace).await;
+            let _ = game.send(GameEvent::Abandon(id));
+        });
+    }
+
+    // Reattaches a player to their seat from a new socket and catches them up on the game. Players
+    // of restored games have no connection until they first resume.
+    fn reconnect(&mut self, id: ConnId, sender: ConnectionSender) {
+        if let Some(player) = self.players.iter_mut().find(|player| player.user.id == id) {
+            player.sender = sender;
+        } else if let Some(user) = [&self.white, &self.black].into_iter().find(|user| user.id == id)
+        {
+            self.players.push(Connection::new(id, user.name.clone(), sender));
+        } else {
+            return;
+        }
+        self.disconnected.remove(&id);
+        if self.started {
+            self.send_to(id, &Event::GameBroadcast(self.info()));
+        }
+        if let Some(player) = self.players.iter().find(|player| player.user.id == id) {
+            self.players_chat.replay(player);
+        }
+    }
+
+    // Adds a lobby user who watches the game without a seat, catching them up on the game so far
+    fn spectate(&mut self, conn: Connection) {
+        let id = conn.user.id;
+        self.players_chat.replay(&conn);
+        self.spectators_chat.replay(&conn);
+        self.players.push(conn);
+        if self.started {
+            self.send_to(id, &Event::GameBroadcast(self.info()));
+        }
+    }
+
+    // Posts a message to the chat of the players, which spectators also read, or to the chat that
+    // only spectators read
+    fn chat(&mut self, id: ConnId, scope: ChatScope, text: String) -> Result<(), Rejection> {
+        let seated = self.seats.contains_key(&id);
+        match scope {
+            ChatScope::Lobby => {
+                let message = "The lobby chat is only for users in the lobby";
+                return Err(Rejection::new(ErrorCode::NotInLobby, message, None));
+            }
+            ChatScope::Players if !seated => {
+                let message = "Only the players can chat to the players";
+                return Err(Rejection::new(ErrorCode::NotSeated, message, None));
+            }
+            ChatScope::Spectators if seated => {
+                let message = "Players can not read the spectator chat";
+                return Err(Rejection::new(ErrorCode::NotSpectating, message, None));
+            }
+            _ => {}
+        }
+        let conn = match self.players.iter_mut().find(|player| player.user.id == id) {
+            Some(conn) => conn,
+            None => return Ok(()),
+        };
+        conn.chat.send(&text, Instant::now())?;
+        let user = conn.user.clone();
+        let seats = &self.seats;
+        match scope {
+            ChatScope::Spectators => {
+                let message = self.spectators_chat.post(user, text);
+                let spectators = self.players.iter().filter(|p| !seats.contains_key(&p.user.id));
+                chat::deliver(&message, spectators);
+            }
+            _ => {
+                let message = self.players_chat.post(user, text);
+                chat::deliver(&message, self.players.iter());
+            }
+        }
+        Ok(())
+    }
+
+    // Sends a spectator, or a player once the game is over, back to the lobby
+    fn leave(&mut self, id: ConnId) {
+        if self.seats.contains_key(&id) && self.result.is_none() {
+            let message = "Players can not leave a game in progress";
+            self.send_to(id, &Event::error(ErrorCode::GameInProgress, message, None));
+            return;
+        }
+        if let Some(index) = self.players.iter().position(|player| player.user.id == id) {
+            let conn = self.players.remove(index);
+            self.left(id);
+            if let Some(lobby) = &self.lobby {
+                lobby.send(LobbyEvent::Join(conn)).unwrap();
+            }
+        }
+    }
+
+    fn left(&self, id: ConnId) {
+        if let Some(pool) = &self.pool {
+            let _ = pool.send(GamePoolEvent::Left(self.id, id));
+        }
+    }
+
+    // Forfeits the game for a player who is still disconnected at the end of their grace period
+    async fn abandon(&mut self, id: ConnId) {
+        let abandoned = match self.disconnected.get(&id) {
+            Some(since) => since.elapsed() >= self.grace,
+            None => false,
+        };
+        if !abandoned || self.result.is_some() {
+            return;
+        }
+
+        let winner = self.seats.get(&id).map(|team| team.next());
+        self.end(GameResult { winner, reason: EndReason::Abandoned });
+        self.save();
+        self.broadcast_gamestate().await;
+    }
+
+    // Ends the game with the result, which rated games record in the ratings of both players
+    fn end(&mut self, result: GameResult) {
+        self.result = Some(result);
+        if let Some(pool) = &self.pool {
+            let _ = pool.send(GamePoolEvent::GameOver(self.id));
+        }
+        if let Some(ratings) = &self.ratings {
+            if let Err(e) = ratings.record(self.id, self.white.id, self.black.id, result.winner) {
+                println!("Failed to record the ratings of game {}: {}", self.id, e);
+            }
+        }
+    }
+
+    // Broadcast an event to all players in the room
+    async fn broadcast(&mut self, event: &Event) {
+        crate::conn::broadcast(event, self.players.iter());
+    }
+
+    // Sends an event to one of the players in the room
+    fn send_to(&self, id: ConnId, event: &Event) {
+        if let Some(player) = self.players.iter().find(|player| player.user.id == id) {
+            player.send(event);
+        }
+    }
 
-        let event = Event::GameBroadcast(GameInfo {
+    fn info(&self) -> GameInfo {
+        GameInfo {
             id: self.id,
-            white: p1.user.clone(),
-            black: p2.user.clone(),
+            white: self.white.clone(),
+            black: self.black.clone(),
             gamestate: self.gamestate,
-        });
+            result: self.result,
+            rated: self.ratings.is_some(),
+            moves: self.actions.len() as u32,
+            time_control: self.time_control,
+        }
+    }
+
+    // Describes what the action that was just played changed, for clients to apply to their copy
+    fn update(&self, action: boombots_core::Action) -> GameUpdate {
+        let before = self.history.last().expect("Played actions are in the history");
+        GameUpdate {
+            id: self.id,
+            action,
+            changes: before.changes(&self.gamestate),
+            moves: self.actions.len() as u32,
+            result: self.result,
+            checksum: self.gamestate.checksum(),
+        }
+    }
+
+    fn save(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let record = GameRecord {
+            id: self.id,
+            white: self.white.clone(),
+            black: self.black.clone(),
+            start: self.start,
+            actions: self.actions.clone(),
+            result: self.result,
+            rated: self.ratings.is_some(),
+            time_control: self.time_control,
+        };
+        if let Err(e) = store.save(&record) {
+            println!("Failed to save game {}: {}", self.id, e);
+        }
+    }
 
-        self.broadcast(serde_json::to_string(&event).unwrap()).await;
+    async fn broadcast_gamestate(&mut self) {
+        let event = Event::GameBroadcast(self.info());
+        self.broadcast(&event).await;
     }
 
+    // The game starts once both seated players have joined, whichever order they join in
     fn can_start(&self) -> bool {
-        self.players.len() == 2 && !self.started
+        let joined = |id| self.players.iter().any(|player| player.user.id == id);
+        !self.started && joined(self.white.id) && joined(self.black.id)
     }
 
     async fn start_game(&mut self) {
         self.started = true;
+        self.save();
         self.broadcast_gamestate().await;
         println!("Game started");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::UnboundedReceiver;
+    use tokio_tungstenite::tungstenite::Message;
+
+    type Events = UnboundedReceiver<Message>;
+
+    fn connect(id: ConnId) -> (Connection, Events) {
+        let (sender, receiver) = mpsc::unbounded_channel::<Message>();
+        (Connection::new(id, format!("player{}", id), sender), receiver)
+    }
+
+    async fn next(events: &mut Events) -> Event {
+        let message = events.recv().await.expect("The game closed the connection");
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    async fn next_error(events: &mut Events) -> ErrorCode {
+        match next(events).await {
+            Event::Error { code, .. } => code,
+            event => panic!("Expected an error but got {:?}", event),
+        }
+    }
+
+    async fn next_info(events: &mut Events) -> GameInfo {
+        match next(events).await {
+            Event::GameBroadcast(info) => info,
+            event => panic!("Expected a game broadcast but got {:?}", event),
+        }
+    }
+
+    async fn next_update(events: &mut Events) -> GameUpdate {
+        match next(events).await {
+            Event::GameUpdate(update) => update,
+            event => panic!("Expected a game update but got {:?}", event),
+        }
+    }
+
+    // Waits for the game to start or for the next action, and finds whose turn it is after it. The
+    // games in the tests start with white to move, so the move number of an update tells the turn.
+    async fn next_turn(events: &mut Events) -> Team {
+        match next(events).await {
+            Event::GameBroadcast(info) => info.gamestate.turn,
+            Event::GameUpdate(update) if update.moves % 2 == 0 => Team::White,
+            Event::GameUpdate(_) => Team::Black,
+            event => panic!("Expected a game broadcast or update but got {:?}", event),
+        }
+    }
+
+    // Creates a game with the players that connect with ids 1 and 2 seated as white and black
+    fn new_game(gamestate: GameState) -> Game {
+        let (white, black) = (User::new(1, "player1".into()), User::new(2, "player2".into()));
+        Game::new(1, gamestate, white, black)
+    }
+
+    fn action(action: &str) -> boombots_core::Action {
+        notation::parse_action(action).unwrap().into()
+    }
+
+    // Starts a game task with white and black seated, waiting for both to see the game start
+    async fn start() -> (GameSender, Events, Events) {
+        start_with_grace(GRACE_PERIOD).await
+    }
+
+    async fn start_with_grace(grace: Duration) -> (GameSender, Events, Events) {
+        let mut game = new_game(GameState::new()).with_grace(grace);
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+
+        let (white, mut white_events) = connect(1);
+        let (black, mut black_events) = connect(2);
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::Join(black)).unwrap();
+        assert_eq!(next_turn(&mut white_events).await, Team::White);
+        assert_eq!(next_turn(&mut black_events).await, Team::White);
+        (sender, white_events, black_events)
+    }
+
+    #[tokio::test]
+    async fn players_alternate_turns() {
+        let (game, mut white, mut black) = start().await;
+
+        game.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        assert_eq!(next_turn(&mut white).await, Team::Black);
+        assert_eq!(next_turn(&mut black).await, Team::Black);
+
+        game.send(GameEvent::GameAction(2, action("a7a6/1"))).unwrap();
+        assert_eq!(next_turn(&mut white).await, Team::White);
+        assert_eq!(next_turn(&mut black).await, Team::White);
+    }
+
+    #[tokio::test]
+    async fn players_can_not_act_out_of_turn() {
+        let (game, mut white, mut black) = start().await;
+
+        game.send(GameEvent::GameAction(2, action("a7a6/1"))).unwrap();
+        assert_eq!(next_error(&mut black).await, ErrorCode::NotYourTurn);
+
+        // White can not move black robots on its own turn either
+        game.send(GameEvent::GameAction(1, action("a7a6/1"))).unwrap();
+        assert_eq!(next_error(&mut white).await, ErrorCode::IllegalAction);
+
+        // Only the rejected player hears about the rejections, so the next event is the move
+        game.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        assert_eq!(next_turn(&mut white).await, Team::Black);
+        assert_eq!(next_turn(&mut black).await, Team::Black);
+
+        game.send(GameEvent::GameAction(1, action("b2b3/1"))).unwrap();
+        assert_eq!(next_error(&mut white).await, ErrorCode::NotYourTurn);
+    }
+
+    #[tokio::test]
+    async fn players_keep_their_seats_whichever_order_they_join_in() {
+        let mut game = new_game(GameState::new());
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+
+        let (white, mut white_events) = connect(1);
+        let (black, mut black_events) = connect(2);
+        sender.send(GameEvent::Join(black)).unwrap();
+        sender.send(GameEvent::GameAction(2, action("a7a6/1"))).unwrap();
+        assert_eq!(next_error(&mut black_events).await, ErrorCode::GameNotStarted);
+        sender.send(GameEvent::Join(white)).unwrap();
+        let info = next_info(&mut white_events).await;
+        assert_eq!((info.white.id, info.black.id), (1, 2));
+        assert_eq!(next_info(&mut black_events).await.white.id, 1);
+    }
+
+    #[tokio::test]
+    async fn spectators_can_not_act() {
+        let (game, mut white, _black) = start().await;
+        let (spectator, mut spectator_events) = connect(3);
+        game.send(GameEvent::Join(spectator)).unwrap();
+
+        game.send(GameEvent::GameAction(3, action("a2a3/1"))).unwrap();
+        assert_eq!(next_error(&mut spectator_events).await, ErrorCode::NotSeated);
+
+        game.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        assert_eq!(next_turn(&mut white).await, Team::Black);
+        assert_eq!(next_turn(&mut spectator_events).await, Team::Black);
+    }
+
+    #[tokio::test]
+    async fn spectators_watch_until_they_leave() {
+        let (lobby, mut lobby_events) = mpsc::unbounded_channel::<LobbyEvent>();
+        let mut game = new_game(GameState::new()).with_lobby(lobby);
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+        let (white, mut white_events) = connect(1);
+        let (black, _black_events) = connect(2);
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::Join(black)).unwrap();
+        next_turn(&mut white_events).await;
+
+        // Spectators are caught up on the game and then see every update
+        sender.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        let (spectator, mut spectator_events) = connect(3);
+        sender.send(GameEvent::Spectate(spectator)).unwrap();
+        assert_eq!(next_turn(&mut spectator_events).await, Team::Black);
+        sender.send(GameEvent::GameAction(2, action("a7a6/1"))).unwrap();
+        assert_eq!(next_turn(&mut spectator_events).await, Team::White);
+
+        // Players can not leave while the game is on, but spectators go back to the lobby
+        sender.send(GameEvent::Leave(1)).unwrap();
+        next_turn(&mut white_events).await;
+        next_turn(&mut white_events).await;
+        assert_eq!(next_error(&mut white_events).await, ErrorCode::GameInProgress);
+        sender.send(GameEvent::Leave(3)).unwrap();
+        match lobby_events.recv().await {
+            Some(LobbyEvent::Join(conn)) => assert_eq!(conn.user.id, 3),
+            event => panic!("Expected the spectator to join the lobby but got {:?}", event),
+        }
+        sender.send(GameEvent::GameAction(1, action("a3a4/1"))).unwrap();
+        assert_eq!(next_turn(&mut white_events).await, Team::Black);
+        assert!(spectator_events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn spectator_chat_is_hidden_from_players() {
+        let (game, mut white, _black) = start().await;
+        let (spectator, mut spectator_events) = connect(3);
+        game.send(GameEvent::Spectate(spectator)).unwrap();
+        next_info(&mut spectator_events).await;
+
+        game.send(GameEvent::Chat(3, ChatScope::Players, "hi".to_string())).unwrap();
+        assert_eq!(next_error(&mut spectator_events).await, ErrorCode::NotSeated);
+        game.send(GameEvent::Chat(3, ChatScope::Spectators, "white wins".to_string())).unwrap();
+        game.send(GameEvent::Chat(1, ChatScope::Players, "good luck".to_string())).unwrap();
+        for expected in ["white wins", "good luck"] {
+            match next(&mut spectator_events).await {
+                Event::Chat(message) => assert_eq!(message.text, expected),
+                event => panic!("Expected a chat message but got {:?}", event),
+            }
+        }
+        match next(&mut white).await {
+            Event::Chat(message) => assert_eq!(message.text, "good luck"),
+            event => panic!("Expected a chat message but got {:?}", event),
+        }
+    }
+
+    async fn next_offer(events: &mut Events) -> Offer {
+        match next(events).await {
+            Event::OfferBroadcast(offer) => offer,
+            event => panic!("Expected an offer but got {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn players_can_resign() {
+        let (game, mut white, _black) = start().await;
+        game.send(GameEvent::Resign(2)).unwrap();
+        let result = GameResult { winner: Some(Team::White), reason: EndReason::Resigned };
+        assert_eq!(next_info(&mut white).await.result, Some(result));
+        game.send(GameEvent::Resign(1)).unwrap();
+        assert_eq!(next_error(&mut white).await, ErrorCode::GameOver);
+    }
+
+    #[tokio::test]
+    async fn draws_are_offered_and_answered() {
+        let (game, mut white, mut black) = start().await;
+        game.send(GameEvent::Offer(1, OfferKind::Draw)).unwrap();
+        assert_eq!(next_offer(&mut black).await.status, OfferStatus::Pending);
+        game.send(GameEvent::Answer(1, OfferKind::Draw, true)).unwrap();
+        next_offer(&mut white).await;
+        assert_eq!(next_error(&mut white).await, ErrorCode::NoOffer);
+        game.send(GameEvent::Answer(2, OfferKind::Draw, false)).unwrap();
+        assert_eq!(next_offer(&mut black).await.status, OfferStatus::Declined);
+
+        game.send(GameEvent::Offer(1, OfferKind::Draw)).unwrap();
+        next_offer(&mut black).await;
+        game.send(GameEvent::Answer(2, OfferKind::Draw, true)).unwrap();
+        assert_eq!(next_offer(&mut black).await.status, OfferStatus::Accepted);
+        let result = GameResult { winner: None, reason: EndReason::DrawAgreed };
+        assert_eq!(next_info(&mut black).await.result, Some(result));
+    }
+
+    #[tokio::test]
+    async fn takebacks_undo_the_last_action_of_the_player() {
+        let (game, _white, mut black) = start().await;
+        game.send(GameEvent::Offer(2, OfferKind::Takeback)).unwrap();
+        assert_eq!(next_error(&mut black).await, ErrorCode::NothingToTakeBack);
+
+        // Taking back an action the opponent has not answered yet undoes only that action
+        game.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        next_turn(&mut black).await;
+        game.send(GameEvent::Offer(1, OfferKind::Takeback)).unwrap();
+        next_offer(&mut black).await;
+        game.send(GameEvent::Answer(2, OfferKind::Takeback, true)).unwrap();
+        next_offer(&mut black).await;
+        let info = next_info(&mut black).await;
+        assert_eq!(info.gamestate, GameState::new());
+
+        // Otherwise the reply of the opponent is undone too
+        game.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        game.send(GameEvent::GameAction(2, action("a7a6/1"))).unwrap();
+        next_turn(&mut black).await;
+        next_turn(&mut black).await;
+        game.send(GameEvent::Offer(1, OfferKind::Takeback)).unwrap();
+        next_offer(&mut black).await;
+        game.send(GameEvent::Answer(2, OfferKind::Takeback, true)).unwrap();
+        next_offer(&mut black).await;
+        assert_eq!(next_info(&mut black).await.gamestate, GameState::new());
+    }
+
+    #[tokio::test]
+    async fn actions_wait_for_the_game_to_start() {
+        let mut game = new_game(GameState::new());
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+
+        let (white, mut white_events) = connect(1);
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        assert_eq!(next_error(&mut white_events).await, ErrorCode::GameNotStarted);
+    }
+
+    #[tokio::test]
+    async fn players_reconnect_within_the_grace_period() {
+        let (game, mut white, mut black) = start_with_grace(Duration::from_millis(50)).await;
+        let (stale, _) = connect(1);
+
+        // The closed socket was not the one white is playing from, so white stays connected
+        game.send(GameEvent::Disconnect(1, stale.sender)).unwrap();
+        game.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        assert_eq!(next_turn(&mut white).await, Team::Black);
+        assert_eq!(next_turn(&mut black).await, Team::Black);
+
+        // Black drops and comes back on a new socket, which is caught up on the game
+        let (socket, _) = connect(2);
+        game.send(GameEvent::Reconnect(2, socket.sender.clone())).unwrap();
+        game.send(GameEvent::Disconnect(2, socket.sender)).unwrap();
+        let (resumed, mut resumed_events) = connect(2);
+        game.send(GameEvent::Reconnect(2, resumed.sender)).unwrap();
+        let info = next_info(&mut resumed_events).await;
+        assert_eq!(info.gamestate.turn, Team::Black);
+        assert_eq!(info.result, None);
+
+        time::sleep(Duration::from_millis(100)).await;
+        game.send(GameEvent::GameAction(2, action("a7a6/1"))).unwrap();
+        assert_eq!(next_turn(&mut resumed_events).await, Team::White);
+        assert_eq!(next_turn(&mut white).await, Team::White);
+    }
+
+    #[tokio::test]
+    async fn players_forfeit_after_the_grace_period() {
+        let (game, mut white, _black) = start_with_grace(Duration::from_millis(10)).await;
+        let (socket, _) = connect(2);
+        game.send(GameEvent::Reconnect(2, socket.sender.clone())).unwrap();
+        game.send(GameEvent::Disconnect(2, socket.sender)).unwrap();
+
+        let info = next_info(&mut white).await;
+        let result = GameResult { winner: Some(Team::White), reason: EndReason::Abandoned };
+        assert_eq!(info.result, Some(result));
+
+        game.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        assert_eq!(next_error(&mut white).await, ErrorCode::GameOver);
+    }
+
+    #[tokio::test]
+    async fn games_are_restored_from_the_store() {
+        let store = GameStore::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut game = new_game(GameState::new()).with_store(store.clone());
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+
+        let (white, mut white_events) = connect(1);
+        let (black, mut black_events) = connect(2);
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::Join(black)).unwrap();
+        sender.send(GameEvent::GameAction(1, action("a2a3/1"))).unwrap();
+        next_turn(&mut white_events).await;
+        let update = next_update(&mut white_events).await;
+        next_turn(&mut black_events).await;
+
+        // The restored game waits for its players to resume and carries on where it left off
+        let records = store.unfinished().unwrap();
+        assert_eq!(records.len(), 1);
+        let mut game = Game::restore(records[0].clone()).unwrap().with_store(store.clone());
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+
+        let (black, mut black_events) = connect(2);
+        sender.send(GameEvent::Reconnect(2, black.sender)).unwrap();
+        let restored = next_info(&mut black_events).await;
+        assert_eq!(restored.gamestate.checksum(), update.checksum);
+        assert_eq!((restored.moves, restored.white.id), (1, 1));
+
+        sender.send(GameEvent::GameAction(2, action("a7a6/1"))).unwrap();
+        assert_eq!(next_turn(&mut black_events).await, Team::White);
+        assert_eq!(store.unfinished().unwrap()[0].actions, ["a2a3/1", "a7a6/1"]);
+    }
+
+    #[tokio::test]
+    async fn restored_players_forfeit_unless_they_resume() {
+        let record = GameRecord {
+            id: 1,
+            white: User::new(1, "player1".to_string()),
+            black: User::new(2, "player2".to_string()),
+            start: GameState::new(),
+            actions: vec!["a2a3/1".to_string()],
+            result: None,
+            rated: false,
+            time_control: Some(TimeControl { initial: 60, increment: 0 }),
+        };
+        let mut game = Game::restore(record.clone()).unwrap().with_grace(Duration::from_millis(10));
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+
+        // Only black resumes, so white forfeits once the grace period is over
+        let (black, mut black_events) = connect(2);
+        sender.send(GameEvent::Reconnect(2, black.sender)).unwrap();
+        let info = next_info(&mut black_events).await;
+        assert_eq!((info.result, info.time_control), (None, record.time_control));
+        let result = GameResult { winner: Some(Team::Black), reason: EndReason::Abandoned };
+        assert_eq!(next_info(&mut black_events).await.result, Some(result));
+
+        // Records with actions that can not be replayed are not restored
+        let corrupt = GameRecord { actions: vec!["a7a6/1".to_string()], ..record };
+        assert!(Game::restore(corrupt).is_err());
+    }
+
+    #[tokio::test]
+    async fn rated_games_update_ratings() {
+        let ratings = Ratings::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        ratings.add(&User::new(1, "player1".to_string())).unwrap();
+        ratings.add(&User::new(2, "player2".to_string())).unwrap();
+        let gamestate = GameState::from_notation("8/8/8/3a4/3A4/8/8/A7 w").unwrap();
+        let mut game = new_game(gamestate).rated(ratings.clone());
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+
+        let (white, mut white_events) = connect(1);
+        let (black, _black_events) = connect(2);
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::Join(black)).unwrap();
+        assert!(next_info(&mut white_events).await.rated);
+
+        // Booming d4 takes the last black robot with it
+        sender.send(GameEvent::GameAction(1, action("d4*"))).unwrap();
+        let update = next_update(&mut white_events).await;
+        assert_eq!(update.result.unwrap().winner, Some(Team::White));
+        assert!(ratings.get(1).unwrap().unwrap().rating > 1500.0);
+        assert!(ratings.get(2).unwrap().unwrap().rating < 1500.0);
+    }
+
+    #[tokio::test]
+    async fn updates_can_be_applied_and_resynced() {
+        let gamestate = GameState::from_notation("8/8/3a4/3a4/3A4/8/8/A7 w").unwrap();
+        let mut game = new_game(gamestate);
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+        let (white, mut white_events) = connect(1);
+        let (black, _black_events) = connect(2);
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::Join(black)).unwrap();
+        let mut local = next_info(&mut white_events).await.gamestate;
+
+        // The boom at d4 sets off d5 and then d6, and every square it empties is in the update
+        sender.send(GameEvent::GameAction(1, action("d4*"))).unwrap();
+        let update = next_update(&mut white_events).await;
+        let squares: Vec<u8> = update.changes.iter().map(|change| change.index).collect();
+        assert_eq!((update.moves, squares), (1, vec![27, 35, 43]));
+        for change in &update.changes {
+            local.board[change.index as usize] = change.bot;
+        }
+        local.turn = local.turn.next();
+        assert_eq!(local.checksum(), update.checksum);
+
+        sender.send(GameEvent::Resync(1)).unwrap();
+        let info = next_info(&mut white_events).await;
+        assert_eq!((info.gamestate, info.moves), (local, 1));
+    }
+
+    #[tokio::test]
+    async fn rematches_swap_colours_in_a_new_game() {
+        let (pool, mut pool_events) = mpsc::unbounded_channel::<GamePoolEvent>();
+        let time_control = Some(TimeControl { initial: 300, increment: 5 });
+        let mut game = new_game(GameState::new()).with_pool(pool).with_time_control(time_control);
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+        let (white, mut white_events) = connect(1);
+        let (black, mut black_events) = connect(2);
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::Join(black)).unwrap();
+        next_turn(&mut white_events).await;
+        next_turn(&mut black_events).await;
+
+        sender.send(GameEvent::Offer(1, OfferKind::Rematch)).unwrap();
+        assert_eq!(next_error(&mut white_events).await, ErrorCode::GameInProgress);
+        sender.send(GameEvent::Resign(2)).unwrap();
+        next_info(&mut black_events).await;
+        sender.send(GameEvent::Offer(2, OfferKind::Rematch)).unwrap();
+        assert_eq!(next_offer(&mut black_events).await.kind, OfferKind::Rematch);
+        sender.send(GameEvent::Answer(1, OfferKind::Rematch, true)).unwrap();
+        assert_eq!(next_offer(&mut black_events).await.status, OfferStatus::Accepted);
+
+        loop {
+            match pool_events.recv().await {
+                Some(GamePoolEvent::Rematch(rematch)) => {
+                    assert_eq!((rematch.white.user.id, rematch.black.user.id), (2, 1));
+                    assert_eq!(rematch.start, GameState::new());
+                    assert!(!rematch.rated);
+                    assert_eq!(rematch.time_control, time_control);
+                    break;
+                }
+                Some(GamePoolEvent::GameOver(1)) => continue,
+                event => panic!("Expected a rematch but got {:?}", event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rematches_need_both_players() {
+        let (pool, _pool_events) = mpsc::unbounded_channel::<GamePoolEvent>();
+        let mut game = new_game(GameState::new()).with_pool(pool);
+        let sender = game.sender();
+        tokio::spawn(async move { game.listen().await });
+        let (white, mut white_events) = connect(1);
+        let (black, mut black_events) = connect(2);
+        let black_socket = black.sender.clone();
+        sender.send(GameEvent::Join(white)).unwrap();
+        sender.send(GameEvent::Join(black)).unwrap();
+        next_turn(&mut white_events).await;
+        next_turn(&mut black_events).await;
+
+        // Black offers a rematch and then closes their socket, so white can not accept it
+        sender.send(GameEvent::Resign(2)).unwrap();
+        next_info(&mut white_events).await;
+        sender.send(GameEvent::Offer(2, OfferKind::Rematch)).unwrap();
+        assert_eq!(next_offer(&mut white_events).await.kind, OfferKind::Rematch);
+        sender.send(GameEvent::Disconnect(2, black_socket)).unwrap();
+        sender.send(GameEvent::Answer(1, OfferKind::Rematch, true)).unwrap();
+        assert_eq!(next_error(&mut white_events).await, ErrorCode::RematchUnavailable);
+        sender.send(GameEvent::Offer(1, OfferKind::Rematch)).unwrap();
+        assert_eq!(next_error(&mut white_events).await, ErrorCode::RematchUnavailable);
+    }
+}
@@ -1,41 +1,107 @@
-use crate::conn::{ConnId, Connection};
-use boombots_core::{net::ChallengeInfo, Action};
+use crate::conn::{ConnId, Connection, ConnectionSender, Rejection, Sessions};
+use boombots_core::{
+    net::{ChallengeInfo, ChatScope, GameSummary, OfferKind, TimeControl, User},
+    Action, GameState,
+};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
 
 mod handler;
 mod pool;
+mod ratings;
+mod store;
 pub use handler::Game;
 pub use pool::GamePool;
+pub use ratings::Ratings;
+pub use store::GameStore;
 
 pub type GameId = u32;
 pub type GameSender = UnboundedSender<GameEvent>;
 pub type GameReceiver = UnboundedReceiver<GameEvent>;
 pub type GamePoolSender = UnboundedSender<GamePoolEvent>;
 pub type GamePoolReceiver = UnboundedReceiver<GamePoolEvent>;
+pub type ResumeSender = oneshot::Sender<Result<ConnId, Rejection>>;
 
 #[derive(Debug)]
 pub enum GamePoolEvent {
     CreateGame(ChallengeInfo),
     GameAction(ConnId, Action),
+    Resign(ConnId),
+    Offer(ConnId, OfferKind),
+    // Accepts or declines the offer of the kind from the opponent
+    Answer(ConnId, OfferKind, bool),
     Join(GameId, Connection),
-    // Disconnect(ConnId),
+    // The socket with the sender closed
+    Disconnect(ConnId, ConnectionSender),
+    // Reattaches a new socket to the seat with the resume token, replying with the seat's id or why
+    // the token was rejected
+    Resume(String, ConnectionSender, ResumeSender),
+    // Asks for the games being played, which are answered on the sender
+    ListGames(ConnectionSender),
+    // Chat and mutes from connections that are not in the lobby
+    Chat(ConnId, ChatScope, String),
+    Mute(ConnId, ConnId, bool),
+    Spectate(GameId, Connection),
+    // The connection asks to leave its game back to the lobby
+    Leave(ConnId),
+    // The connection asks for the whole game, after its copy fell out of step with the updates
+    Resync(ConnId),
+    // Sent by games when a connection has left them, and when they end
+    Left(GameId, ConnId),
+    GameOver(GameId),
+    Rematch(Box<Rematch>),
+}
+
+/// A new game that a finished game hands its players over to when both agree to a rematch
+#[derive(Debug)]
+pub struct Rematch {
+    pub white: Connection,
+    pub black: Connection,
+    pub start: GameState,
+    pub rated: bool,
+    pub time_control: Option<TimeControl>,
 }
 
 #[derive(Debug)]
 pub enum GameEvent {
     Join(Connection),
     GameAction(ConnId, Action),
-    // Disconnect(ConnId),
+    Resign(ConnId),
+    Offer(ConnId, OfferKind),
+    Answer(ConnId, OfferKind, bool),
+    Disconnect(ConnId, ConnectionSender),
+    Reconnect(ConnId, ConnectionSender),
+    Spectate(Connection),
+    Leave(ConnId),
+    Resync(ConnId),
+    Chat(ConnId, ChatScope, String),
+    Mute(ConnId, ConnId, bool),
+    // Sent by the game to itself once the grace period of a disconnected player has passed
+    Abandon(ConnId),
 }
 
 #[derive(Debug)]
 pub struct GameInfo {
     pub id: GameId,
     pub sender: GameSender,
+    pub white: User,
+    pub black: User,
+    pub rated: bool,
+    pub over: bool,
 }
 
 impl GameInfo {
-    pub fn new(id: GameId, sender: GameSender) -> GameInfo {
-        GameInfo { id, sender }
+    pub fn new(id: GameId, sender: GameSender, white: User, black: User, rated: bool) -> GameInfo {
+        GameInfo { id, sender, white, black, rated, over: false }
+    }
+
+    // Summarises the game for spectators, as long as it is running
+    pub fn summary(&self) -> Option<GameSummary> {
+        (!self.over).then(|| GameSummary {
+            id: self.id,
+            white: self.white.clone(),
+            black: self.black.clone(),
+            rated: self.rated,
+        })
     }
 }
@@ -1,23 +1,31 @@
 use super::*;
 use crate::lobby::*;
+use boombots_core::net::{ErrorCode, Event, GameSummary, TimeControl};
+use boombots_core::GameState;
 use std::collections::HashMap;
 use tokio::sync::mpsc;
 
 #[derive(Debug)]
 pub struct GamePool {
     games: HashMap<GameId, GameInfo>,
-    conn_games: HashMap<GameId, ConnId>,
+    conn_games: HashMap<ConnId, GameId>,
+    store: GameStore,
+    ratings: Ratings,
+    sessions: Sessions,
     sender: GamePoolSender,
     receiver: GamePoolReceiver,
     lobby: Option<LobbySender>,
 }
 
 impl GamePool {
-    pub fn new() -> GamePool {
+    pub fn new(db: &sled::Db, sessions: Sessions) -> GamePool {
         let (sender, receiver) = mpsc::unbounded_channel::<GamePoolEvent>();
         GamePool {
             games: HashMap::new(),
             conn_games: HashMap::new(),
+            store: GameStore::open(db).expect("Failed to open the games database"),
+            ratings: Ratings::open(db).expect("Failed to open the ratings database"),
+            sessions,
             sender,
             receiver,
             lobby: None,
@@ -36,43 +44,221 @@ impl GamePool {
         self.lobby = Some(lobby.sender());
     }
 
-    fn next_id(&mut self) -> u32 {
-        (self.games.len() + 1) as u32
+    // Restarts every game that had not ended when the server last stopped. Their players can take
+    // their seats back with the resume tokens they were given.
+    pub fn restore(&mut self) {
+        let records = self.store.unfinished().expect("Failed to load unfinished games");
+        for record in records {
+            println!("Restoring game {}", record.id);
+            let (id, rated) = (record.id, record.rated);
+            let (white, black) = (record.white.clone(), record.black.clone());
+            let game = match Game::restore(record) {
+                Ok(game) => game,
+                Err(e) => {
+                    println!("Skipping game {}: {}", id, e);
+                    continue;
+                }
+            };
+            let info = GameInfo::new(id, game.sender(), white, black, rated);
+            self.spawn(game, info);
+        }
+    }
+
+    fn create_game(
+        &mut self,
+        gamestate: GameState,
+        white: User,
+        black: User,
+        rated: bool,
+        time_control: Option<TimeControl>,
+    ) -> GameId {
+        let id = self.store.next_id().expect("Failed to generate a game id");
+        let game =
+            Game::new(id, gamestate, white.clone(), black.clone()).with_time_control(time_control);
+        let info = GameInfo::new(id, game.sender(), white, black, rated);
+        self.spawn(game, info);
+        id
+    }
+
+    // Connects a game to the store, the ratings if it is rated, the pool and the lobby, and then
+    // starts its task
+    fn spawn(&mut self, game: Game, info: GameInfo) {
+        let mut game = game.with_store(self.store.clone()).with_pool(self.sender());
+        if info.rated {
+            game = game.rated(self.ratings.clone());
+        }
+        if let Some(lobby) = &self.lobby {
+            game = game.with_lobby(lobby.clone());
+        }
+        self.games.insert(info.id, info);
+        tokio::spawn(async move {
+            game.listen().await.unwrap();
+        });
+    }
+
+    // Moves a player into the game they are seated in
+    fn join(&mut self, game_id: GameId, conn: Connection) {
+        self.conn_games.insert(conn.user.id, game_id);
+        // Give the player a token for reattaching to their seat if their socket closes
+        let token = format!("{:032x}", rand::random::<u128>());
+        match self.store.add_resume_token(&token, game_id, conn.user.id) {
+            Ok(()) => conn.send(&Event::ResumeToken(token)),
+            Err(e) => println!("Failed to store resume token: {}", e),
+        }
+        let game = &self.games[&game_id];
+        game.sender.send(GameEvent::Join(conn)).expect("couldnt send event to game");
+    }
+
+    // Reattaches a socket to the seat the resume token was given out for, which may be in a game
+    // restored after a restart that nobody has rejoined. Tokens of games that have ended are
+    // rejected, along with those of games that ended before a restart and were never restored.
+    fn resume(&mut self, token: &str, sender: ConnectionSender) -> Result<ConnId, Rejection> {
+        let unknown = || {
+            let message = "No seat has this resume token";
+            Rejection::new(ErrorCode::UnknownResumeToken, message, None)
+        };
+        let (game_id, id) = match self.store.resume_token(token) {
+            Ok(Some(seat)) => seat,
+            Ok(None) => return Err(unknown()),
+            Err(e) => {
+                println!("Failed to read a resume token: {}", e);
+                let message = "Failed to read the resume token";
+                return Err(Rejection::new(ErrorCode::StorageError, message, None));
+            }
+        };
+        let game = match self.games.get(&game_id) {
+            Some(game) if !game.over => game,
+            Some(_) => return Err(unknown()),
+            None => {
+                let _ = self.store.remove_resume_tokens(game_id);
+                return Err(unknown());
+            }
+        };
+        // The player may have connected again with another socket instead of resuming
+        if !self.sessions.claim(id) {
+            let message = "Another socket is already connected for this seat";
+            return Err(Rejection::new(ErrorCode::AlreadyConnected, message, None));
+        }
+        self.conn_games.insert(id, game_id);
+        game.sender.send(GameEvent::Reconnect(id, sender)).unwrap();
+        Ok(id)
     }
 
-    fn create_game(&mut self) -> (Game, GameInfo) {
-        let id = self.next_id();
-        let game = Game::new(id);
-        let sender = game.sender();
-        let info = GameInfo::new(id, sender);
-        (game, info)
+    // Finds the game the connection is in
+    fn game_of(&self, id: ConnId) -> Option<&GameInfo> {
+        self.conn_games.get(&id).and_then(|game| self.games.get(game))
+    }
+
+    // Passes an event from a player on to their game
+    fn forward(&self, id: ConnId, event: GameEvent) {
+        match self.game_of(id) {
+            Some(game) => game.sender.send(event).unwrap(),
+            None => self.reject(id, ErrorCode::NotInGame, "Not seated in a game"),
+        }
+    }
+
+    // Sends an error to a connection outside of any game, which the lobby holds
+    fn reject(&self, id: ConnId, code: ErrorCode, message: &str) {
+        if let Some(lobby) = &self.lobby {
+            lobby.send(LobbyEvent::Error(id, Event::error(code, message, None))).unwrap();
+        }
     }
 
     pub async fn listen(&mut self) {
         while let Some(event) = self.receiver.recv().await {
-            println!("{:?}", event);
+            match &event {
+                // Resume tokens let anyone take the seat, so they are kept out of the log
+                GamePoolEvent::Resume(..) => println!("Resume"),
+                event => println!("{:?}", event),
+            }
             match event {
                 GamePoolEvent::CreateGame(challenge) => {
-                    let (mut game, info) = self.create_game();
-                    let game_id = info.id;
-                    tokio::spawn(async move {
-                        game.listen().await.unwrap();
-                    });
-                    // Keep track of the game
-                    self.games.insert(info.id, info);
+                    let gamestate = challenge
+                        .rules
+                        .setup(challenge.position.as_ref())
+                        .expect("Challenges are validated by the lobby");
+                    let (white, black) =
+                        challenge.seats().expect("Accepted challenges have settled seats");
+                    let (rated, time_control) = (challenge.rated, challenge.time_control);
+                    let game_id = self.create_game(gamestate, white, black, rated, time_control);
                     if let Some(lobby) = &self.lobby {
                         lobby.send(LobbyEvent::GameReady(game_id, challenge)).unwrap();
                     }
                 }
                 GamePoolEvent::GameAction(id, action) => {
-                    let game_id = self.conn_games.get(&id).unwrap();
-                    let game = self.games.get(game_id).unwrap();
-                    game.sender.send(GameEvent::GameAction(id, action)).unwrap();
+                    self.forward(id, GameEvent::GameAction(id, action));
+                }
+                GamePoolEvent::Resign(id) => self.forward(id, GameEvent::Resign(id)),
+                GamePoolEvent::Offer(id, kind) => self.forward(id, GameEvent::Offer(id, kind)),
+                GamePoolEvent::Answer(id, kind, accepted) => {
+                    self.forward(id, GameEvent::Answer(id, kind, accepted));
+                }
+                GamePoolEvent::Join(game_id, conn) => self.join(game_id, conn),
+                GamePoolEvent::Rematch(rematch) => {
+                    let Rematch { white, black, start, rated, time_control } = *rematch;
+                    let (white_user, black_user) = (white.user.clone(), black.user.clone());
+                    let game_id =
+                        self.create_game(start, white_user, black_user, rated, time_control);
+                    self.join(game_id, white);
+                    self.join(game_id, black);
                 }
-                GamePoolEvent::Join(game_id, conn) => {
-                    self.conn_games.insert(conn.user.id, game_id);
-                    let game = self.games.get(&game_id).expect("couldnt get game by id");
-                    game.sender.send(GameEvent::Join(conn)).expect("couldnt send event to game");
+                GamePoolEvent::Disconnect(id, sender) => {
+                    if let Some(game) = self.game_of(id) {
+                        game.sender.send(GameEvent::Disconnect(id, sender)).unwrap();
+                    }
+                }
+                GamePoolEvent::Resume(token, sender, resumed) => {
+                    let _ = resumed.send(self.resume(&token, sender));
+                }
+                GamePoolEvent::ListGames(sender) => {
+                    let mut games: Vec<GameSummary> =
+                        self.games.values().filter_map(GameInfo::summary).collect();
+                    games.sort_by_key(|game| game.id);
+                    crate::conn::send(&sender, &Event::GameList(games));
+                }
+                GamePoolEvent::Chat(id, scope, text) => {
+                    if let Some(game) = self.game_of(id) {
+                        game.sender.send(GameEvent::Chat(id, scope, text)).unwrap();
+                    }
+                }
+                GamePoolEvent::Mute(id, target, muted) => {
+                    if let Some(game) = self.game_of(id) {
+                        game.sender.send(GameEvent::Mute(id, target, muted)).unwrap();
+                    }
+                }
+                GamePoolEvent::Spectate(game_id, conn) => {
+                    match self.games.get(&game_id).filter(|game| game.summary().is_some()) {
+                        Some(game) => {
+                            self.conn_games.insert(conn.user.id, game_id);
+                            game.sender.send(GameEvent::Spectate(conn)).unwrap();
+                        }
+                        None => {
+                            // Send the connection back to the lobby it came from
+                            let id = conn.user.id;
+                            if let Some(lobby) = &self.lobby {
+                                lobby.send(LobbyEvent::Join(conn)).unwrap();
+                            }
+                            self.reject(id, ErrorCode::UnknownGame, "No such game is being played");
+                        }
+                    }
+                }
+                GamePoolEvent::Resync(id) => self.forward(id, GameEvent::Resync(id)),
+                GamePoolEvent::Leave(id) => match self.game_of(id) {
+                    Some(game) => game.sender.send(GameEvent::Leave(id)).unwrap(),
+                    None => self.reject(id, ErrorCode::NotInGame, "Not in a game"),
+                },
+                GamePoolEvent::Left(game_id, id) => {
+                    if self.conn_games.get(&id) == Some(&game_id) {
+                        self.conn_games.remove(&id);
+                    }
+                }
+                GamePoolEvent::GameOver(game_id) => {
+                    if let Some(game) = self.games.get_mut(&game_id) {
+                        game.over = true;
+                    }
+                    if let Err(e) = self.store.remove_resume_tokens(game_id) {
+                        println!("Failed to remove the resume tokens of game {}: {}", game_id, e);
+                    }
                 }
             }
         }
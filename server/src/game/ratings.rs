@@ -0,0 +1,251 @@
+use super::GameId;
+use crate::conn::ConnId;
+use boombots_core::net::{RatedGame, Rating, Standing, User};
+use boombots_core::Team;
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use std::f64::consts::PI;
+
+// Converts ratings to and from the Glicko-2 scale
+const SCALE: f64 = 173.7178;
+// How much the volatility can change after a game, where smaller values keep it steadier
+const TAU: f64 = 0.5;
+const TOLERANCE: f64 = 0.000001;
+// The deviation of a rating never grows past that of a new player
+const MAX_DEVIATION: f64 = 350.0;
+const LEADERBOARD_SIZE: usize = 50;
+
+/// Finds the rating after a rating period with the results, which pair the rating of each opponent
+/// with the score against them: 1 for a win, 0.5 for a draw and 0 for a loss. Follows the steps
+/// of "Example of the Glicko-2 system" by Mark Glickman.
+pub fn update(rating: &Rating, results: &[(Rating, f64)]) -> Rating {
+    let mu = (rating.rating - 1500.0) / SCALE;
+    let phi = rating.deviation / SCALE;
+    let sigma = rating.volatility;
+    if results.is_empty() {
+        let deviation = (phi * phi + sigma * sigma).sqrt() * SCALE;
+        return Rating { deviation: deviation.min(MAX_DEVIATION), ..*rating };
+    }
+
+    let g = |phi: f64| 1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt();
+    let games: Vec<(f64, f64, f64)> = results
+        .iter()
+        .map(|(opponent, score)| {
+            let g = g(opponent.deviation / SCALE);
+            let e = 1.0 / (1.0 + (-g * (mu - (opponent.rating - 1500.0) / SCALE)).exp());
+            (g, e, *score)
+        })
+        .collect();
+    let v = 1.0 / games.iter().map(|(g, e, _)| g * g * e * (1.0 - e)).sum::<f64>();
+    let improvement = games.iter().map(|(g, e, s)| g * (s - e)).sum::<f64>();
+    let delta = v * improvement;
+
+    // Find the new volatility with the Illinois algorithm
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        let d = phi * phi + v + ex;
+        ex * (delta * delta - d) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+    };
+    let mut x = a;
+    let mut y = if delta * delta > phi * phi + v {
+        (delta * delta - phi * phi - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+    let (mut f_x, mut f_y) = (f(x), f(y));
+    while (y - x).abs() > TOLERANCE {
+        let z = x + (x - y) * f_x / (f_y - f_x);
+        let f_z = f(z);
+        if f_z * f_y <= 0.0 {
+            x = y;
+            f_x = f_y;
+        } else {
+            f_x /= 2.0;
+        }
+        y = z;
+        f_y = f_z;
+    }
+    let volatility = (x / 2.0).exp();
+
+    let phi_star = (phi * phi + volatility * volatility).sqrt();
+    let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+    let mu = mu + phi * phi * improvement;
+    Rating {
+        rating: mu * SCALE + 1500.0,
+        deviation: (phi * SCALE).min(MAX_DEVIATION),
+        volatility,
+        games: rating.games + results.len() as u32,
+    }
+}
+
+/// The ratings of registered accounts, stored in the embedded database by account id. Each rated
+/// game is its own rating period, so ratings change as soon as a game ends. The rating after each
+/// game is also kept by account and game id, so that accounts can follow their progress.
+#[derive(Debug, Clone)]
+pub struct Ratings {
+    ratings: sled::Tree,
+    history: sled::Tree,
+}
+
+impl Ratings {
+    pub fn open(db: &sled::Db) -> sled::Result<Ratings> {
+        Ok(Ratings { ratings: db.open_tree("ratings")?, history: db.open_tree("rating_history")? })
+    }
+
+    /// Gives the account the rating of a new player, unless it already has a rating
+    pub fn add(&self, user: &User) -> sled::Result<()> {
+        let standing = Standing { user: user.clone(), rating: Rating::default() };
+        let json = serde_json::to_vec(&standing).expect("Standings always serialize");
+        let _ = self.ratings.compare_and_swap(
+            user.id.to_be_bytes(),
+            None as Option<&[u8]>,
+            Some(json),
+        )?;
+        Ok(())
+    }
+
+    /// Finds the rating of a user, which is None for guests
+    pub fn get(&self, id: ConnId) -> sled::Result<Option<Rating>> {
+        let json = self.ratings.get(id.to_be_bytes())?;
+        Ok(json.map(|json| standing(&json).rating))
+    }
+
+    /// Updates the ratings of both players with the result of the game between them, where a
+    /// winner of None is a draw
+    pub fn record(
+        &self,
+        game: GameId,
+        white: ConnId,
+        black: ConnId,
+        winner: Option<Team>,
+    ) -> sled::Result<()> {
+        let score = match winner {
+            Some(Team::White) => 1.0,
+            Some(Team::Black) => 0.0,
+            None => 0.5,
+        };
+        let (white, black) = (white.to_be_bytes(), black.to_be_bytes());
+        let result = (&self.ratings, &self.history).transaction(|(ratings, history)| {
+            let missing = || ConflictableTransactionError::Abort(());
+            let mut w = standing(&ratings.get(white)?.ok_or_else(missing)?);
+            let mut b = standing(&ratings.get(black)?.ok_or_else(missing)?);
+            let rating = update(&w.rating, &[(b.rating, score)]);
+            b.rating = update(&b.rating, &[(w.rating, 1.0 - score)]);
+            w.rating = rating;
+            for (id, standing) in [(white, &w), (black, &b)] {
+                let json = serde_json::to_vec(standing).expect("Standings always serialize");
+                ratings.insert(&id, json)?;
+                let rated = RatedGame { game, rating: standing.rating };
+                let json = serde_json::to_vec(&rated).expect("Rated games always serialize");
+                history.insert([id, game.to_be_bytes()].concat(), json)?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) | Err(TransactionError::Abort(())) => Ok(()),
+            Err(TransactionError::Storage(e)) => Err(e),
+        }
+    }
+
+    /// The rating of the account after each of its rated games, oldest first
+    pub fn history(&self, id: ConnId) -> sled::Result<Vec<RatedGame>> {
+        let mut games = Vec::new();
+        for entry in self.history.scan_prefix(id.to_be_bytes()) {
+            let (_, json) = entry?;
+            games.push(serde_json::from_slice(&json).expect("Stored rated games are valid"));
+        }
+        Ok(games)
+    }
+
+    /// The highest rated accounts that have played a rated game, best first
+    pub fn leaderboard(&self) -> sled::Result<Vec<Standing>> {
+        let mut standings = Vec::new();
+        for entry in self.ratings.iter() {
+            let (_, json) = entry?;
+            let standing = standing(&json);
+            if standing.rating.games > 0 {
+                standings.push(standing);
+            }
+        }
+        standings.sort_by(|a, b| b.rating.rating.total_cmp(&a.rating.rating));
+        standings.truncate(LEADERBOARD_SIZE);
+        Ok(standings)
+    }
+}
+
+fn standing(json: &[u8]) -> Standing {
+    serde_json::from_slice(json).expect("Stored standings are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating { rating, deviation, ..Rating::default() }
+    }
+
+    fn open() -> Ratings {
+        Ratings::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn updates_match_the_glicko2_example() {
+        let results = [
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ];
+        let updated = update(&rating(1500.0, 200.0), &results);
+        assert!((updated.rating - 1464.06).abs() < 0.01);
+        assert!((updated.deviation - 151.52).abs() < 0.01);
+        assert!((updated.volatility - 0.05999).abs() < 0.00001);
+        assert_eq!(updated.games, 3);
+    }
+
+    #[test]
+    fn results_move_ratings_apart() {
+        let ratings = open();
+        let (ada, bob, eve) =
+            (User::new(1, "ada".into()), User::new(2, "bob".into()), User::new(3, "eve".into()));
+        for user in [&ada, &bob, &eve] {
+            ratings.add(user).unwrap();
+        }
+        ratings.record(7, 1, 2, Some(Team::White)).unwrap();
+        let (winner, loser) = (ratings.get(1).unwrap().unwrap(), ratings.get(2).unwrap().unwrap());
+        assert!(winner.rating > 1500.0 && loser.rating < 1500.0);
+        assert!(winner.deviation < 350.0 && loser.deviation < 350.0);
+
+        // Adding an account again keeps its rating, and guests have none
+        ratings.add(&ada).unwrap();
+        assert_eq!(ratings.get(1).unwrap(), Some(winner));
+        assert_eq!(ratings.get(4).unwrap(), None);
+
+        // Only accounts that have played are on the leaderboard
+        let leaderboard = ratings.leaderboard().unwrap();
+        let names: Vec<&str> = leaderboard.iter().map(|s| s.user.name.as_str()).collect();
+        assert_eq!(names, ["ada", "bob"]);
+    }
+
+    #[test]
+    fn ratings_are_kept_after_every_game() {
+        let ratings = open();
+        ratings.add(&User::new(1, "ada".into())).unwrap();
+        ratings.add(&User::new(2, "bob".into())).unwrap();
+        ratings.record(3, 1, 2, Some(Team::White)).unwrap();
+        let first = ratings.get(1).unwrap().unwrap();
+        ratings.record(12, 2, 1, None).unwrap();
+        let second = ratings.get(1).unwrap().unwrap();
+
+        let history = ratings.history(1).unwrap();
+        let expected =
+            [RatedGame { game: 3, rating: first }, RatedGame { game: 12, rating: second }];
+        assert_eq!(history, expected);
+        assert_eq!(ratings.history(2).unwrap().len(), 2);
+        assert_eq!(ratings.history(3).unwrap(), []);
+    }
+}
@@ -0,0 +1,170 @@
+use super::GameId;
+use crate::conn::ConnId;
+use boombots_core::net::{GameResult, TimeControl, User};
+use boombots_core::GameState;
+use serde::{Deserialize, Serialize};
+
+/// Everything needed to carry on a game after the server restarts. The actions are kept in action
+/// notation and replayed from the starting position to rebuild the current one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    pub id: GameId,
+    pub white: User,
+    pub black: User,
+    pub start: GameState,
+    pub actions: Vec<String>,
+    pub result: Option<GameResult>,
+    #[serde(default)]
+    pub rated: bool,
+    /// The time control the players were paired by, which records saved before it was kept lack
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+}
+
+/// Games and the resume tokens of their seats, stored in the embedded database as they are played
+#[derive(Debug, Clone)]
+pub struct GameStore {
+    db: sled::Db,
+    games: sled::Tree,
+    resume_tokens: sled::Tree,
+}
+
+impl GameStore {
+    pub fn open(db: &sled::Db) -> sled::Result<GameStore> {
+        Ok(GameStore {
+            db: db.clone(),
+            games: db.open_tree("games")?,
+            resume_tokens: db.open_tree("resume_tokens")?,
+        })
+    }
+
+    /// Hands out a game id that has never been used, even across restarts
+    pub fn next_id(&self) -> sled::Result<GameId> {
+        Ok(self.db.generate_id()? as GameId + 1)
+    }
+
+    pub fn save(&self, record: &GameRecord) -> sled::Result<()> {
+        let json = serde_json::to_vec(record).expect("Game records always serialize");
+        self.games.insert(record.id.to_be_bytes(), json)?;
+        self.games.flush()?;
+        Ok(())
+    }
+
+    /// Loads every game that has not ended yet, skipping records that can not be read
+    pub fn unfinished(&self) -> sled::Result<Vec<GameRecord>> {
+        let mut records = Vec::new();
+        for entry in self.games.iter() {
+            let (_, json) = entry?;
+            let record: GameRecord = match serde_json::from_slice(&json) {
+                Ok(record) => record,
+                Err(e) => {
+                    println!("Skipping an unreadable game record: {}", e);
+                    continue;
+                }
+            };
+            if record.result.is_none() {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn add_resume_token(&self, token: &str, game: GameId, id: ConnId) -> sled::Result<()> {
+        let mut seat = game.to_be_bytes().to_vec();
+        seat.extend_from_slice(&id.to_be_bytes());
+        self.resume_tokens.insert(token, seat)?;
+        Ok(())
+    }
+
+    /// Forgets the resume tokens of every seat in the game, once it has ended and there is nothing
+    /// left to resume
+    pub fn remove_resume_tokens(&self, game: GameId) -> sled::Result<()> {
+        for entry in self.resume_tokens.iter() {
+            let (token, seat) = entry?;
+            if seat[0..4] == game.to_be_bytes() {
+                self.resume_tokens.remove(token)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the game and connection id of the seat the resume token was given out for
+    pub fn resume_token(&self, token: &str) -> sled::Result<Option<(GameId, ConnId)>> {
+        Ok(self.resume_tokens.get(token)?.map(|seat| {
+            let game = GameId::from_be_bytes(seat[0..4].try_into().expect("Game ids are 4 bytes"));
+            let id = ConnId::from_be_bytes(seat[4..8].try_into().expect("Ids are 4 bytes"));
+            (game, id)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boombots_core::net::EndReason;
+    use boombots_core::Team;
+
+    fn record(id: GameId, result: Option<GameResult>) -> GameRecord {
+        GameRecord {
+            id,
+            white: User::new(1, "white".to_string()),
+            black: User::new(2, "black".to_string()),
+            start: GameState::new(),
+            actions: vec!["a2a3/1".to_string()],
+            result,
+            rated: false,
+            time_control: Some(TimeControl { initial: 300, increment: 5 }),
+        }
+    }
+
+    #[test]
+    fn only_unfinished_games_are_reloaded() {
+        let store = GameStore::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let finished = GameResult { winner: Some(Team::White), reason: EndReason::Boomed };
+        store.save(&record(1, None)).unwrap();
+        store.save(&record(2, Some(finished))).unwrap();
+        assert_eq!(store.unfinished().unwrap(), vec![record(1, None)]);
+
+        // Saving a game again replaces its record
+        store.save(&record(1, Some(finished))).unwrap();
+        assert_eq!(store.unfinished().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn unreadable_records_are_skipped() {
+        let store = GameStore::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        store.games.insert(1u32.to_be_bytes(), b"not a record".to_vec()).unwrap();
+        store.save(&record(2, None)).unwrap();
+        assert_eq!(store.unfinished().unwrap(), vec![record(2, None)]);
+    }
+
+    #[test]
+    fn records_without_a_time_control_are_read() {
+        let store = GameStore::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let mut json = serde_json::to_value(record(1, None)).unwrap();
+        json.as_object_mut().unwrap().remove("time_control");
+        store.games.insert(1u32.to_be_bytes(), serde_json::to_vec(&json).unwrap()).unwrap();
+        let expected = GameRecord { time_control: None, ..record(1, None) };
+        assert_eq!(store.unfinished().unwrap(), vec![expected]);
+    }
+
+    #[test]
+    fn resume_tokens_find_their_seat() {
+        let store = GameStore::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        store.add_resume_token("token", 3, 7).unwrap();
+        assert_eq!(store.resume_token("token").unwrap(), Some((3, 7)));
+        assert_eq!(store.resume_token("other").unwrap(), None);
+    }
+
+    #[test]
+    fn resume_tokens_are_removed_with_their_game() {
+        let store = GameStore::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        store.add_resume_token("white", 3, 7).unwrap();
+        store.add_resume_token("black", 3, 8).unwrap();
+        store.add_resume_token("other", 4, 7).unwrap();
+        store.remove_resume_tokens(3).unwrap();
+        assert_eq!(store.resume_token("white").unwrap(), None);
+        assert_eq!(store.resume_token("black").unwrap(), None);
+        assert_eq!(store.resume_token("other").unwrap(), Some((4, 7)));
+    }
+}
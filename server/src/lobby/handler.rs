@@ -1,15 +1,26 @@
+use super::queue::{Queue, Seeker};
 use super::{LobbyEvent, LobbyReceiver, LobbySender};
+use crate::chat::{self, Chat};
 use crate::conn::ConnId;
-use crate::conn::Connection;
+use crate::conn::{Connection, ConnectionSender, Rejection};
 use crate::game::GameId;
 use crate::game::GamePool;
-use crate::game::{GamePoolEvent, GamePoolSender};
+use crate::game::{GamePoolEvent, GamePoolSender, Ratings};
 use boombots_core::net::AcceptChallengeInfo;
-use boombots_core::net::{ChallengeInfo, Event, SendChallengeInfo, User};
-use futures_util::sink::SinkExt;
+use boombots_core::net::{
+    ChallengeInfo, ChallengeStatus, ChatScope, ColourChoice, ErrorCode, Event, QueueRequest,
+    RatedUser, Rating, RatingHistory, SendChallengeInfo, StartingPosition, User,
+};
+use boombots_core::{Preset, RuleSet};
 use std::collections::HashMap;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::time::{self, Instant};
+
+// How often the queue tries to pair players and tells them how long they have waited
+const QUEUE_INTERVAL: Duration = Duration::from_secs(5);
+// How long a challenge waits for an answer before it expires
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(120);
 
 #[derive(Debug)]
 pub struct Lobby {
@@ -17,6 +28,12 @@ pub struct Lobby {
     connections: HashMap<u32, Connection>,
     // Challenges
     challenges: HashMap<u32, ChallengeInfo>,
+    // Challenge ids are never reused, so answers to old challenges can not reach new ones
+    next_challenge: u32,
+    challenge_timeout: Duration,
+    queue: Queue,
+    chat: Chat,
+    ratings: Ratings,
     // Channels
     sender: LobbySender,
     receiver: LobbyReceiver,
@@ -24,17 +41,28 @@ pub struct Lobby {
 }
 
 impl Lobby {
-    pub fn new() -> Self {
+    pub fn new(db: &sled::Db) -> Self {
         let (sender, receiver) = mpsc::unbounded_channel::<LobbyEvent>();
         Self {
             connections: HashMap::new(),
             challenges: HashMap::new(),
+            next_challenge: 1,
+            challenge_timeout: CHALLENGE_TIMEOUT,
+            queue: Queue::default(),
+            chat: Chat::new(ChatScope::Lobby),
+            ratings: Ratings::open(db).expect("Failed to open the ratings database"),
             gamepool: None,
             sender,
             receiver,
         }
     }
 
+    // Changes how long challenges wait for an answer, which tests shorten
+    pub fn with_challenge_timeout(mut self, timeout: Duration) -> Self {
+        self.challenge_timeout = timeout;
+        self
+    }
+
     pub fn sender(&self) -> LobbySender {
         self.sender.clone()
     }
@@ -44,100 +72,704 @@ impl Lobby {
     }
 
     pub async fn listen(&mut self) {
-        while let Some(event) = self.receiver.recv().await {
-            println!("{:?}", event);
-            match event {
-                LobbyEvent::Join(conn) => self.add_conn(conn).await,
-                LobbyEvent::Disconnect(id) => self.disconnect(id).await,
-                LobbyEvent::SendChallenge(info) => self.send_challenge(info).await,
-                LobbyEvent::AcceptChallenge(info) => self.accept_challenge(info).await,
-                LobbyEvent::GameReady(game, challenge) => self.move_players(game, challenge).await,
+        let mut queue_interval = time::interval(QUEUE_INTERVAL);
+        loop {
+            tokio::select! {
+                event = self.receiver.recv() => match event {
+                    Some(event) => self.handle(event).await,
+                    None => break,
+                },
+                _ = queue_interval.tick() => {
+                    self.match_queue();
+                    self.send_queue_status();
+                }
+            }
+        }
+    }
+
+    async fn handle(&mut self, event: LobbyEvent) {
+        println!("{:?}", event);
+        match event {
+            LobbyEvent::Join(conn) => self.add_conn(conn).await,
+            LobbyEvent::Disconnect(id) => self.disconnect(id).await,
+            LobbyEvent::SendChallenge(info, sender) => self.send_challenge(info, &sender).await,
+            LobbyEvent::AcceptChallenge(info, sender) => self.accept_challenge(info, &sender).await,
+            LobbyEvent::DeclineChallenge(id, challenge, sender) => {
+                self.decline_challenge(id, challenge, &sender);
+            }
+            LobbyEvent::CancelChallenge(id, challenge, sender) => {
+                self.cancel_challenge(id, challenge, &sender);
+            }
+            LobbyEvent::ExpireChallenge(challenge) => {
+                self.close_challenge(challenge, ChallengeStatus::Expired);
+            }
+            LobbyEvent::GameReady(game, challenge) => self.move_players(game, challenge).await,
+            LobbyEvent::Chat(id, scope, text) => self.chat(id, scope, text),
+            LobbyEvent::Mute(id, target, muted) => match self.connections.get_mut(&id) {
+                Some(conn) => conn.chat.mute(target, muted),
+                None => {
+                    if let Some(gamepool) = &self.gamepool {
+                        gamepool.send(GamePoolEvent::Mute(id, target, muted)).unwrap();
+                    }
+                }
+            },
+            LobbyEvent::Spectate(id, game, sender) => self.spectate(id, game, &sender).await,
+            LobbyEvent::JoinQueue(id, request, sender) => self.join_queue(id, request, &sender),
+            LobbyEvent::LeaveQueue(id) => {
+                self.queue.leave(id);
+                self.send_to(id, &Event::QueueStatus(self.queue.status(id, Instant::now())));
             }
+            LobbyEvent::Leaderboard(sender) => self.send_leaderboard(&sender),
+            LobbyEvent::RatingHistory(id, sender) => self.send_rating_history(id, &sender),
+            LobbyEvent::Error(id, error) => self.send_to(id, &error),
         }
     }
 
     // Adds a new connection to the lobby and broadcasts the current connectiosn to all users
     async fn add_conn(&mut self, conn: Connection) {
+        self.chat.replay(&conn);
         self.connections.insert(conn.user.id, conn);
         self.broadcast_users().await;
     }
 
     // Disconnect a user from the lobby and broadcast the current connections to all users
     async fn disconnect(&mut self, id: ConnId) {
+        self.leave(id);
         if let Some(_conn) = self.connections.remove(&id) {
             self.broadcast_users().await;
         };
     }
 
+    // Takes a user out of the queue and cancels every challenge they sent or were sent, for when
+    // they leave the lobby
+    fn leave(&mut self, id: ConnId) {
+        self.queue.leave(id);
+        let challenges: Vec<u32> = self
+            .challenges
+            .values()
+            .filter(|challenge| {
+                challenge.source.id == id
+                    || challenge.target.as_ref().map(|user| user.id) == Some(id)
+            })
+            .map(|challenge| challenge.id)
+            .collect();
+        for challenge in challenges {
+            self.close_challenge(challenge, ChallengeStatus::Cancelled);
+        }
+    }
+
+    // Posts a message to the lobby chat, or passes it on to the game of a user outside the lobby
+    fn chat(&mut self, id: ConnId, scope: ChatScope, text: String) {
+        let conn = match self.connections.get_mut(&id) {
+            Some(conn) => conn,
+            None => {
+                if let Some(gamepool) = &self.gamepool {
+                    gamepool.send(GamePoolEvent::Chat(id, scope, text)).unwrap();
+                }
+                return;
+            }
+        };
+        if scope != ChatScope::Lobby {
+            let message = "Game chats are only for users in a game";
+            return conn.send(&Event::error(ErrorCode::NotInGame, message, None));
+        }
+        if let Err(rejection) = conn.chat.send(&text, Instant::now()) {
+            return conn.send(&rejection.event());
+        }
+        let message = self.chat.post(conn.user.clone(), text);
+        chat::deliver(&message, self.connections.values());
+    }
+
+    // Moves a user from the lobby into a game they only watch
+    async fn spectate(&mut self, id: ConnId, game: GameId, sender: &ConnectionSender) {
+        if let Err(rejection) = self.member(id) {
+            return crate::conn::send(sender, &rejection.event());
+        }
+        self.leave(id);
+        if let Some(conn) = self.connections.remove(&id) {
+            self.broadcast_users().await;
+            if let Some(gamepool) = &self.gamepool {
+                gamepool.send(GamePoolEvent::Spectate(game, conn)).unwrap();
+            }
+        }
+    }
+
     // Move players specified in a challenge into a new game lobby
     async fn move_players(&mut self, game: GameId, challenge: ChallengeInfo) {
-        let p1 = self.connections.remove(&challenge.source.id).unwrap();
-        let p2 = self.connections.remove(&challenge.target.id).unwrap();
+        let players = [Some(challenge.source.id), challenge.target.map(|user| user.id)];
+        for id in players.into_iter().flatten() {
+            self.leave(id);
+            // Players who left the lobby before the game was ready are not seated
+            if let (Some(player), Some(gamepool)) = (self.connections.remove(&id), &self.gamepool) {
+                gamepool.send(GamePoolEvent::Join(game, player)).unwrap();
+            }
+        }
+    }
 
+    async fn send_challenge(&mut self, info: SendChallengeInfo, sender: &ConnectionSender) {
+        let challenge = match self.create_challenge(info) {
+            Ok(challenge) => challenge,
+            Err(rejection) => return crate::conn::send(sender, &rejection.event()),
+        };
+        self.broadcast_challenge(&challenge);
+        self.challenges.insert(challenge.id, challenge.clone());
+
+        // Expire the challenge if it is still waiting for an answer once the timeout is over
+        let lobby = self.sender.clone();
+        let timeout = self.challenge_timeout;
+        tokio::spawn(async move {
+            time::sleep(timeout).await;
+            let _ = lobby.send(LobbyEvent::ExpireChallenge(challenge.id));
+        });
+    }
+
+    async fn accept_challenge(
+        &mut self,
+        challenge: AcceptChallengeInfo,
+        sender: &ConnectionSender,
+    ) {
+        let target = match self.can_accept(&challenge) {
+            Ok(target) => target,
+            Err(rejection) => return crate::conn::send(sender, &rejection.event()),
+        };
+        // Remove the challenge from the lobby and broadcast it to the relevent users, where whoever
+        // accepts an open challenge becomes its target
+        let mut challenge = self.challenges.remove(&challenge.id).unwrap();
+        let open = challenge.target.is_none();
+        challenge.target = Some(target);
+        challenge.status = ChallengeStatus::Accepted;
+        settle_colour(&mut challenge);
+        self.broadcast_challenge_to(&challenge, open);
+        // Create a new game
         if let Some(gamepool) = &self.gamepool {
-            gamepool.send(GamePoolEvent::Join(game, p1)).unwrap();
-            gamepool.send(GamePoolEvent::Join(game, p2)).unwrap();
+            gamepool.send(GamePoolEvent::CreateGame(challenge)).unwrap();
         }
     }
 
-    async fn send_challenge(&mut self, info: SendChallengeInfo) {
-        let challenge = self.create_challenge(info).unwrap();
-        self.broadcast_challenge(&challenge).await;
-        self.challenges.insert(challenge.id, challenge);
+    // This is to ensure that another user couldn't send an challenge accept for another player, and
+    // that whoever accepts is in the lobby to be moved into the game. Returns the accepting user.
+    fn can_accept(&self, accepted_challenge: &AcceptChallengeInfo) -> Result<User, Rejection> {
+        let target = self.member(accepted_challenge.target.unwrap())?.user.clone();
+        let context = Some(accepted_challenge.id.to_string());
+        let challenge = self.challenge(accepted_challenge.id)?;
+        let accepted = match &challenge.target {
+            Some(user) if user.id != target.id => Err(Rejection::new(
+                ErrorCode::NotChallenged,
+                "The challenge is for another user",
+                context,
+            )),
+            None if challenge.source.id == target.id => Err(Rejection::new(
+                ErrorCode::NotChallenged,
+                "Can not accept your own challenge",
+                context,
+            )),
+            None if challenge.rated => self.registered(target.id),
+            _ => Ok(()),
+        };
+        accepted.map(|_| target)
     }
 
-    async fn accept_challenge(&mut self, challenge: AcceptChallengeInfo) {
-        if self.can_accept(&challenge) {
-            // Remove the challenge from the lobby and broadcast it to the relevent users
-            let mut challenge = self.challenges.remove(&challenge.id).unwrap();
-            challenge.accepted = true;
-            self.broadcast_challenge(&challenge).await;
-            // Create a new game
+    fn decline_challenge(&mut self, id: ConnId, challenge: u32, sender: &ConnectionSender) {
+        let declined =
+            self.member(id).and_then(|_| self.challenge(challenge)).and_then(|challenge| {
+                match challenge.target.as_ref().map(|user| user.id) {
+                    Some(target) if target == id => Ok(()),
+                    _ => Err(Rejection::new(
+                        ErrorCode::NotChallenged,
+                        "Only the challenged user can decline a challenge",
+                        Some(challenge.id.to_string()),
+                    )),
+                }
+            });
+        match declined {
+            Ok(()) => self.close_challenge(challenge, ChallengeStatus::Declined),
+            Err(rejection) => crate::conn::send(sender, &rejection.event()),
+        }
+    }
+
+    fn cancel_challenge(&mut self, id: ConnId, challenge: u32, sender: &ConnectionSender) {
+        let cancelled =
+            self.member(id).and_then(|_| self.challenge(challenge)).and_then(|challenge| {
+                match challenge.source.id {
+                    source if source == id => Ok(()),
+                    _ => Err(Rejection::new(
+                        ErrorCode::NotChallenger,
+                        "Only the challenger can cancel a challenge",
+                        Some(challenge.id.to_string()),
+                    )),
+                }
+            });
+        match cancelled {
+            Ok(()) => self.close_challenge(challenge, ChallengeStatus::Cancelled),
+            Err(rejection) => crate::conn::send(sender, &rejection.event()),
+        }
+    }
+
+    // Finds a user in the lobby. Users playing or watching a game can still send lobby events, which
+    // are rejected as only users in the lobby can be challenged, queued or moved into games.
+    fn member(&self, id: ConnId) -> Result<&Connection, Rejection> {
+        self.connections.get(&id).ok_or_else(|| {
+            let message = "Only users in the lobby can do this";
+            Rejection::new(ErrorCode::NotInLobby, message, None)
+        })
+    }
+
+    fn challenge(&self, id: u32) -> Result<&ChallengeInfo, Rejection> {
+        self.challenges.get(&id).ok_or_else(|| {
+            Rejection::new(ErrorCode::UnknownChallenge, "No such challenge", Some(id.to_string()))
+        })
+    }
+
+    // Removes a challenge that will not be played and tells everyone who could see it why. Does
+    // nothing if the challenge was already answered.
+    fn close_challenge(&mut self, id: u32, status: ChallengeStatus) {
+        if let Some(mut challenge) = self.challenges.remove(&id) {
+            challenge.status = status;
+            self.broadcast_challenge(&challenge);
+        }
+    }
+
+    // Adds a player in the lobby to the matchmaking queue, where guests are paired as if they had the
+    // rating of a new player
+    fn join_queue(&mut self, id: ConnId, request: QueueRequest, sender: &ConnectionSender) {
+        if let Err(rejection) = self.member(id) {
+            return crate::conn::send(sender, &rejection.event());
+        }
+        let rating = match self.ratings.get(id) {
+            Ok(Some(rating)) => rating,
+            Ok(None) if request.rated => {
+                let message = "Rated games are only played between registered accounts";
+                let error = Event::error(ErrorCode::NotRegistered, message, None);
+                return crate::conn::send(sender, &error);
+            }
+            Ok(None) => Rating::default(),
+            Err(e) => {
+                println!("Failed to read a rating: {}", e);
+                let error = Event::error(ErrorCode::StorageError, "Failed to join the queue", None);
+                return crate::conn::send(sender, &error);
+            }
+        };
+        let now = Instant::now();
+        self.queue.join(Seeker { id, request, rating: rating.rating, joined: now });
+        self.send_to(id, &Event::QueueStatus(self.queue.status(id, now)));
+        self.match_queue();
+    }
+
+    // Starts a game of the standard rules for every pair of compatible players in the queue, where
+    // whoever waited longer plays white
+    fn match_queue(&mut self) {
+        for (white, black) in self.queue.pair(Instant::now()) {
+            let QueueRequest { time_control, rated, .. } = white.request;
+            let (white, black) =
+                match (self.connections.get(&white.id), self.connections.get(&black.id)) {
+                    (Some(white), Some(black)) => (white.user.clone(), black.user.clone()),
+                    _ => continue,
+                };
+            let id = self.next_challenge_id();
+            let mut challenge = ChallengeInfo::new(
+                id,
+                white,
+                Some(black),
+                RuleSet::standard(),
+                None,
+                rated,
+                ColourChoice::White,
+            );
+            challenge.status = ChallengeStatus::Accepted;
+            challenge.time_control = Some(time_control);
             if let Some(gamepool) = &self.gamepool {
                 gamepool.send(GamePoolEvent::CreateGame(challenge)).unwrap();
             }
         }
     }
 
-    // This is to ensure that another user couldn't send an challenge accept for another player
-    fn can_accept(&self, accepted_challenge: &AcceptChallengeInfo) -> bool {
-        let challenge = self.challenges.get(&accepted_challenge.id).unwrap();
-        challenge.target.id == accepted_challenge.target.unwrap()
+    fn send_queue_status(&self) {
+        let now = Instant::now();
+        for id in self.queue.ids() {
+            self.send_to(id, &Event::QueueStatus(self.queue.status(id, now)));
+        }
+    }
+
+    fn send_leaderboard(&self, sender: &ConnectionSender) {
+        let event = match self.ratings.leaderboard() {
+            Ok(standings) => Event::LeaderboardBroadcast(standings),
+            Err(e) => {
+                println!("Failed to read the leaderboard: {}", e);
+                Event::error(ErrorCode::StorageError, "Failed to read the leaderboard", None)
+            }
+        };
+        crate::conn::send(sender, &event);
+    }
+
+    fn send_rating_history(&self, id: ConnId, sender: &ConnectionSender) {
+        let event = match self.ratings.history(id) {
+            Ok(games) => Event::RatingHistoryBroadcast(RatingHistory { id, games }),
+            Err(e) => {
+                println!("Failed to read the rating history of {}: {}", id, e);
+                Event::error(ErrorCode::StorageError, "Failed to read the rating history", None)
+            }
+        };
+        crate::conn::send(sender, &event);
     }
 
-    async fn broadcast_challenge(&mut self, challenge: &ChallengeInfo) {
+    // Sends a challenge to both of its users, or to everyone in the lobby when it is open
+    fn broadcast_challenge(&self, challenge: &ChallengeInfo) {
+        self.broadcast_challenge_to(challenge, challenge.target.is_none());
+    }
+
+    fn broadcast_challenge_to(&self, challenge: &ChallengeInfo, everyone: bool) {
         let event = Event::ChallengeBroadcast(challenge.clone());
-        let json = serde_json::to_string(&event).unwrap();
+        if everyone {
+            self.connections.values().for_each(|connection| connection.send(&event));
+        } else {
+            self.send_to(challenge.source.id, &event);
+            if let Some(target) = &challenge.target {
+                self.send_to(target.id, &event);
+            }
+        }
+    }
 
-        if let Some(connection) = self.connections.get_mut(&challenge.clone().source.id) {
-            connection.sender.send(Message::Text(json.clone())).await.unwrap();
+    // Sends an event to a connection if it is in the lobby
+    fn send_to(&self, id: ConnId, event: &Event) {
+        if let Some(connection) = self.connections.get(&id) {
+            connection.send(event);
         }
+    }
 
-        if let Some(connection) = self.connections.get_mut(&challenge.clone().target.id) {
-            connection.sender.send(Message::Text(json.clone())).await.unwrap();
+    // Given a challenge source and challenge target, try create a new challenge under the rules and
+    // from the starting position the challenger chose, which must describe a playable game
+    pub fn create_challenge(
+        &mut self,
+        challenge: SendChallengeInfo,
+    ) -> Result<ChallengeInfo, Rejection> {
+        let source = self.member(challenge.source.unwrap())?.user.clone();
+        if !challenge.rules.valid() {
+            return Err(Rejection::new(
+                ErrorCode::InvalidChallenge,
+                "The rules are not playable",
+                None,
+            ));
+        }
+        if challenge.rules.setup(challenge.position.as_ref()).is_none() {
+            let context = challenge.position.map(|position| format!("{:?}", position));
+            let message = "The starting position is not playable under the rules";
+            return Err(Rejection::new(ErrorCode::InvalidChallenge, message, context));
         }
+        let target = match challenge.target.map(|target| self.connections.get(&target)) {
+            Some(Some(target)) => Some(target.user.clone()),
+            Some(None) => {
+                let context = challenge.target.map(|target| target.to_string());
+                return Err(Rejection::new(
+                    ErrorCode::UnknownUser,
+                    "No such user in the lobby",
+                    context,
+                ));
+            }
+            None => None,
+        };
+        if challenge.rated {
+            self.can_rate(&challenge)?;
+            self.registered(source.id)?;
+            if let Some(target) = &target {
+                self.registered(target.id)?;
+            }
+        }
+
+        Ok(ChallengeInfo::new(
+            self.next_challenge_id(),
+            source,
+            target,
+            challenge.rules,
+            challenge.position,
+            challenge.rated,
+            challenge.colour,
+        ))
     }
 
-    // Given a challenge source and challenge target, try create a new challenge
-    pub fn create_challenge(&mut self, challenge: SendChallengeInfo) -> Result<ChallengeInfo, ()> {
-        let id = (self.challenges.len() + 1) as u32;
-        let source = self.connections.get(&challenge.source.unwrap()).unwrap();
-        let target = self.connections.get(&challenge.target).unwrap();
+    fn next_challenge_id(&mut self) -> u32 {
+        let id = self.next_challenge;
+        self.next_challenge += 1;
+        id
+    }
 
-        Ok(ChallengeInfo::new(id, source.user.clone(), target.user.clone()))
+    // Ratings are only comparable between games of the standard rules from the standard start
+    fn can_rate(&self, challenge: &SendChallengeInfo) -> Result<(), Rejection> {
+        let standard = match &challenge.position {
+            None | Some(StartingPosition::Preset(Preset::Standard)) => true,
+            Some(_) => false,
+        };
+        if challenge.rules != RuleSet::standard() || !standard {
+            let message = "Rated games use the standard rules and starting position";
+            return Err(Rejection::new(ErrorCode::InvalidChallenge, message, None));
+        }
+        Ok(())
+    }
+
+    // Only accounts have ratings for rated games to update
+    fn registered(&self, id: ConnId) -> Result<(), Rejection> {
+        match self.ratings.get(id) {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => {
+                let message = "Rated games are only played between registered accounts";
+                Err(Rejection::new(ErrorCode::NotRegistered, message, Some(id.to_string())))
+            }
+            Err(e) => {
+                println!("Failed to read a rating: {}", e);
+                let message = "Failed to read the ratings";
+                Err(Rejection::new(ErrorCode::StorageError, message, None))
+            }
+        }
     }
 
     async fn broadcast_users(&mut self) {
-        let users: Vec<User> = self
+        let users: Vec<RatedUser> = self
             .connections
-            .iter()
-            .map(|(_, user)| User { id: user.user.id, name: user.user.name.clone() })
+            .values()
+            .map(|conn| RatedUser {
+                user: conn.user.clone(),
+                rating: self.ratings.get(conn.user.id).ok().flatten(),
+            })
             .collect();
 
-        for connection in self.connections.values_mut() {
-            let msg = Event::LobbyUserBroadcast(connection.user.id, users.clone());
-            let txt = serde_json::to_string(&msg).unwrap();
-            connection.sender.send(Message::Text(txt)).await.unwrap();
+        crate::conn::broadcast(&Event::LobbyUserBroadcast(users), self.connections.values());
+    }
+}
+
+// Picks the colour of the challenger by a coin toss when they left it to chance, so that the
+// game knows who plays white
+fn settle_colour(challenge: &mut ChallengeInfo) {
+    if challenge.colour == ColourChoice::Random {
+        challenge.colour = match rand::random() {
+            true => ColourChoice::White,
+            false => ColourChoice::Black,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boombots_core::net::TimeControl;
+    use tokio::sync::mpsc::UnboundedReceiver;
+    use tokio_tungstenite::tungstenite::Message;
+
+    type Events = UnboundedReceiver<Message>;
+
+    // Starts a lobby task with users 1 to 3 in it, returning the senders of their connections and
+    // the events they receive
+    fn start(timeout: Duration) -> (LobbySender, Vec<ConnectionSender>, Vec<Events>) {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let mut lobby = Lobby::new(&db).with_challenge_timeout(timeout);
+        let lobby_sender = lobby.sender();
+        tokio::spawn(async move { lobby.listen().await });
+
+        let (senders, events) = (1..=3)
+            .map(|id| {
+                let (sender, receiver) = mpsc::unbounded_channel::<Message>();
+                let conn = Connection::new(id, format!("player{}", id), sender.clone());
+                lobby_sender.send(LobbyEvent::Join(conn)).unwrap();
+                (sender, receiver)
+            })
+            .unzip();
+        (lobby_sender, senders, events)
+    }
+
+    // Waits for the next event that is not a broadcast of the users in the lobby
+    async fn next(events: &mut Events) -> Event {
+        loop {
+            let message = events.recv().await.expect("The lobby closed the connection");
+            match serde_json::from_str(message.to_text().unwrap()).unwrap() {
+                Event::LobbyUserBroadcast(..) => continue,
+                event => return event,
+            }
+        }
+    }
+
+    async fn next_challenge(events: &mut Events) -> ChallengeInfo {
+        match next(events).await {
+            Event::ChallengeBroadcast(challenge) => challenge,
+            event => panic!("Expected a challenge broadcast but got {:?}", event),
+        }
+    }
+
+    async fn next_error(events: &mut Events) -> ErrorCode {
+        match next(events).await {
+            Event::Error { code, .. } => code,
+            event => panic!("Expected an error but got {:?}", event),
+        }
+    }
+
+    fn challenge(
+        senders: &[ConnectionSender],
+        source: ConnId,
+        target: Option<ConnId>,
+    ) -> LobbyEvent {
+        let info = SendChallengeInfo {
+            source: Some(source),
+            target,
+            rules: RuleSet::standard(),
+            position: None,
+            rated: false,
+            colour: ColourChoice::Random,
+        };
+        LobbyEvent::SendChallenge(info, senders[source as usize - 1].clone())
+    }
+
+    fn accept(senders: &[ConnectionSender], id: u32, target: ConnId) -> LobbyEvent {
+        let info = AcceptChallengeInfo { id, target: Some(target) };
+        LobbyEvent::AcceptChallenge(info, senders[target as usize - 1].clone())
+    }
+
+    #[tokio::test]
+    async fn challenges_can_be_declined_or_cancelled() {
+        let (lobby, senders, mut users) = start(CHALLENGE_TIMEOUT);
+
+        lobby.send(challenge(&senders, 1, Some(2))).unwrap();
+        let sent = next_challenge(&mut users[1]).await;
+        assert_eq!(sent.status, ChallengeStatus::Pending);
+        lobby.send(LobbyEvent::DeclineChallenge(3, sent.id, senders[2].clone())).unwrap();
+        assert_eq!(next_error(&mut users[2]).await, ErrorCode::NotChallenged);
+        lobby.send(LobbyEvent::DeclineChallenge(2, sent.id, senders[1].clone())).unwrap();
+        assert_eq!(next_challenge(&mut users[0]).await.status, ChallengeStatus::Pending);
+        assert_eq!(next_challenge(&mut users[0]).await.status, ChallengeStatus::Declined);
+        assert_eq!(next_challenge(&mut users[1]).await.status, ChallengeStatus::Declined);
+
+        // Ids keep counting up after challenges are removed
+        lobby.send(challenge(&senders, 1, Some(2))).unwrap();
+        let resent = next_challenge(&mut users[1]).await;
+        assert!(resent.id > sent.id);
+        lobby.send(LobbyEvent::CancelChallenge(2, resent.id, senders[1].clone())).unwrap();
+        assert_eq!(next_error(&mut users[1]).await, ErrorCode::NotChallenger);
+        lobby.send(LobbyEvent::CancelChallenge(1, resent.id, senders[0].clone())).unwrap();
+        assert_eq!(next_challenge(&mut users[1]).await.status, ChallengeStatus::Cancelled);
+        lobby.send(accept(&senders, resent.id, 2)).unwrap();
+        assert_eq!(next_error(&mut users[1]).await, ErrorCode::UnknownChallenge);
+    }
+
+    #[tokio::test]
+    async fn challenges_expire() {
+        let (lobby, senders, mut users) = start(Duration::from_millis(10));
+
+        lobby.send(challenge(&senders, 1, Some(2))).unwrap();
+        assert_eq!(next_challenge(&mut users[1]).await.status, ChallengeStatus::Pending);
+        assert_eq!(next_challenge(&mut users[1]).await.status, ChallengeStatus::Expired);
+    }
+
+    #[tokio::test]
+    async fn challenges_are_cancelled_when_a_user_leaves() {
+        let (lobby, senders, mut users) = start(CHALLENGE_TIMEOUT);
+
+        lobby.send(challenge(&senders, 1, Some(2))).unwrap();
+        assert_eq!(next_challenge(&mut users[1]).await.status, ChallengeStatus::Pending);
+        lobby.send(LobbyEvent::Disconnect(1)).unwrap();
+        assert_eq!(next_challenge(&mut users[1]).await.status, ChallengeStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn open_challenges_can_be_accepted_by_anyone() {
+        let (lobby, senders, mut users) = start(CHALLENGE_TIMEOUT);
+
+        lobby.send(challenge(&senders, 1, None)).unwrap();
+        let open = next_challenge(&mut users[1]).await;
+        assert_eq!(open.target, None);
+        assert_eq!(next_challenge(&mut users[2]).await.id, open.id);
+
+        lobby.send(accept(&senders, open.id, 1)).unwrap();
+        assert_eq!(next_challenge(&mut users[0]).await.id, open.id);
+        assert_eq!(next_error(&mut users[0]).await, ErrorCode::NotChallenged);
+
+        // Everyone sees who accepted the challenge
+        lobby.send(accept(&senders, open.id, 3)).unwrap();
+        let accepted = next_challenge(&mut users[1]).await;
+        assert_eq!(accepted.status, ChallengeStatus::Accepted);
+        assert_eq!(accepted.target.map(|user| user.id), Some(3));
+    }
+
+    // Seats users 2 and 3 in a game, which moves them out of the lobby
+    async fn seat(lobby: &LobbySender, senders: &[ConnectionSender], users: &mut [Events]) {
+        lobby.send(challenge(senders, 2, Some(3))).unwrap();
+        let sent = next_challenge(&mut users[2]).await;
+        lobby.send(accept(senders, sent.id, 3)).unwrap();
+        let accepted = next_challenge(&mut users[2]).await;
+        lobby.send(LobbyEvent::GameReady(1, accepted)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn lobby_events_from_users_in_a_game_are_rejected() {
+        let (lobby, senders, mut users) = start(CHALLENGE_TIMEOUT);
+        seat(&lobby, &senders, &mut users).await;
+
+        lobby.send(challenge(&senders, 3, Some(1))).unwrap();
+        assert_eq!(next_error(&mut users[2]).await, ErrorCode::NotInLobby);
+        let time_control = TimeControl { initial: 300, increment: 0 };
+        let request = QueueRequest { time_control, rating_range: None, rated: false };
+        lobby.send(LobbyEvent::JoinQueue(3, request, senders[2].clone())).unwrap();
+        assert_eq!(next_error(&mut users[2]).await, ErrorCode::NotInLobby);
+        lobby.send(LobbyEvent::Spectate(3, 1, senders[2].clone())).unwrap();
+        assert_eq!(next_error(&mut users[2]).await, ErrorCode::NotInLobby);
+        lobby.send(LobbyEvent::DeclineChallenge(3, 1, senders[2].clone())).unwrap();
+        assert_eq!(next_error(&mut users[2]).await, ErrorCode::NotInLobby);
+    }
+
+    #[tokio::test]
+    async fn seated_players_can_not_accept_open_challenges() {
+        let (lobby, senders, mut users) = start(CHALLENGE_TIMEOUT);
+        seat(&lobby, &senders, &mut users).await;
+
+        lobby.send(challenge(&senders, 1, None)).unwrap();
+        let open = next_challenge(&mut users[0]).await;
+        lobby.send(accept(&senders, open.id, 3)).unwrap();
+        assert_eq!(next_error(&mut users[2]).await, ErrorCode::NotInLobby);
+
+        // The challenge is still open to users in the lobby
+        lobby.send(LobbyEvent::CancelChallenge(1, open.id, senders[0].clone())).unwrap();
+        assert_eq!(next_challenge(&mut users[0]).await.status, ChallengeStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn accepted_challenges_settle_the_colours() {
+        let (lobby, senders, mut users) = start(CHALLENGE_TIMEOUT);
+        for colour in [ColourChoice::Black, ColourChoice::Random] {
+            let mut event = challenge(&senders, 1, Some(2));
+            if let LobbyEvent::SendChallenge(info, _) = &mut event {
+                info.colour = colour;
+            }
+            lobby.send(event).unwrap();
+            let sent = next_challenge(&mut users[1]).await;
+            assert_eq!(sent.colour, colour);
+
+            lobby.send(accept(&senders, sent.id, 2)).unwrap();
+            let accepted = next_challenge(&mut users[1]).await;
+            let (white, black) = accepted.seats().expect("Accepted challenges have seats");
+            match colour {
+                ColourChoice::Black => assert_eq!((white.id, black.id), (2, 1)),
+                _ => assert_eq!(white.id + black.id, 3),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn lobby_chat_is_replayed_to_joiners_and_can_be_muted() {
+        let (lobby, _, mut users) = start(CHALLENGE_TIMEOUT);
+        let text = |event: Event| match event {
+            Event::Chat(message) => message.text,
+            event => panic!("Expected a chat message but got {:?}", event),
+        };
+
+        lobby.send(LobbyEvent::Mute(2, 1, true)).unwrap();
+        lobby.send(LobbyEvent::Chat(1, ChatScope::Lobby, "hello".to_string())).unwrap();
+        lobby.send(LobbyEvent::Chat(3, ChatScope::Lobby, "hi".to_string())).unwrap();
+        assert_eq!(text(next(&mut users[2]).await), "hello");
+        assert_eq!(text(next(&mut users[1]).await), "hi");
+
+        let (sender, mut joiner) = mpsc::unbounded_channel::<Message>();
+        lobby.send(LobbyEvent::Join(Connection::new(4, "player4".to_string(), sender))).unwrap();
+        match next(&mut joiner).await {
+            Event::ChatHistory(history) => {
+                let texts: Vec<String> = history.into_iter().map(|message| message.text).collect();
+                assert_eq!(texts, ["hello", "hi"]);
+            }
+            event => panic!("Expected the chat history but got {:?}", event),
         }
     }
 }
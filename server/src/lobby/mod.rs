@@ -1,9 +1,12 @@
-use crate::conn::{ConnId, Connection};
+use crate::conn::{ConnId, Connection, ConnectionSender};
 use crate::game::GameId;
-use boombots_core::net::{AcceptChallengeInfo, ChallengeInfo, SendChallengeInfo};
+use boombots_core::net::{
+    AcceptChallengeInfo, ChallengeInfo, ChatScope, Event, QueueRequest, SendChallengeInfo,
+};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 mod handler;
+mod queue;
 pub use handler::Lobby;
 
 pub type LobbySender = UnboundedSender<LobbyEvent>;
@@ -13,7 +16,30 @@ pub type LobbyReceiver = UnboundedReceiver<LobbyEvent>;
 pub enum LobbyEvent {
     Join(Connection),
     Disconnect(ConnId),
-    SendChallenge(SendChallengeInfo),
-    AcceptChallenge(AcceptChallengeInfo),
+    // Events that only users in the lobby can send carry the sender of the connection, so that users
+    // in a game can still be told why they were rejected
+    SendChallenge(SendChallengeInfo, ConnectionSender),
+    AcceptChallenge(AcceptChallengeInfo, ConnectionSender),
+    // The user with the id declines or cancels the challenge with the id
+    DeclineChallenge(ConnId, u32, ConnectionSender),
+    CancelChallenge(ConnId, u32, ConnectionSender),
+    // Sent by the lobby to itself once a challenge has waited for an answer for too long
+    ExpireChallenge(u32),
     GameReady(GameId, ChallengeInfo),
+    // Chat from the user with the id, which is passed on to their game when they are not in the lobby
+    Chat(ConnId, ChatScope, String),
+    // The user with the first id mutes or unmutes the user with the second id
+    Mute(ConnId, ConnId, bool),
+    // Moves the user out of the lobby to watch the game with the id
+    Spectate(ConnId, GameId, ConnectionSender),
+    JoinQueue(ConnId, QueueRequest, ConnectionSender),
+    LeaveQueue(ConnId),
+    // Asks for the leaderboard, which is answered on the sender so that players in games can ask
+    Leaderboard(ConnectionSender),
+    // Asks for the rating history of the account with the id, answered on the sender like the
+    // leaderboard
+    RatingHistory(ConnId, ConnectionSender),
+    // An error event for a connection in the lobby, which other tasks send when they reject events
+    // from connections they do not hold
+    Error(ConnId, Event),
 }
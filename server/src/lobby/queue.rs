@@ -0,0 +1,155 @@
+use crate::conn::ConnId;
+use boombots_core::net::{QueueRequest, QueueStatus};
+use tokio::time::Instant;
+
+// How far the rating range of a waiting player widens every second
+const WIDENING: f64 = 10.0;
+
+/// A player waiting in the queue along with the rating they are paired by
+#[derive(Debug, Copy, Clone)]
+pub struct Seeker {
+    pub id: ConnId,
+    pub request: QueueRequest,
+    pub rating: f64,
+    pub joined: Instant,
+}
+
+impl Seeker {
+    // The rating range after waiting until now, which is None when any opponent is accepted
+    fn range(&self, now: Instant) -> Option<f64> {
+        let waited = now.saturating_duration_since(self.joined).as_secs_f64();
+        self.request.rating_range.map(|range| range + WIDENING * waited)
+    }
+
+    fn accepts(&self, other: &Seeker, now: Instant) -> bool {
+        match self.range(now) {
+            Some(range) => (self.rating - other.rating).abs() <= range,
+            None => true,
+        }
+    }
+
+    // Two players can be paired when they want the same kind of game and both accept the rating of
+    // the other
+    fn compatible(&self, other: &Seeker, now: Instant) -> bool {
+        self.request.time_control == other.request.time_control
+            && self.request.rated == other.request.rated
+            && self.accepts(other, now)
+            && other.accepts(self, now)
+    }
+}
+
+/// The matchmaking queue, which holds players in the order they joined so that whoever has waited
+/// longest is paired first
+#[derive(Debug, Default)]
+pub struct Queue {
+    seekers: Vec<Seeker>,
+}
+
+impl Queue {
+    pub fn join(&mut self, seeker: Seeker) {
+        self.leave(seeker.id);
+        self.seekers.push(seeker);
+    }
+
+    // Removes the player from the queue, returning whether they were waiting in it
+    pub fn leave(&mut self, id: ConnId) -> bool {
+        let len = self.seekers.len();
+        self.seekers.retain(|seeker| seeker.id != id);
+        self.seekers.len() != len
+    }
+
+    pub fn ids(&self) -> Vec<ConnId> {
+        self.seekers.iter().map(|seeker| seeker.id).collect()
+    }
+
+    /// Takes every pair of compatible players out of the queue, where the first of each pair has
+    /// waited longer
+    pub fn pair(&mut self, now: Instant) -> Vec<(Seeker, Seeker)> {
+        let mut pairs = Vec::new();
+        let mut i = 0;
+        while i < self.seekers.len() {
+            let seeker = self.seekers[i];
+            match self.seekers[i + 1..].iter().position(|other| seeker.compatible(other, now)) {
+                Some(j) => {
+                    let other = self.seekers.remove(i + 1 + j);
+                    self.seekers.remove(i);
+                    pairs.push((seeker, other));
+                }
+                None => i += 1,
+            }
+        }
+        pairs
+    }
+
+    pub fn status(&self, id: ConnId, now: Instant) -> QueueStatus {
+        let seeker = self.seekers.iter().find(|seeker| seeker.id == id);
+        QueueStatus {
+            request: seeker.map(|seeker| seeker.request),
+            rating_range: seeker.and_then(|seeker| seeker.range(now)),
+            waiting: seeker
+                .map_or(0, |seeker| now.saturating_duration_since(seeker.joined).as_secs()),
+            players: self.seekers.len() as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boombots_core::net::TimeControl;
+    use std::time::Duration;
+
+    fn seeker(id: ConnId, rating: f64, range: Option<f64>, joined: Instant) -> Seeker {
+        let time_control = TimeControl { initial: 300, increment: 5 };
+        let request = QueueRequest { time_control, rating_range: range, rated: false };
+        Seeker { id, request, rating, joined }
+    }
+
+    fn ids(pairs: Vec<(Seeker, Seeker)>) -> Vec<(ConnId, ConnId)> {
+        pairs.into_iter().map(|(a, b)| (a.id, b.id)).collect()
+    }
+
+    #[test]
+    fn players_are_paired_in_the_order_they_joined() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        for id in 1..=5 {
+            queue.join(seeker(id, 1500.0, None, now));
+        }
+        assert_eq!(ids(queue.pair(now)), [(1, 2), (3, 4)]);
+        assert_eq!(queue.ids(), [5]);
+    }
+
+    #[test]
+    fn only_compatible_players_are_paired() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        let mut blitz = seeker(1, 1500.0, None, now);
+        blitz.request.time_control = TimeControl { initial: 60, increment: 0 };
+        queue.join(blitz);
+        let mut rated = seeker(2, 1500.0, None, now);
+        rated.request.rated = true;
+        queue.join(rated);
+        queue.join(seeker(3, 1500.0, Some(100.0), now));
+        queue.join(seeker(4, 1800.0, None, now));
+        assert_eq!(ids(queue.pair(now)), []);
+
+        // Joining again replaces the earlier request
+        queue.join(seeker(1, 1500.0, None, now));
+        assert_eq!(ids(queue.pair(now)), [(3, 1)]);
+    }
+
+    #[test]
+    fn rating_ranges_widen_while_waiting() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        queue.join(seeker(1, 1500.0, Some(100.0), now));
+        queue.join(seeker(2, 1700.0, Some(100.0), now));
+        assert_eq!(ids(queue.pair(now)), []);
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(queue.status(1, later).rating_range, Some(200.0));
+        assert_eq!(ids(queue.pair(later)), [(1, 2)]);
+        assert_eq!(queue.status(1, later).request, None);
+    }
+}
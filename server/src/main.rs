@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 mod auth;
+mod chat;
 mod conn;
 mod game;
 mod lobby;
@@ -8,5 +9,6 @@ use server::*;
 
 #[tokio::main]
 async fn main() {
-    Server::start("0.0.0.0:8008").await;
+    let database = std::env::var("BOOMBOTS_DB").unwrap_or_else(|_| "boombots.db".to_string());
+    Server::start("0.0.0.0:8008", &database).await;
 }
@@ -1,5 +1,5 @@
 use crate::auth::Auth;
-use crate::conn::Connection;
+use crate::conn::{Connection, Sessions};
 use crate::game::GamePool;
 use crate::lobby::Lobby;
 use tokio::net::TcpListener;
@@ -8,7 +8,9 @@ use tokio_tungstenite::accept_async;
 pub struct Server {}
 
 impl Server {
-    pub async fn start(address: &str) {
+    pub async fn start(address: &str, database: &str) {
+        let db = sled::open(database).expect("Failed to open the database");
+
         let listener = TcpListener::bind(&address)
             .await
             .expect("The TCP connection failed to bind to the address");
@@ -16,9 +18,10 @@ impl Server {
         println!("Listening on: {}", address);
 
         // Create a game pool
-        let mut auth = Auth::new();
-        let mut lobby = Lobby::new();
-        let mut gamepool = GamePool::new();
+        let sessions = Sessions::default();
+        let mut auth = Auth::new(&db, sessions.clone());
+        let mut lobby = Lobby::new(&db);
+        let mut gamepool = GamePool::new(&db, sessions.clone());
 
         let auth_sender = auth.sender();
         let lobby_sender = lobby.sender();
@@ -27,6 +30,7 @@ impl Server {
         // Share senders so that tasks can communicate using events
         lobby.add_gamepool(&gamepool);
         gamepool.add_lobby(&lobby);
+        gamepool.restore();
 
         // Starts the tasks
         tokio::spawn(async move {
@@ -49,6 +53,7 @@ impl Server {
                         lobby_sender.clone(),
                         gamepool_sender.clone(),
                         auth_sender.clone(),
+                        sessions.clone(),
                     ));
                 }
                 Err(e) => println!("Websocket connection error: {}", e),